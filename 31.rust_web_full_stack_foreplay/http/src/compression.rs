@@ -0,0 +1,299 @@
+use super::http_response::Response;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{self, Read, Write};
+
+/// Bodies smaller than this are sent as-is; compressing them costs more than
+/// it saves.
+pub const MIN_COMPRESS_SIZE: usize = 1024;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+  Gzip,
+  Deflate,
+  Identity,
+}
+
+impl Encoding {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Encoding::Gzip => "gzip",
+      Encoding::Deflate => "deflate",
+      Encoding::Identity => "identity",
+    }
+  }
+
+  fn from_token(token: &str) -> Option<Encoding> {
+    match token.trim().to_ascii_lowercase().as_str() {
+      "gzip" | "x-gzip" => Some(Encoding::Gzip),
+      "deflate" => Some(Encoding::Deflate),
+      "identity" => Some(Encoding::Identity),
+      _ => None,
+    }
+  }
+}
+
+/// Picks the best encoding we support from an `Accept-Encoding` value.
+///
+/// The highest q-value wins and gzip is preferred over deflate on a tie.
+/// Encodings we don't implement, such as `br`, are ignored.
+pub fn negotiate(accept_encoding: &str) -> Encoding {
+  let mut wildcard = None;
+  let mut accepted: Vec<(Encoding, f32)> = Vec::new();
+  for item in accept_encoding.split(',') {
+    let mut params = item.split(';');
+    let token = params.next().unwrap_or_default().trim();
+    let q = params
+      .find_map(|p| {
+        let (k, v) = p.split_once('=')?;
+        (k.trim() == "q").then(|| v.trim().parse::<f32>().ok())?
+      })
+      .unwrap_or(1.0);
+    if token == "*" {
+      wildcard = Some(q);
+    } else if let Some(encoding) = Encoding::from_token(token) {
+      accepted.push((encoding, q));
+    }
+  }
+
+  let q_of = |encoding: Encoding| {
+    accepted
+      .iter()
+      .find(|(e, _)| *e == encoding)
+      .map(|(_, q)| *q)
+      .or(wildcard)
+      .unwrap_or(0.0)
+  };
+  [Encoding::Gzip, Encoding::Deflate]
+    .into_iter()
+    .map(|encoding| (encoding, q_of(encoding)))
+    .filter(|(_, q)| *q > 0.0)
+    .fold(
+      None,
+      |best: Option<(Encoding, f32)>, (encoding, q)| match best {
+        Some((_, best_q)) if best_q >= q => best,
+        _ => Some((encoding, q)),
+      },
+    )
+    .map(|(encoding, _)| encoding)
+    .unwrap_or(Encoding::Identity)
+}
+
+/// Whether a body of this content type benefits from compression. Images,
+/// video, archives and fonts are already compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+  let mime = content_type
+    .split(';')
+    .next()
+    .unwrap_or_default()
+    .trim()
+    .to_ascii_lowercase();
+
+  mime.starts_with("text/")
+    || mime.ends_with("+json")
+    || mime.ends_with("+xml")
+    || matches!(
+      mime.as_str(),
+      "application/json"
+        | "application/javascript"
+        | "application/xml"
+        | "application/wasm"
+        | "application/x-www-form-urlencoded"
+        | "image/svg+xml"
+        | "image/x-icon"
+    )
+}
+
+pub fn encode(encoding: Encoding, body: &[u8]) -> io::Result<Vec<u8>> {
+  match encoding {
+    Encoding::Gzip => {
+      let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+      encoder.write_all(body)?;
+      encoder.finish()
+    }
+    Encoding::Deflate => {
+      let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+      encoder.write_all(body)?;
+      encoder.finish()
+    }
+    Encoding::Identity => Ok(body.to_vec()),
+  }
+}
+
+/// Decodes a body sent with the given `Content-Encoding`.
+///
+/// Stacked encodings such as `deflate, gzip` are undone in reverse order.
+/// Decoding stops with an error once the output would exceed `max_size`, so
+/// a small compressed body can't expand into an unbounded one. Encodings we
+/// don't implement are an `Unsupported` error.
+pub fn decode(content_encoding: &str, body: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+  let mut body = body.to_vec();
+  for token in content_encoding
+    .rsplit(',')
+    .filter(|t| !t.trim().is_empty())
+  {
+    body = match Encoding::from_token(token) {
+      Some(Encoding::Gzip) => read_limited(GzDecoder::new(&body[..]), max_size)?,
+      // `deflate` is meant to be zlib-wrapped, but some clients send raw
+      // deflate data, so fall back to that.
+      Some(Encoding::Deflate) => read_limited(ZlibDecoder::new(&body[..]), max_size)
+        .or_else(|_| read_limited(DeflateDecoder::new(&body[..]), max_size))?,
+      Some(Encoding::Identity) => body,
+      None => {
+        return Err(io::Error::new(
+          io::ErrorKind::Unsupported,
+          format!("unsupported content encoding: {}", token.trim()),
+        ))
+      }
+    };
+  }
+
+  Ok(body)
+}
+
+fn read_limited(reader: impl Read, max_size: usize) -> io::Result<Vec<u8>> {
+  let mut out = Vec::new();
+  reader.take(max_size as u64 + 1).read_to_end(&mut out)?;
+  if out.len() > max_size {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!("decoded body exceeds {} bytes", max_size),
+    ));
+  }
+
+  Ok(out)
+}
+
+impl Response {
+  /// Compresses the body according to the request's `Accept-Encoding`.
+  ///
+  /// Responses that are small, already encoded or of a content type that
+  /// doesn't compress well are returned unchanged. Every compressible
+  /// response gets `Vary: Accept-Encoding`, whether or not it was compressed,
+  /// so caches keep the variants apart, and a compressed body's `ETag` is
+  /// tagged with its encoding so it never passes for the identity one.
+  pub fn compress(self, accept_encoding: Option<&str>) -> Self {
+    let compressible = self
+      .header_value("Content-Type")
+      .map(is_compressible)
+      .unwrap_or(false);
+    if !compressible || self.header_value("Content-Encoding").is_some() {
+      return self;
+    }
+
+    let res = self.header("Vary", "Accept-Encoding");
+    let encoding = accept_encoding.map(negotiate).unwrap_or(Encoding::Identity);
+    if encoding == Encoding::Identity || res.body_bytes().len() < MIN_COMPRESS_SIZE {
+      return res;
+    }
+    match encode(encoding, res.body_bytes()) {
+      Ok(body) if body.len() < res.body_bytes().len() => {
+        let res = match res.header_value("ETag") {
+          Some(etag) => {
+            let etag = encoded_etag(etag, encoding);
+            res.set_header("ETag", etag)
+          }
+          None => res,
+        };
+        res.header("Content-Encoding", encoding.as_str()).body(body)
+      }
+      _ => res,
+    }
+  }
+}
+
+/// Appends the encoding inside the quotes, turning `"abc"` into `"abc-gzip"`.
+fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+  match etag.strip_suffix('"') {
+    Some(tag) => format!("{}-{}\"", tag, encoding.as_str()),
+    None => etag.to_string(),
+  }
+}
+
+/// Strips the suffix [`Response::compress`] adds to an `ETag`, so a tag the
+/// client got with a compressed body can be compared with the identity one.
+pub fn identity_etag(etag: &str) -> String {
+  [Encoding::Gzip, Encoding::Deflate]
+    .iter()
+    .find_map(|encoding| etag.strip_suffix(&format!("-{}\"", encoding.as_str())))
+    .map(|tag| format!("{}\"", tag))
+    .unwrap_or_else(|| etag.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_negotiate() {
+    assert_eq!(negotiate("gzip, deflate, br"), Encoding::Gzip);
+    assert_eq!(negotiate("deflate, gzip;q=0.5"), Encoding::Deflate);
+    assert_eq!(negotiate("br"), Encoding::Identity);
+    assert_eq!(negotiate("*;q=0.1, gzip;q=0"), Encoding::Deflate);
+    assert_eq!(negotiate(""), Encoding::Identity);
+  }
+
+  #[test]
+  fn test_compress_response() {
+    let html = "<p>Hello Rust</p>".repeat(100);
+    let res = Response::new()
+      .header("Content-Type", "text/html")
+      .body(html.clone())
+      .compress(Some("gzip, deflate, br"));
+
+    assert_eq!(res.header_value("Content-Encoding"), Some("gzip"));
+    assert_eq!(res.header_value("Vary"), Some("Accept-Encoding"));
+    assert_eq!(
+      decode("gzip", res.body_bytes(), 1 << 20).unwrap(),
+      html.as_bytes()
+    );
+  }
+
+  #[test]
+  fn test_compress_etag() {
+    let page = || {
+      Response::new()
+        .header("Content-Type", "text/html")
+        .header("ETag", "\"abc\"")
+        .body("<p>Hello Rust</p>".repeat(100))
+    };
+
+    let gzip = page().compress(Some("gzip"));
+    assert_eq!(gzip.header_value("ETag"), Some("\"abc-gzip\""));
+    assert_eq!(identity_etag("\"abc-gzip\""), "\"abc\"");
+    let deflate = page().compress(Some("deflate"));
+    assert_eq!(deflate.header_value("ETag"), Some("\"abc-deflate\""));
+    let identity = page().compress(None);
+    assert_eq!(identity.header_value("ETag"), Some("\"abc\""));
+    assert_eq!(identity_etag("\"abc\""), "\"abc\"");
+  }
+
+  #[test]
+  fn test_skip_compression() {
+    let small = Response::new()
+      .header("Content-Type", "text/html")
+      .body("tiny")
+      .compress(Some("gzip"));
+    assert_eq!(small.header_value("Content-Encoding"), None);
+    assert_eq!(small.header_value("Vary"), Some("Accept-Encoding"));
+
+    let png = Response::new()
+      .header("Content-Type", "image/png")
+      .body(vec![0; 4096])
+      .compress(Some("gzip"));
+    assert_eq!(png.header_value("Content-Encoding"), None);
+    assert_eq!(png.header_value("Vary"), None);
+  }
+
+  #[test]
+  fn test_decode() {
+    let body = b"order_id=1&order_status=Done".repeat(10);
+    let stacked = encode(Encoding::Gzip, &encode(Encoding::Deflate, &body).unwrap()).unwrap();
+
+    assert_eq!(decode("deflate, gzip", &stacked, 1024).unwrap(), body);
+    assert!(decode("gzip", &encode(Encoding::Gzip, &body).unwrap(), 16).is_err());
+    let err = decode("br", &body, 1024).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+  }
+}
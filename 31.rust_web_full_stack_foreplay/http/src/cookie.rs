@@ -0,0 +1,405 @@
+use super::http_response::http_date;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SameSite {
+  Strict,
+  Lax,
+  None,
+}
+
+impl SameSite {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SameSite::Strict => "Strict",
+      SameSite::Lax => "Lax",
+      SameSite::None => "None",
+    }
+  }
+}
+
+/// A cookie to send to the client through `Set-Cookie`.
+///
+/// ```
+/// use http::cookie::{Cookie, SameSite};
+///
+/// let cookie = Cookie::new("theme", "dark")
+///   .path("/")
+///   .http_only(true)
+///   .same_site(SameSite::Lax);
+/// assert_eq!(cookie.to_string(), "theme=dark; Path=/; HttpOnly; SameSite=Lax");
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cookie {
+  name: String,
+  value: String,
+  expires: Option<SystemTime>,
+  max_age: Option<Duration>,
+  path: Option<String>,
+  domain: Option<String>,
+  secure: bool,
+  http_only: bool,
+  same_site: Option<SameSite>,
+}
+
+impl Cookie {
+  pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+    Cookie {
+      name: name.into(),
+      value: value.into(),
+      expires: None,
+      max_age: None,
+      path: None,
+      domain: None,
+      secure: false,
+      http_only: false,
+      same_site: None,
+    }
+  }
+
+  /// A cookie that makes the client delete `name` right away.
+  pub fn removal(name: impl Into<String>) -> Self {
+    Cookie::new(name, "")
+      .max_age(Duration::ZERO)
+      .expires(SystemTime::UNIX_EPOCH)
+  }
+
+  /// Creates a cookie whose value is signed with `key`, so that
+  /// [`CookieJar::get_signed`] can later tell whether the client tampered
+  /// with it. The value itself is not encrypted.
+  pub fn signed(name: impl Into<String>, value: impl Into<String>, key: &Key) -> Self {
+    let name = name.into();
+    let value = value.into();
+    let signature = key.sign(&name, &value);
+    Cookie::new(name, format!("{}.{}", value, signature))
+  }
+
+  pub fn expires(mut self, expires: SystemTime) -> Self {
+    self.expires = Some(expires);
+    self
+  }
+
+  pub fn max_age(mut self, max_age: Duration) -> Self {
+    self.max_age = Some(max_age);
+    self
+  }
+
+  pub fn path(mut self, path: impl Into<String>) -> Self {
+    self.path = Some(path.into());
+    self
+  }
+
+  pub fn domain(mut self, domain: impl Into<String>) -> Self {
+    self.domain = Some(domain.into());
+    self
+  }
+
+  pub fn secure(mut self, secure: bool) -> Self {
+    self.secure = secure;
+    self
+  }
+
+  pub fn http_only(mut self, http_only: bool) -> Self {
+    self.http_only = http_only;
+    self
+  }
+
+  pub fn same_site(mut self, same_site: SameSite) -> Self {
+    self.same_site = Some(same_site);
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn value(&self) -> &str {
+    &self.value
+  }
+}
+
+/// Formats the cookie as a `Set-Cookie` header value.
+///
+/// Bytes that may not appear in a name (an RFC 6265 token), a value (a
+/// cookie-octet) or an attribute are percent-encoded, so no value can add
+/// attributes with `;` or split the header with CR/LF. [`CookieJar::parse`]
+/// decodes them again.
+///
+/// Browsers drop `SameSite=None` cookies that are not `Secure`, so that
+/// combination always gets the `Secure` attribute.
+impl fmt::Display for Cookie {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{}={}",
+      percent_encode(&self.name, is_token),
+      percent_encode(&self.value, is_cookie_octet)
+    )?;
+    if let Some(expires) = self.expires {
+      write!(f, "; Expires={}", http_date(expires))?;
+    }
+    if let Some(max_age) = self.max_age {
+      write!(f, "; Max-Age={}", max_age.as_secs())?;
+    }
+    if let Some(domain) = &self.domain {
+      write!(f, "; Domain={}", percent_encode(domain, is_attribute_octet))?;
+    }
+    if let Some(path) = &self.path {
+      write!(f, "; Path={}", percent_encode(path, is_attribute_octet))?;
+    }
+    if self.secure || self.same_site == Some(SameSite::None) {
+      write!(f, "; Secure")?;
+    }
+    if self.http_only {
+      write!(f, "; HttpOnly")?;
+    }
+    if let Some(same_site) = self.same_site {
+      write!(f, "; SameSite={}", same_site.as_str())?;
+    }
+
+    Ok(())
+  }
+}
+
+fn is_token(b: u8) -> bool {
+  b.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&b)
+}
+
+fn is_cookie_octet(b: u8) -> bool {
+  b.is_ascii_graphic() && !b"\",;\\".contains(&b)
+}
+
+fn is_attribute_octet(b: u8) -> bool {
+  b.is_ascii_graphic() && b != b';'
+}
+
+/// Percent-encodes every byte that isn't `allowed`, and `%` itself so the
+/// encoding can be undone.
+fn percent_encode(text: &str, allowed: fn(u8) -> bool) -> String {
+  let mut out = String::with_capacity(text.len());
+  for &b in text.as_bytes() {
+    if allowed(b) && b != b'%' {
+      out.push(b as char);
+    } else {
+      out.push_str(&format!("%{:02X}", b));
+    }
+  }
+  out
+}
+
+fn percent_decode(text: &str) -> String {
+  let input = text.as_bytes();
+  let mut out = Vec::with_capacity(input.len());
+  let mut i = 0;
+  while i < input.len() {
+    let hex = input
+      .get(i + 1..i + 3)
+      .and_then(|hex| std::str::from_utf8(hex).ok())
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+    match (input[i], hex) {
+      (b'%', Some(b)) => {
+        out.push(b);
+        i += 3;
+      }
+      (b, _) => {
+        out.push(b);
+        i += 1;
+      }
+    }
+  }
+
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The cookies a client sent in its `Cookie` header.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct CookieJar {
+  cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+  /// Parses a `Cookie` header value such as `a=1; b="2"`.
+  ///
+  /// Pairs without `=` are skipped, surrounding double quotes are stripped
+  /// from values, and percent-encoded bytes are decoded.
+  pub fn parse(header: &str) -> Self {
+    let cookies = header
+      .split(';')
+      .filter_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        let name = name.trim();
+        let value = value.trim();
+        let value = value
+          .strip_prefix('"')
+          .and_then(|v| v.strip_suffix('"'))
+          .unwrap_or(value);
+        (!name.is_empty()).then(|| (percent_decode(name), percent_decode(value)))
+      })
+      .collect();
+
+    CookieJar { cookies }
+  }
+
+  /// Returns the value of the first cookie called `name`.
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .cookies
+      .iter()
+      .find(|(k, _)| k == name)
+      .map(|(_, v)| v.as_str())
+  }
+
+  /// Returns the value of a cookie created by [`Cookie::signed`], or `None`
+  /// if it is missing or its signature does not match `key`.
+  pub fn get_signed(&self, name: &str, key: &Key) -> Option<&str> {
+    let (value, signature) = self.get(name)?.rsplit_once('.')?;
+    key.verify(name, value, signature).then_some(value)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self.cookies.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+  }
+
+  pub fn len(&self) -> usize {
+    self.cookies.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.cookies.is_empty()
+  }
+}
+
+/// A secret used to sign cookies with HMAC-SHA256.
+///
+/// The key never leaves the server; anyone who knows it can forge cookies,
+/// so load it from configuration rather than hardcoding it.
+#[derive(Clone)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+  /// Smallest accepted key, in bytes.
+  pub const MIN_LEN: usize = 32;
+
+  /// Creates a key, returning `None` if it is shorter than [`Key::MIN_LEN`].
+  pub fn new(secret: &[u8]) -> Option<Self> {
+    (secret.len() >= Self::MIN_LEN).then(|| Key(secret.to_vec()))
+  }
+
+  fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+    // The name is part of the signed message so that a signed value can't be
+    // replayed under another cookie name.
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(value.as_bytes());
+    mac
+  }
+
+  fn sign(&self, name: &str, value: &str) -> String {
+    let tag = self.mac(name, value).finalize().into_bytes();
+    tag.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  fn verify(&self, name: &str, value: &str, signature: &str) -> bool {
+    if signature.len() != 64 || !signature.is_ascii() {
+      return false;
+    }
+    let tag: Option<Vec<u8>> = (0..signature.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).ok())
+      .collect();
+
+    match tag {
+      Some(tag) => self.mac(name, value).verify_slice(&tag).is_ok(),
+      None => false,
+    }
+  }
+}
+
+impl fmt::Debug for Key {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("Key(..)")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key() -> Key {
+    Key::new(b"0123456789abcdef0123456789abcdef").unwrap()
+  }
+
+  #[test]
+  fn test_set_cookie() {
+    let cookie = Cookie::new("session", "abc")
+      .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(784111777))
+      .max_age(Duration::from_secs(3600))
+      .domain("example.com")
+      .path("/api")
+      .same_site(SameSite::None);
+
+    assert_eq!(
+      cookie.to_string(),
+      "session=abc; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=3600; Domain=example.com; Path=/api; Secure; SameSite=None"
+    );
+    assert_eq!(
+      Cookie::removal("session").to_string(),
+      "session=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"
+    );
+  }
+
+  #[test]
+  fn test_set_cookie_injection() {
+    let cookie = Cookie::new("id; Secure", "1; Domain=evil.com\r\nSet-Cookie: admin=1")
+      .path("/;\r\nX-Injected: 1");
+    let header = cookie.to_string();
+
+    assert_eq!(
+      header,
+      "id%3B%20Secure=1%3B%20Domain=evil.com%0D%0ASet-Cookie:%20admin=1; Path=/%3B%0D%0AX-Injected:%201"
+    );
+    assert_eq!(header.matches(';').count(), 1);
+    let (pair, _) = header.split_once(';').unwrap();
+    let jar = CookieJar::parse(pair);
+    assert_eq!(jar.get("id; Secure"), Some(cookie.value()));
+    assert_eq!(
+      CookieJar::parse("v=50%25%20off; w=%zz").get("v"),
+      Some("50% off")
+    );
+    assert_eq!(CookieJar::parse("w=%zz").get("w"), Some("%zz"));
+  }
+
+  #[test]
+  fn test_parse_jar() {
+    let jar = CookieJar::parse(" a=1; b=\"two\";broken; c=x=y ");
+
+    assert_eq!(jar.len(), 3);
+    assert_eq!(jar.get("a"), Some("1"));
+    assert_eq!(jar.get("b"), Some("two"));
+    assert_eq!(jar.get("c"), Some("x=y"));
+    assert_eq!(jar.get("broken"), None);
+  }
+
+  #[test]
+  fn test_signed_cookie() {
+    let cookie = Cookie::signed("user", "42", &key());
+    let jar = CookieJar::parse(&format!("user={}", cookie.value()));
+    assert_eq!(jar.get_signed("user", &key()), Some("42"));
+
+    let tampered = cookie.value().replacen("42", "43", 1);
+    let jar = CookieJar::parse(&format!("user={}; other={}", tampered, cookie.value()));
+    assert_eq!(jar.get_signed("user", &key()), None);
+    assert_eq!(jar.get_signed("other", &key()), None);
+
+    let other_key = Key::new(&[7; 32]).unwrap();
+    let jar = CookieJar::parse(&format!("user={}", cookie.value()));
+    assert_eq!(jar.get_signed("user", &other_key), None);
+    assert!(Key::new(b"short").is_none());
+  }
+}
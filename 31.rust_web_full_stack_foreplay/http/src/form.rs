@@ -0,0 +1,564 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Fields decoded from an `application/x-www-form-urlencoded` body.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Form {
+  fields: Vec<(String, String)>,
+}
+
+impl Form {
+  /// Decodes `a=1&b=two+words`. `+` becomes a space and `%XX` escapes are
+  /// decoded; invalid UTF-8 is replaced rather than rejected.
+  pub fn parse(body: &[u8]) -> Self {
+    let fields = body
+      .split(|b| *b == b'&')
+      .filter(|pair| !pair.is_empty())
+      .map(|pair| {
+        let mut kv = pair.splitn(2, |b| *b == b'=');
+        let key = kv.next().unwrap_or_default();
+        let value = kv.next().unwrap_or_default();
+        (percent_decode(key), percent_decode(value))
+      })
+      .collect();
+
+    Form { fields }
+  }
+
+  /// Returns the first value of the field called `name`.
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .fields
+      .iter()
+      .find(|(k, _)| k == name)
+      .map(|(_, v)| v.as_str())
+  }
+
+  /// Returns every value of the field called `name`, e.g. for checkboxes.
+  pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+    self
+      .fields
+      .iter()
+      .filter(move |(k, _)| k == name)
+      .map(|(_, v)| v.as_str())
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+  }
+}
+
+fn percent_decode(input: &[u8]) -> String {
+  let mut out = Vec::with_capacity(input.len());
+  let mut i = 0;
+  while i < input.len() {
+    match input[i] {
+      b'+' => out.push(b' '),
+      b'%' if i + 2 < input.len() => {
+        match std::str::from_utf8(&input[i + 1..i + 3])
+          .ok()
+          .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+          Some(b) => {
+            out.push(b);
+            i += 2;
+          }
+          None => out.push(b'%'),
+        }
+      }
+      b => out.push(b),
+    }
+    i += 1;
+  }
+
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Extracts the boundary from a `multipart/form-data; boundary=...`
+/// content type.
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+  let mut params = content_type.split(';');
+  let mime = params.next()?.trim();
+  if !mime.eq_ignore_ascii_case("multipart/form-data") {
+    return None;
+  }
+
+  params.find_map(|param| {
+    let (k, v) = param.split_once('=')?;
+    k.trim()
+      .eq_ignore_ascii_case("boundary")
+      .then(|| v.trim().trim_matches('"').to_string())
+      .filter(|b| !b.is_empty())
+  })
+}
+
+#[derive(Debug, Clone)]
+pub struct MultipartOptions {
+  /// Parts larger than this many bytes are moved from memory to a temporary
+  /// file.
+  pub memory_limit: usize,
+  /// Where temporary files for large parts are created.
+  pub temp_dir: PathBuf,
+  /// Largest accepted part header block, in bytes.
+  pub max_header_size: usize,
+  /// Most parts accepted in one body.
+  pub max_parts: usize,
+  /// Most bytes of part data accepted in one body, in memory and on disk
+  /// together.
+  pub max_total_size: usize,
+}
+
+impl Default for MultipartOptions {
+  fn default() -> Self {
+    MultipartOptions {
+      memory_limit: 64 * 1024,
+      temp_dir: env::temp_dir(),
+      max_header_size: 16 * 1024,
+      max_parts: 100,
+      max_total_size: 64 * 1024 * 1024,
+    }
+  }
+}
+
+/// A file on disk that is deleted when dropped, unless it is persisted.
+#[derive(Debug)]
+pub struct TempFile {
+  path: PathBuf,
+}
+
+impl TempFile {
+  fn create(dir: &Path) -> io::Result<(TempFile, File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_nanos())
+      .unwrap_or(0);
+    let name = format!(
+      "upload-{}-{}-{}",
+      process::id(),
+      nanos,
+      COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = dir.join(name);
+    let file = File::options().write(true).create_new(true).open(&path)?;
+
+    Ok((TempFile { path }, file))
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Moves the file to `to` so it survives this value being dropped.
+  pub fn persist(self, to: impl AsRef<Path>) -> io::Result<()> {
+    let to = to.as_ref();
+    if fs::rename(&self.path, to).is_err() {
+      // `rename` can't cross file systems, so fall back to copying.
+      fs::copy(&self.path, to)?;
+    }
+    Ok(())
+  }
+}
+
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+#[derive(Debug)]
+pub enum PartData {
+  Memory(Vec<u8>),
+  File(TempFile),
+}
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Part {
+  pub name: String,
+  pub filename: Option<String>,
+  pub content_type: Option<String>,
+  pub headers: Vec<(String, String)>,
+  pub data: PartData,
+}
+
+impl Part {
+  fn new(headers: Vec<(String, String)>, sink: Sink) -> io::Result<Part> {
+    let header = |name: &str| {
+      headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.clone())
+    };
+    let disposition = header("Content-Disposition").unwrap_or_default();
+    let param = |key: &str| {
+      disposition.split(';').skip(1).find_map(|param| {
+        let (k, v) = param.split_once('=')?;
+        k.trim()
+          .eq_ignore_ascii_case(key)
+          .then(|| v.trim().trim_matches('"').to_string())
+      })
+    };
+    let name = param("name")
+      .ok_or_else(|| invalid_data("multipart part is missing a Content-Disposition name".into()))?;
+
+    Ok(Part {
+      name,
+      filename: param("filename"),
+      content_type: header("Content-Type"),
+      data: sink.finish()?,
+      headers,
+    })
+  }
+
+  /// Returns the part as text if it was kept in memory.
+  pub fn text(&self) -> Option<String> {
+    match &self.data {
+      PartData::Memory(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+      PartData::File(_) => None,
+    }
+  }
+
+  /// Reads the whole part, from memory or from its temporary file.
+  pub fn bytes(&self) -> io::Result<Vec<u8>> {
+    match &self.data {
+      PartData::Memory(bytes) => Ok(bytes.clone()),
+      PartData::File(file) => fs::read(file.path()),
+    }
+  }
+}
+
+/// A decoded `multipart/form-data` body.
+#[derive(Debug, Default)]
+pub struct Multipart {
+  parts: Vec<Part>,
+}
+
+impl Multipart {
+  /// Streams a multipart body from `reader`.
+  ///
+  /// Parts are buffered in memory until they exceed
+  /// [`MultipartOptions::memory_limit`], after which they continue into a
+  /// [`TempFile`], so large uploads never have to fit in memory. Bodies with
+  /// more than [`MultipartOptions::max_parts`] parts or
+  /// [`MultipartOptions::max_total_size`] bytes are an `InvalidData` error.
+  pub fn parse(reader: impl Read, boundary: &str, options: &MultipartOptions) -> io::Result<Self> {
+    let mut parser = Parser {
+      reader: BufReader::new(reader),
+      // Pretend the body starts with a line break so the first delimiter
+      // looks like every other one.
+      buf: b"\r\n".to_vec(),
+      eof: false,
+    };
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+    let mut total = 0;
+
+    parser.skip_until(&delimiter)?;
+    loop {
+      parser.fill(2)?;
+      if parser.buf.starts_with(b"--") {
+        break;
+      }
+      if parts.len() == options.max_parts {
+        return Err(invalid_data(format!(
+          "multipart body has more than {} parts",
+          options.max_parts
+        )));
+      }
+      parser.skip_until(b"\r\n")?;
+      let headers = parser.read_headers(options.max_header_size)?;
+      let mut sink = Sink::Memory(Vec::new());
+      parser.copy_until(&delimiter, &mut sink, &mut total, options)?;
+      parts.push(Part::new(headers, sink)?);
+    }
+
+    Ok(Multipart { parts })
+  }
+
+  /// Returns the first part called `name`.
+  pub fn get(&self, name: &str) -> Option<&Part> {
+    self.parts.iter().find(|p| p.name == name)
+  }
+
+  pub fn parts(&self) -> &[Part] {
+    &self.parts
+  }
+
+  pub fn into_parts(self) -> Vec<Part> {
+    self.parts
+  }
+}
+
+enum Sink {
+  Memory(Vec<u8>),
+  File(TempFile, io::BufWriter<File>),
+}
+
+impl Sink {
+  fn write(&mut self, data: &[u8], options: &MultipartOptions) -> io::Result<()> {
+    if let Sink::Memory(bytes) = self {
+      if bytes.len() + data.len() <= options.memory_limit {
+        bytes.extend_from_slice(data);
+        return Ok(());
+      }
+      let (temp, file) = TempFile::create(&options.temp_dir)?;
+      let mut file = io::BufWriter::new(file);
+      file.write_all(bytes)?;
+      *self = Sink::File(temp, file);
+    }
+    if let Sink::File(_, file) = self {
+      file.write_all(data)?;
+    }
+
+    Ok(())
+  }
+
+  fn finish(self) -> io::Result<PartData> {
+    match self {
+      Sink::Memory(bytes) => Ok(PartData::Memory(bytes)),
+      Sink::File(temp, mut file) => {
+        file.flush()?;
+        Ok(PartData::File(temp))
+      }
+    }
+  }
+}
+
+struct Parser<R> {
+  reader: BufReader<R>,
+  buf: Vec<u8>,
+  eof: bool,
+}
+
+impl<R: Read> Parser<R> {
+  /// Reads until at least `n` bytes are buffered, failing on a premature end.
+  fn fill(&mut self, n: usize) -> io::Result<()> {
+    while self.buf.len() < n {
+      if !self.read_more()? {
+        return Err(unexpected_eof());
+      }
+    }
+    Ok(())
+  }
+
+  fn read_more(&mut self) -> io::Result<bool> {
+    if self.eof {
+      return Ok(false);
+    }
+    let mut chunk = [0; 8192];
+    let n = self.reader.read(&mut chunk)?;
+    self.eof = n == 0;
+    self.buf.extend_from_slice(&chunk[..n]);
+    Ok(n > 0)
+  }
+
+  /// Discards everything up to and including `needle`.
+  fn skip_until(&mut self, needle: &[u8]) -> io::Result<()> {
+    loop {
+      if let Some(i) = find(&self.buf, needle) {
+        self.buf.drain(..i + needle.len());
+        return Ok(());
+      }
+      let keep = needle.len().saturating_sub(1).min(self.buf.len());
+      self.buf.drain(..self.buf.len() - keep);
+      if !self.read_more()? {
+        return Err(unexpected_eof());
+      }
+    }
+  }
+
+  fn read_headers(&mut self, max_size: usize) -> io::Result<Vec<(String, String)>> {
+    // An immediate blank line means the part has no headers at all.
+    let end = loop {
+      if self.buf.starts_with(b"\r\n") {
+        break 0;
+      }
+      if let Some(i) = find(&self.buf, b"\r\n\r\n") {
+        break i + 2;
+      }
+      if self.buf.len() > max_size {
+        return Err(invalid_data("multipart part headers are too large".into()));
+      }
+      if !self.read_more()? {
+        return Err(unexpected_eof());
+      }
+    };
+    let head: Vec<u8> = self.buf.drain(..end + 2).collect();
+    let head = String::from_utf8_lossy(&head);
+
+    Ok(
+      head
+        .split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect(),
+    )
+  }
+
+  /// Streams everything before `delimiter` into `sink` and consumes the
+  /// delimiter itself. `total` counts the bytes of every part so far.
+  fn copy_until(
+    &mut self,
+    delimiter: &[u8],
+    sink: &mut Sink,
+    total: &mut usize,
+    options: &MultipartOptions,
+  ) -> io::Result<()> {
+    let mut write = |data: &[u8]| {
+      *total += data.len();
+      if *total > options.max_total_size {
+        return Err(invalid_data(format!(
+          "multipart body is larger than {} bytes",
+          options.max_total_size
+        )));
+      }
+      sink.write(data, options)
+    };
+    loop {
+      if let Some(i) = find(&self.buf, delimiter) {
+        write(&self.buf[..i])?;
+        self.buf.drain(..i + delimiter.len());
+        return Ok(());
+      }
+      // The end of the buffer may hold the start of a delimiter that is cut
+      // off, so it stays until more data arrives.
+      let safe = self.buf.len().saturating_sub(delimiter.len() - 1);
+      write(&self.buf[..safe])?;
+      self.buf.drain(..safe);
+      if !self.read_more()? {
+        return Err(unexpected_eof());
+      }
+    }
+  }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unexpected_eof() -> io::Error {
+  io::Error::new(
+    io::ErrorKind::UnexpectedEof,
+    "multipart body ended before the closing boundary",
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BODY: &str = "preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\nContent-Type: application/octet-stream\r\n\r\n\x00\x01--XyZ\r\n\r\n--XyZ--\r\nepilogue";
+
+  /// Hands out one byte per `read` call to exercise delimiters that are
+  /// split across reads.
+  struct Trickle<'a>(&'a [u8]);
+
+  impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      match self.0.split_first() {
+        Some((b, rest)) if !buf.is_empty() => {
+          buf[0] = *b;
+          self.0 = rest;
+          Ok(1)
+        }
+        _ => Ok(0),
+      }
+    }
+  }
+
+  #[test]
+  fn test_urlencoded() {
+    let form = Form::parse(b"a=1&b=two+words&c=%E4%BD%A0%2B&a=2&flag&bad=%zz");
+
+    assert_eq!(form.get("a"), Some("1"));
+    assert_eq!(form.get_all("a").collect::<Vec<_>>(), vec!["1", "2"]);
+    assert_eq!(form.get("b"), Some("two words"));
+    assert_eq!(form.get("c"), Some("你+"));
+    assert_eq!(form.get("flag"), Some(""));
+    assert_eq!(form.get("bad"), Some("%zz"));
+  }
+
+  #[test]
+  fn test_boundary() {
+    assert_eq!(
+      multipart_boundary("multipart/form-data; boundary=\"a b\""),
+      Some("a b".into())
+    );
+    assert_eq!(multipart_boundary("text/plain; boundary=x"), None);
+  }
+
+  #[test]
+  fn test_multipart_in_memory() {
+    let multipart = Multipart::parse(
+      Trickle(BODY.as_bytes()),
+      "XyZ",
+      &MultipartOptions::default(),
+    )
+    .unwrap();
+
+    assert_eq!(multipart.parts().len(), 2);
+    assert_eq!(multipart.get("title").unwrap().text().unwrap(), "Hello");
+    let file = multipart.get("file").unwrap();
+    assert_eq!(file.filename.as_deref(), Some("a.bin"));
+    assert_eq!(
+      file.content_type.as_deref(),
+      Some("application/octet-stream")
+    );
+    assert_eq!(file.bytes().unwrap(), b"\x00\x01--XyZ\r\n");
+  }
+
+  #[test]
+  fn test_multipart_spills_to_disk() {
+    let options = MultipartOptions {
+      memory_limit: 3,
+      ..MultipartOptions::default()
+    };
+    let multipart = Multipart::parse(BODY.as_bytes(), "XyZ", &options).unwrap();
+    let file = multipart.get("file").unwrap();
+
+    let PartData::File(temp) = &file.data else {
+      panic!("expected the part to spill to disk");
+    };
+    let path = temp.path().to_path_buf();
+    assert_eq!(fs::read(&path).unwrap(), b"\x00\x01--XyZ\r\n");
+    assert!(file.text().is_none());
+
+    drop(multipart);
+    assert!(!path.exists());
+  }
+
+  #[test]
+  fn test_multipart_truncated() {
+    let err =
+      Multipart::parse(&BODY.as_bytes()[..60], "XyZ", &MultipartOptions::default()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+  }
+
+  #[test]
+  fn test_multipart_limits() {
+    let parse = |max_parts, max_total_size| {
+      let options = MultipartOptions {
+        max_parts,
+        max_total_size,
+        ..MultipartOptions::default()
+      };
+      Multipart::parse(BODY.as_bytes(), "XyZ", &options)
+    };
+
+    assert_eq!(parse(2, 14).unwrap().parts().len(), 2);
+    let err = parse(1, 14).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = parse(2, 13).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+}
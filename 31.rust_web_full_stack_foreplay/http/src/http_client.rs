@@ -0,0 +1,526 @@
+use super::http_request::{HttpRequest, Method, Resource, Version};
+use super::http_response::Response;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// A blocking HTTP/1.1 client.
+///
+/// Connections are kept alive and reused per `host:port` for as long as the
+/// server allows it. Redirects are followed up to [`HttpClient::max_redirects`]
+/// hops, and chunked responses are decoded transparently.
+///
+/// ```no_run
+/// use http::http_client::HttpClient;
+///
+/// let mut client = HttpClient::new();
+/// let res = client.get("http://localhost:3000/api/shipping/orders").unwrap();
+/// println!("{}", res.status_code());
+/// ```
+pub struct HttpClient {
+  timeout: Option<Duration>,
+  max_redirects: usize,
+  max_body_size: usize,
+  connections: HashMap<String, BufReader<TcpStream>>,
+}
+
+impl Default for HttpClient {
+  fn default() -> Self {
+    Self {
+      timeout: Some(Duration::from_secs(30)),
+      max_redirects: 5,
+      max_body_size: 16 * 1024 * 1024,
+      connections: HashMap::new(),
+    }
+  }
+}
+
+impl HttpClient {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the connect, read and write timeout. `None` waits forever.
+  pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Sets how many redirects are followed before giving up. `0` disables
+  /// redirect handling and returns the redirect response itself.
+  pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+    self.max_redirects = max_redirects;
+    self
+  }
+
+  /// Sets the largest response body accepted, in bytes. Larger bodies are an
+  /// `InvalidData` error.
+  pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+    self.max_body_size = max_body_size;
+    self
+  }
+
+  pub fn get(&mut self, url: &str) -> io::Result<Response> {
+    self.send(Method::Get, url, &[], "")
+  }
+
+  pub fn delete(&mut self, url: &str) -> io::Result<Response> {
+    self.send(Method::Delete, url, &[], "")
+  }
+
+  pub fn post_json<T: Serialize + ?Sized>(&mut self, url: &str, body: &T) -> io::Result<Response> {
+    self.send_json(Method::Post, url, body)
+  }
+
+  pub fn put_json<T: Serialize + ?Sized>(&mut self, url: &str, body: &T) -> io::Result<Response> {
+    self.send_json(Method::Put, url, body)
+  }
+
+  pub fn patch_json<T: Serialize + ?Sized>(&mut self, url: &str, body: &T) -> io::Result<Response> {
+    self.send_json(Method::Patch, url, body)
+  }
+
+  /// Sends `body` serialized as JSON with `Content-Type: application/json`.
+  pub fn send_json<T: Serialize + ?Sized>(
+    &mut self,
+    method: Method,
+    url: &str,
+    body: &T,
+  ) -> io::Result<Response> {
+    let body = serde_json::to_string(body)?;
+    self.send(method, url, &[("Content-Type", "application/json")], &body)
+  }
+
+  /// Sends a request and follows redirects.
+  ///
+  /// `301`, `302` and `303` turn the follow-up request into a bodiless `GET`,
+  /// while `307` and `308` repeat the original method and body. Credential
+  /// headers are dropped once a redirect leads to another host or port.
+  pub fn send(
+    &mut self,
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+  ) -> io::Result<Response> {
+    let mut method = method;
+    let mut url = Url::parse(url)?;
+    let mut headers = headers.to_vec();
+    let mut body = body;
+    let mut redirects = 0;
+
+    loop {
+      let res = self.send_once(method, &url, &headers, body)?;
+      let location = match res.status_code() {
+        301 | 302 | 303 | 307 | 308 if self.max_redirects > 0 => res.header_value("Location"),
+        _ => None,
+      };
+      let Some(location) = location else {
+        return Ok(res);
+      };
+      if redirects == self.max_redirects {
+        return Err(io::Error::other(format!(
+          "too many redirects (max {})",
+          self.max_redirects
+        )));
+      }
+
+      redirects += 1;
+      let next = url.join(location)?;
+      if next.authority() != url.authority() {
+        headers.retain(|(k, _)| !is_credential(k));
+      }
+      url = next;
+      if matches!(res.status_code(), 301..=303) && method != Method::Head {
+        method = Method::Get;
+        body = "";
+      }
+    }
+  }
+
+  fn send_once(
+    &mut self,
+    method: Method,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &str,
+  ) -> io::Result<Response> {
+    let mut req_headers: HashMap<String, String> = headers
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect();
+    req_headers.insert("Host".into(), url.authority());
+    req_headers
+      .entry("Connection".into())
+      .or_insert_with(|| "keep-alive".into());
+    let req = HttpRequest {
+      method,
+      version: Version::V1_1,
+      resource: Resource::Path(url.path.clone()),
+      headers: req_headers,
+      msg_body: body.as_bytes().to_vec(),
+    };
+
+    let key = url.authority();
+    // A pooled connection may have been closed by the server in the meantime,
+    // so a failure on a reused connection gets one retry on a fresh one. Once
+    // the request has been sent the server may have acted on it, so only
+    // idempotent methods are retried then.
+    if let Some(mut conn) = self.connections.remove(&key) {
+      if req.write_to(conn.get_mut()).is_ok() {
+        match self.read_response(&mut conn, &req) {
+          Ok(res) => {
+            self.release(key, conn, &res);
+            return Ok(res);
+          }
+          Err(err) if !req.method.is_idempotent() => return Err(err),
+          Err(_) => {}
+        }
+      }
+    }
+    let mut conn = self.connect(url)?;
+    req.write_to(conn.get_mut())?;
+    let res = self.read_response(&mut conn, &req)?;
+    self.release(key, conn, &res);
+
+    Ok(res)
+  }
+
+  fn read_response(
+    &self,
+    conn: &mut BufReader<TcpStream>,
+    req: &HttpRequest,
+  ) -> io::Result<Response> {
+    let mut res = Response::read_head(conn)?;
+    if req.method != Method::Head {
+      res.read_body_limited(conn, self.max_body_size)?;
+    }
+
+    Ok(res)
+  }
+
+  fn release(&mut self, key: String, conn: BufReader<TcpStream>, res: &Response) {
+    if res.keep_alive() {
+      self.connections.insert(key, conn);
+    }
+  }
+
+  fn connect(&self, url: &Url) -> io::Result<BufReader<TcpStream>> {
+    let mut last_err = None;
+    for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+      let stream = match self.timeout {
+        Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+        None => TcpStream::connect(addr),
+      };
+      match stream {
+        Ok(stream) => {
+          stream.set_read_timeout(self.timeout)?;
+          stream.set_write_timeout(self.timeout)?;
+          return Ok(BufReader::new(stream));
+        }
+        Err(err) => last_err = Some(err),
+      }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("could not resolve {}", url.host),
+      )
+    }))
+  }
+}
+
+/// The parts of an `http://` URL the client needs.
+#[derive(Debug, PartialEq, Clone)]
+struct Url {
+  host: String,
+  port: u16,
+  path: String,
+}
+
+impl Url {
+  fn parse(url: &str) -> io::Result<Url> {
+    let rest = url
+      .strip_prefix("http://")
+      .ok_or_else(|| invalid_input(format!("only http:// URLs are supported: {}", url)))?;
+    let (authority, path) = match rest.find(['/', '?']) {
+      Some(i) => (&rest[..i], &rest[i..]),
+      None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+      Some((host, port)) => (
+        host,
+        port
+          .parse()
+          .map_err(|_| invalid_input(format!("invalid port in {}", url)))?,
+      ),
+      None => (authority, 80),
+    };
+    if host.is_empty() {
+      return Err(invalid_input(format!("missing host in {}", url)));
+    }
+    let path = if path.starts_with('?') {
+      format!("/{}", path)
+    } else {
+      path.to_string()
+    };
+
+    Ok(Url {
+      host: host.into(),
+      port,
+      path: path.split('#').next().unwrap_or_default().into(),
+    })
+  }
+
+  /// Resolves a `Location` header value against this URL.
+  fn join(&self, location: &str) -> io::Result<Url> {
+    if location.starts_with("http://") {
+      return Url::parse(location);
+    }
+
+    let path = if location.starts_with('/') {
+      location.to_string()
+    } else {
+      let base = self.path.split('?').next().unwrap_or_default();
+      let dir = &base[..base.rfind('/').map(|i| i + 1).unwrap_or(0)];
+      format!("{}{}", dir, location)
+    };
+
+    Ok(Url {
+      path,
+      ..self.clone()
+    })
+  }
+
+  fn authority(&self) -> String {
+    if self.port == 80 {
+      self.host.clone()
+    } else {
+      format!("{}:{}", self.host, self.port)
+    }
+  }
+}
+
+/// Headers that must not leak to another host on a redirect.
+fn is_credential(name: &str) -> bool {
+  ["Authorization", "Cookie", "Proxy-Authorization"]
+    .iter()
+    .any(|h| h.eq_ignore_ascii_case(name))
+}
+
+fn invalid_input(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, Read, Write};
+  use std::net::TcpListener;
+  use std::sync::mpsc;
+  use std::thread;
+
+  /// Serves each canned response, in order, on a single accepted connection
+  /// and returns the request heads it received.
+  fn serve<S: AsRef<str> + Send + 'static>(
+    responses: Vec<S>,
+  ) -> (String, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+      let (stream, _) = listener.accept().unwrap();
+      let mut reader = BufReader::new(stream);
+      let mut heads = Vec::new();
+      for res in responses {
+        heads.push(read_request(&mut reader));
+        reader.get_mut().write_all(res.as_ref().as_bytes()).unwrap();
+      }
+      heads
+    });
+
+    (addr, handle)
+  }
+
+  /// Reads one request and returns its head.
+  fn read_request(reader: &mut BufReader<TcpStream>) -> String {
+    let mut head = String::new();
+    loop {
+      let mut line = String::new();
+      reader.read_line(&mut line).unwrap();
+      if line == "\r\n" || line.is_empty() {
+        break;
+      }
+      head.push_str(&line);
+    }
+    if let Some(len) = head
+      .lines()
+      .find_map(|l| l.strip_prefix("Content-Length: "))
+    {
+      let mut body = vec![0; len.parse().unwrap()];
+      reader.read_exact(&mut body).unwrap();
+    }
+    head
+  }
+
+  /// Answers one request with keep-alive, then reads the next one on the
+  /// same connection and closes it without answering, as a server that
+  /// crashed mid-request would. Any later connection gets `200 OK`. Sends
+  /// the head of every request it reads.
+  fn serve_then_drop() -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      let (stream, _) = listener.accept().unwrap();
+      let mut reader = BufReader::new(stream);
+      tx.send(read_request(&mut reader)).unwrap();
+      let ok = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+      reader.get_mut().write_all(ok.as_bytes()).unwrap();
+      tx.send(read_request(&mut reader)).unwrap();
+      drop(reader);
+
+      for stream in listener.incoming() {
+        let mut reader = BufReader::new(stream.unwrap());
+        let _ = tx.send(read_request(&mut reader));
+        reader.get_mut().write_all(ok.as_bytes()).unwrap();
+      }
+    });
+
+    (addr, rx)
+  }
+
+  #[test]
+  fn test_url_parse() {
+    let url = Url::parse("http://localhost:3000/api/shipping?id=1").unwrap();
+    assert_eq!(url.host, "localhost");
+    assert_eq!(url.port, 3000);
+    assert_eq!(url.path, "/api/shipping?id=1");
+    assert_eq!(url.join("orders").unwrap().path, "/api/orders");
+    assert!(Url::parse("https://localhost").is_err());
+  }
+
+  #[test]
+  fn test_keep_alive_and_chunked() {
+    let (addr, server) = serve(vec![
+      "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst",
+      "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nsec\r\n3\r\nond\r\n0\r\nTrailer: 1\r\n\r\n",
+    ]);
+    let mut client = HttpClient::new();
+
+    let first = client.get(&format!("{}/first", addr)).unwrap();
+    let second = client.get(&format!("{}/second", addr)).unwrap();
+
+    assert_eq!(first.body_bytes(), b"first");
+    assert_eq!(second.body_bytes(), b"second");
+    assert_eq!(second.header_value("Content-Length"), Some("6"));
+    assert_eq!(server.join().unwrap().len(), 2);
+  }
+
+  #[test]
+  fn test_redirect() {
+    let (addr, server) = serve(vec![
+      "HTTP/1.1 303 See Other\r\nLocation: /done\r\nContent-Length: 0\r\n\r\n",
+      "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+    ]);
+    let mut client = HttpClient::new();
+
+    let res = client
+      .post_json(&format!("{}/orders", addr), &vec![1, 2])
+      .unwrap();
+    let heads = server.join().unwrap();
+
+    assert_eq!(res.status_code(), 200);
+    assert!(heads[0].starts_with("POST /orders HTTP/1.1\r\n"));
+    assert!(heads[0].contains("Content-Type: application/json\r\n"));
+    assert!(heads[1].starts_with("GET /done HTTP/1.1\r\n"));
+  }
+
+  #[test]
+  fn test_redirect_drops_credentials_across_hosts() {
+    let (other, other_server) = serve(vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+    let redirect = format!(
+      "HTTP/1.1 302 Found\r\nLocation: {}/landing\r\nContent-Length: 0\r\n\r\n",
+      other
+    );
+    let (addr, server) = serve(vec![
+      "HTTP/1.1 302 Found\r\nLocation: /next\r\nContent-Length: 0\r\n\r\n".to_string(),
+      redirect,
+    ]);
+    let mut client = HttpClient::new();
+
+    let headers = [
+      ("Authorization", "Bearer secret"),
+      ("Cookie", "a=1"),
+      ("Accept", "*/*"),
+    ];
+    let res = client.send(Method::Get, &addr, &headers, "").unwrap();
+    assert_eq!(res.status_code(), 200);
+
+    let heads = server.join().unwrap();
+    assert!(heads[1].contains("Authorization: Bearer secret\r\n"));
+    let head = &other_server.join().unwrap()[0];
+    assert!(head.starts_with("GET /landing HTTP/1.1\r\n"));
+    assert!(head.contains("Accept: */*\r\n"));
+    assert!(!head.contains("Authorization"));
+    assert!(!head.contains("Cookie"));
+  }
+
+  #[test]
+  fn test_too_many_redirects() {
+    let redirect = "HTTP/1.1 302 Found\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n";
+    let (addr, _server) = serve(vec![redirect]);
+    let mut client = HttpClient::new().max_redirects(0);
+    assert_eq!(client.get(&addr).unwrap().status_code(), 302);
+
+    let (addr, _server) = serve(vec![redirect, redirect]);
+    let mut client = HttpClient::new().max_redirects(1);
+    assert!(client.get(&addr).is_err());
+  }
+
+  #[test]
+  fn test_retry_idempotent_only() {
+    let (addr, heads) = serve_then_drop();
+    let mut client = HttpClient::new();
+    client.get(&addr).unwrap();
+    let res = client.get(&addr).unwrap();
+    assert_eq!(res.body_bytes(), b"ok");
+    assert_eq!(heads.iter().take(3).count(), 3);
+
+    let (addr, heads) = serve_then_drop();
+    let mut client = HttpClient::new();
+    client.get(&addr).unwrap();
+    assert!(client.post_json(&addr, &vec![1]).is_err());
+    assert_eq!(
+      heads.iter().nth(1).unwrap().lines().next(),
+      Some("POST / HTTP/1.1")
+    );
+    assert!(heads.recv_timeout(Duration::from_millis(200)).is_err());
+  }
+
+  #[test]
+  fn test_max_body_size() {
+    let (addr, _server) = serve(vec![
+      "HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n",
+    ]);
+    let mut client = HttpClient::new().max_body_size(1024);
+    let err = client.get(&addr).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn test_timeout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    let mut client = HttpClient::new().timeout(Some(Duration::from_millis(100)));
+
+    let err = client.get(&addr).unwrap_err();
+    assert!(matches!(
+      err.kind(),
+      io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    ));
+    drop(listener);
+  }
+}
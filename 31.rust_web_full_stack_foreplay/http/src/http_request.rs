@@ -1,9 +1,17 @@
+use super::cookie::CookieJar;
+use super::form::{multipart_boundary, Form, Multipart, MultipartOptions};
 use std::collections::HashMap;
+use std::io::{self, Write};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Method {
   Get,
+  Head,
   Post,
+  Put,
+  Patch,
+  Delete,
+  Options,
   Uninitialized,
 }
 
@@ -11,13 +19,42 @@ impl From<&str> for Method {
   fn from(method: &str) -> Self {
     match method {
       "GET" => Method::Get,
+      "HEAD" => Method::Head,
       "POST" => Method::Post,
+      "PUT" => Method::Put,
+      "PATCH" => Method::Patch,
+      "DELETE" => Method::Delete,
+      "OPTIONS" => Method::Options,
       _ => Method::Uninitialized,
     }
   }
 }
 
-#[derive(Debug, PartialEq)]
+impl Method {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Method::Get => "GET",
+      Method::Head => "HEAD",
+      Method::Post => "POST",
+      Method::Put => "PUT",
+      Method::Patch => "PATCH",
+      Method::Delete => "DELETE",
+      Method::Options => "OPTIONS",
+      Method::Uninitialized => "",
+    }
+  }
+
+  /// Whether sending the request twice has the same effect as sending it
+  /// once, so it is safe to retry.
+  pub fn is_idempotent(&self) -> bool {
+    matches!(
+      self,
+      Method::Get | Method::Head | Method::Put | Method::Delete | Method::Options
+    )
+  }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Version {
   V1_1,
   V2_0,
@@ -34,6 +71,15 @@ impl From<&str> for Version {
   }
 }
 
+impl Version {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Version::V1_1 | Version::Uninitialized => "HTTP/1.1",
+      Version::V2_0 => "HTTP/2.0",
+    }
+  }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Resource {
   Path(String),
@@ -45,7 +91,8 @@ pub struct HttpRequest {
   pub version: Version,
   pub resource: Resource,
   pub headers: HashMap<String, String>,
-  pub msg_body: String,
+  /// The raw body, which need not be UTF-8, e.g. for file uploads.
+  pub msg_body: Vec<u8>,
 }
 
 impl From<String> for HttpRequest {
@@ -56,32 +103,108 @@ impl From<String> for HttpRequest {
     let mut parsed_headers = HashMap::new();
     let mut parsed_msg_body = "";
 
-    for line in req.lines() {
+    // Everything after the first empty line is the body, kept verbatim so
+    // multi-line and form bodies survive. Without an empty line the last
+    // line that is not a header is taken as the body.
+    let (head, body) = match req.find("\r\n\r\n") {
+      Some(i) => (&req[..i], Some(&req[i + 4..])),
+      None => (req.as_str(), None),
+    };
+
+    for line in head.lines() {
       if line.contains("HTTP") {
         let (method, resource, version) = process_req_line(line);
         parsed_method = method;
         parsed_resource = resource;
         parsed_version = version;
-      } else if line.contains(":") {
+      } else if line.contains(':') {
         let (key, value) = process_req_header(line);
         parsed_headers.insert(key, value);
-      } else if line.len() == 0 {
+      } else if line.is_empty() {
         // empty line
       } else {
         parsed_msg_body = line;
       }
     }
+    if let Some(body) = body {
+      parsed_msg_body = body;
+    }
 
     HttpRequest {
       method: parsed_method,
       version: parsed_version,
       resource: parsed_resource,
       headers: parsed_headers,
-      msg_body: parsed_msg_body.to_string(),
+      msg_body: parsed_msg_body.as_bytes().to_vec(),
     }
   }
 }
 
+impl HttpRequest {
+  /// Returns the value of the header called `name`, compared
+  /// case-insensitively and trimmed of surrounding whitespace.
+  pub fn header(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(k, _)| k.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.trim())
+  }
+
+  /// Decodes an `application/x-www-form-urlencoded` body, or returns `None`
+  /// if the request has another content type.
+  pub fn form(&self) -> Option<Form> {
+    let content_type = self.header("Content-Type")?;
+    content_type
+      .to_ascii_lowercase()
+      .starts_with("application/x-www-form-urlencoded")
+      .then(|| Form::parse(&self.msg_body))
+  }
+
+  /// Decodes a `multipart/form-data` body, or returns `None` if the request
+  /// has another content type or no boundary.
+  ///
+  /// This works on the already-buffered `msg_body`; use [`Multipart::parse`]
+  /// directly to stream a large body from the connection.
+  pub fn multipart(&self, options: &MultipartOptions) -> Option<io::Result<Multipart>> {
+    let boundary = multipart_boundary(self.header("Content-Type")?)?;
+    Some(Multipart::parse(&self.msg_body[..], &boundary, options))
+  }
+
+  /// Parses the `Cookie` header. A request without one yields an empty jar.
+  pub fn cookies(&self) -> CookieJar {
+    CookieJar::parse(self.header("Cookie").unwrap_or_default())
+  }
+
+  /// Writes the request line, headers and body into `write_stream`.
+  ///
+  /// Header values are trimmed, since the parser keeps the space after the
+  /// colon. `Content-Length` is derived from the body whenever there is one.
+  pub fn write_to(&self, write_stream: &mut impl Write) -> io::Result<()> {
+    let Resource::Path(path) = &self.resource;
+    write!(
+      write_stream,
+      "{} {} {}\r\n",
+      self.method.as_str(),
+      path,
+      self.version.as_str()
+    )?;
+    for (k, v) in self.headers.iter() {
+      if !k.eq_ignore_ascii_case("Content-Length") {
+        write!(write_stream, "{}: {}\r\n", k, v.trim())?;
+      }
+    }
+    if !self.msg_body.is_empty()
+      || matches!(self.method, Method::Post | Method::Put | Method::Patch)
+    {
+      write!(write_stream, "Content-Length: {}\r\n", self.msg_body.len())?;
+    }
+    write!(write_stream, "\r\n")?;
+    write_stream.write_all(&self.msg_body)?;
+    write_stream.flush()
+  }
+}
+
 fn process_req_line(line: &str) -> (Method, Resource, Version) {
   let mut words = line.split_whitespace();
   let method = words.next().unwrap();
@@ -96,18 +219,10 @@ fn process_req_line(line: &str) -> (Method, Resource, Version) {
 }
 
 fn process_req_header(line: &str) -> (String, String) {
-  let mut words = line.split(":");
-  let mut key = String::from("");
-  let mut value = String::from("");
-
-  if let Some(k) = words.next() {
-    key = k.to_string();
-  };
-  if let Some(v) = words.next() {
-    value = v.to_string();
-  };
-
-  (key, value)
+  match line.split_once(':') {
+    Some((key, value)) => (key.to_string(), value.to_string()),
+    None => (line.to_string(), String::from("")),
+  }
 }
 
 #[cfg(test)]
@@ -120,6 +235,12 @@ mod tests {
     assert_eq!(method, Method::Get);
   }
 
+  #[test]
+  fn test_method_as_str() {
+    let method: Method = "DELETE".into();
+    assert_eq!(method.as_str(), "DELETE");
+  }
+
   #[test]
   fn test_version_into() {
     let version: Version = "HTTP/1.1".into();
@@ -141,6 +262,50 @@ mod tests {
         .unwrap()[..],
       " zh-CN,zh;q=0.9,en;q=0.8"
     );
-    assert_eq!(request.msg_body, String::from("Hello World"));
+    assert_eq!(request.msg_body, b"Hello World");
+  }
+
+  #[test]
+  fn test_write_http() {
+    let mut headers = HashMap::new();
+    headers.insert("Host".to_string(), " localhost:3000".to_string());
+    let request = HttpRequest {
+      method: Method::Post,
+      version: Version::V1_1,
+      resource: Resource::Path("/api/shipping/orders".into()),
+      headers,
+      msg_body: "{}".into(),
+    };
+    let mut buf = Vec::new();
+    request.write_to(&mut buf).unwrap();
+
+    assert_eq!(
+      String::from_utf8(buf).unwrap(),
+      "POST /api/shipping/orders HTTP/1.1\r\nHost: localhost:3000\r\nContent-Length: 2\r\n\r\n{}"
+    );
+  }
+
+  #[test]
+  fn test_read_cookies() {
+    let req = String::from("GET / HTTP/1.1\r\ncookie: theme=dark; lang=zh\r\n\r\n");
+    let request: HttpRequest = req.into();
+    let jar = request.cookies();
+
+    assert_eq!(jar.get("theme"), Some("dark"));
+    assert_eq!(jar.get("lang"), Some("zh"));
+  }
+
+  #[test]
+  fn test_read_multiline_body() {
+    let req = String::from(
+      "POST /login HTTP/1.1\r\nHost: localhost:3000\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=Ferris+Crab&note=a%3Ab\nnext",
+    );
+    let request: HttpRequest = req.into();
+
+    assert_eq!(request.header("host"), Some("localhost:3000"));
+    assert_eq!(request.msg_body, b"name=Ferris+Crab&note=a%3Ab\nnext");
+    let form = request.form().unwrap();
+    assert_eq!(form.get("name"), Some("Ferris Crab"));
+    assert_eq!(form.get("note"), Some("a:b\nnext"));
   }
 }
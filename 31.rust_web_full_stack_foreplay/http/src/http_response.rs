@@ -1,5 +1,8 @@
+use super::cookie::Cookie;
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::HashMap;
-use std::io::{self, Write};
+use std::io::{self, BufRead, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct HttpResponse<'a> {
@@ -24,7 +27,6 @@ impl<'a> Default for HttpResponse<'a> {
 
 impl<'a> From<HttpResponse<'a>> for String {
   fn from(res: HttpResponse) -> Self {
-    let res = res.clone();
     format!(
       "{} {} {}\r\n{}Content-Length: {}\r\n\r\n{}",
       &res.version(),
@@ -67,21 +69,28 @@ impl<'a> HttpResponse<'a> {
   }
 
   pub fn send_response(&self, write_stream: &mut impl Write) -> io::Result<()> {
-    let res = self.clone();
-    let res_str = String::from(res);
-    write!(write_stream, "{}", res_str)
+    write!(
+      write_stream,
+      "{} {} {}\r\n{}Content-Length: {}\r\n\r\n{}",
+      self.version(),
+      self.status_code(),
+      self.status_text(),
+      self.headers(),
+      self.msg_body().len(),
+      self.msg_body()
+    )
   }
 
   fn version(&self) -> &str {
-    &self.version
+    self.version
   }
 
   fn status_code(&self) -> &str {
-    &self.status_code
+    self.status_code
   }
 
   fn status_text(&self) -> &str {
-    &self.status_text
+    self.status_text
   }
 
   fn headers(&self) -> String {
@@ -102,6 +111,468 @@ impl<'a> HttpResponse<'a> {
   }
 }
 
+/// Returns the reason phrase for a status code.
+pub fn status_text(status_code: u16) -> &'static str {
+  match status_code {
+    100 => "Continue",
+    101 => "Switching Protocols",
+    200 => "OK",
+    201 => "Created",
+    202 => "Accepted",
+    204 => "No Content",
+    206 => "Partial Content",
+    301 => "Moved Permanently",
+    302 => "Found",
+    303 => "See Other",
+    304 => "Not Modified",
+    307 => "Temporary Redirect",
+    308 => "Permanent Redirect",
+    400 => "Bad Request",
+    401 => "Unauthorized",
+    403 => "Forbidden",
+    404 => "Not Found",
+    405 => "Method Not Allowed",
+    408 => "Request Timeout",
+    409 => "Conflict",
+    411 => "Length Required",
+    413 => "Payload Too Large",
+    415 => "Unsupported Media Type",
+    422 => "Unprocessable Entity",
+    429 => "Too Many Requests",
+    500 => "Internal Server Error",
+    501 => "Not Implemented",
+    502 => "Bad Gateway",
+    503 => "Service Unavailable",
+    504 => "Gateway Timeout",
+    505 => "HTTP Version Not Supported",
+    _ => "Unknown",
+  }
+}
+
+/// Formats `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`,
+/// the format used by `Date`, `Expires` and `Last-Modified`.
+pub fn http_date(time: SystemTime) -> String {
+  const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+  const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+  ];
+
+  let secs = time
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0);
+  let days = secs / 86400;
+  let secs_of_day = secs % 86400;
+
+  // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+  let z = days as i64 + 719468;
+  let era = z / 146097;
+  let doe = z - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + i64::from(month <= 2);
+
+  format!(
+    "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+    WEEKDAYS[(days % 7) as usize],
+    day,
+    MONTHS[(month - 1) as usize],
+    year,
+    secs_of_day / 3600,
+    secs_of_day % 3600 / 60,
+    secs_of_day % 60
+  )
+}
+
+/// An owned HTTP response.
+///
+/// Unlike [`HttpResponse`], every part of the response is owned, so headers can
+/// be built from runtime data and the body can hold arbitrary bytes such as
+/// images. Responses are built fluently:
+///
+/// ```
+/// use http::http_response::Response;
+///
+/// let res = Response::new()
+///   .status(404)
+///   .header("Content-Type", "text/plain")
+///   .body("not here");
+/// assert_eq!(res.status_code(), 404);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Response {
+  version: String,
+  status_code: u16,
+  status_text: String,
+  headers: Vec<(String, String)>,
+  body: Vec<u8>,
+}
+
+impl Default for Response {
+  fn default() -> Self {
+    Self {
+      version: "HTTP/1.1".into(),
+      status_code: 200,
+      status_text: status_text(200).into(),
+      headers: Vec::new(),
+      body: Vec::new(),
+    }
+  }
+}
+
+impl Response {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the status code along with its standard reason phrase.
+  pub fn status(mut self, status_code: u16) -> Self {
+    self.status_code = status_code;
+    self.status_text = status_text(status_code).into();
+    self
+  }
+
+  /// Appends a header. Calling it twice with the same name sends the header
+  /// twice, which is what `Set-Cookie` needs; use [`Response::set_header`] to
+  /// replace an existing value instead.
+  pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+    self.headers.push((name.into(), value.into()));
+    self
+  }
+
+  /// Replaces every header called `name` (case-insensitively) with `value`.
+  pub fn set_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+    let name = name.into();
+    self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(&name));
+    self.headers.push((name, value.into()));
+    self
+  }
+
+  /// Appends a `Set-Cookie` header for `cookie`.
+  pub fn cookie(self, cookie: &Cookie) -> Self {
+    self.header("Set-Cookie", cookie.to_string())
+  }
+
+  pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+    self.body = body.into();
+    self
+  }
+
+  /// Serializes `value` as the body and sets `Content-Type: application/json`.
+  ///
+  /// If serialization fails the response becomes a `500` carrying the error
+  /// message, so the builder chain never has to deal with a `Result`.
+  pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Self {
+    match serde_json::to_vec(value) {
+      Ok(body) => self
+        .set_header("Content-Type", "application/json")
+        .body(body),
+      Err(err) => self
+        .status(500)
+        .set_header("Content-Type", "text/plain")
+        .body(err.to_string()),
+    }
+  }
+
+  pub fn version(&self) -> &str {
+    &self.version
+  }
+
+  pub fn status_code(&self) -> u16 {
+    self.status_code
+  }
+
+  pub fn status_text(&self) -> &str {
+    &self.status_text
+  }
+
+  pub fn headers(&self) -> &[(String, String)] {
+    &self.headers
+  }
+
+  /// Returns the first value of the header called `name`, compared
+  /// case-insensitively.
+  pub fn header_value(&self, name: &str) -> Option<&str> {
+    self
+      .headers
+      .iter()
+      .find(|(k, _)| k.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+
+  pub fn body_bytes(&self) -> &[u8] {
+    &self.body
+  }
+
+  /// Deserializes the body as JSON.
+  pub fn parse_json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+    serde_json::from_slice(&self.body)
+  }
+
+  /// Reads a complete response, head and body, from `reader`.
+  pub fn read_from(reader: &mut impl BufRead) -> io::Result<Response> {
+    let mut res = Self::read_head(reader)?;
+    res.read_body(reader)?;
+    Ok(res)
+  }
+
+  /// Reads the status line and headers from `reader`, leaving the body unread.
+  pub fn read_head(reader: &mut impl BufRead) -> io::Result<Response> {
+    let status_line = read_line(reader)?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status_code = parts
+      .next()
+      .and_then(|code| code.parse().ok())
+      .filter(|_| version.starts_with("HTTP/"))
+      .ok_or_else(|| invalid_data(format!("malformed status line: {:?}", status_line)))?;
+    let status_text = parts.next().unwrap_or_default();
+
+    let mut res = Response {
+      version: version.into(),
+      status_code,
+      status_text: status_text.into(),
+      ..Response::default()
+    };
+    loop {
+      let line = read_line(reader)?;
+      if line.is_empty() {
+        break;
+      }
+      let (k, v) = line
+        .split_once(':')
+        .ok_or_else(|| invalid_data(format!("malformed header: {:?}", line)))?;
+      res.headers.push((k.trim().into(), v.trim().into()));
+    }
+
+    Ok(res)
+  }
+
+  /// Reads the body that belongs to a head returned by
+  /// [`Response::read_head`], however large it is.
+  pub fn read_body(&mut self, reader: &mut impl BufRead) -> io::Result<()> {
+    self.read_body_limited(reader, usize::MAX)
+  }
+
+  /// Reads the body that belongs to a head returned by
+  /// [`Response::read_head`]. Bodies over `max_size` bytes are an
+  /// `InvalidData` error.
+  ///
+  /// Chunked bodies are decoded and their `Transfer-Encoding` header is
+  /// replaced by the decoded `Content-Length`. Without either header the body
+  /// runs until the peer closes the connection.
+  pub fn read_body_limited(
+    &mut self,
+    reader: &mut impl BufRead,
+    max_size: usize,
+  ) -> io::Result<()> {
+    if !self.has_body() {
+      return Ok(());
+    }
+
+    let chunked = self
+      .header_value("Transfer-Encoding")
+      .map(|te| te.to_ascii_lowercase().contains("chunked"))
+      .unwrap_or(false);
+    self.body.clear();
+    if chunked {
+      read_chunked(reader, &mut self.body, max_size)?;
+      let len = self.body.len().to_string();
+      self
+        .headers
+        .retain(|(k, _)| !k.eq_ignore_ascii_case("Transfer-Encoding"));
+      *self = std::mem::take(self).set_header("Content-Length", len);
+    } else if let Some(len) = self.content_length() {
+      let len = len.map_err(|_| invalid_data("invalid Content-Length".into()))?;
+      if len > max_size {
+        return Err(too_large(max_size));
+      }
+      read_exactly(reader, &mut self.body, len)?;
+    } else {
+      let limit = (max_size as u64).saturating_add(1);
+      reader.by_ref().take(limit).read_to_end(&mut self.body)?;
+      if self.body.len() > max_size {
+        return Err(too_large(max_size));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Whether the peer is allowed to keep the connection open after this
+  /// response, per HTTP/1.1 persistence rules.
+  pub fn keep_alive(&self) -> bool {
+    let close = self
+      .header_value("Connection")
+      .map(|c| c.eq_ignore_ascii_case("close"))
+      .unwrap_or(false);
+    let delimited = !self.has_body()
+      || self.content_length().is_some()
+      || self.header_value("Transfer-Encoding").is_some();
+
+    !close && delimited && self.version == "HTTP/1.1"
+  }
+
+  /// Whether the status allows a body; `1xx`, `204` and `304` never have
+  /// one.
+  pub fn has_body(&self) -> bool {
+    !matches!(self.status_code, 100..=199 | 204 | 304)
+  }
+
+  fn content_length(&self) -> Option<Result<usize, std::num::ParseIntError>> {
+    self
+      .header_value("Content-Length")
+      .map(|len| len.trim().parse())
+  }
+
+  /// Writes only the status line and headers, for bodies that are streamed
+  /// afterwards. No `Content-Length` is added, so the body ends when the
+  /// connection closes unless the headers say otherwise.
+  pub fn write_head(&self, write_stream: &mut impl Write) -> io::Result<()> {
+    write!(
+      write_stream,
+      "{} {} {}\r\n",
+      self.version, self.status_code, self.status_text
+    )?;
+    for (k, v) in self.headers.iter() {
+      write!(write_stream, "{}: {}\r\n", k, v)?;
+    }
+    write!(write_stream, "\r\n")?;
+    write_stream.flush()
+  }
+
+  /// Writes the status line, headers and body straight into `write_stream`.
+  ///
+  /// `Content-Length` is always derived from the body, so any value set
+  /// through [`Response::header`] is ignored. Statuses that can't have a body,
+  /// such as `204` and `304`, are written without one.
+  pub fn write_to(&self, write_stream: &mut impl Write) -> io::Result<()> {
+    self.write(write_stream, true)
+  }
+
+  /// Writes the response as [`Response::write_to`] would, including its
+  /// `Content-Length`, but not the body, which is how `HEAD` is answered.
+  pub fn write_without_body(&self, write_stream: &mut impl Write) -> io::Result<()> {
+    self.write(write_stream, false)
+  }
+
+  fn write(&self, write_stream: &mut impl Write, with_body: bool) -> io::Result<()> {
+    write!(
+      write_stream,
+      "{} {} {}\r\n",
+      self.version, self.status_code, self.status_text
+    )?;
+    for (k, v) in self.headers.iter() {
+      if !k.eq_ignore_ascii_case("Content-Length") {
+        write!(write_stream, "{}: {}\r\n", k, v)?;
+      }
+    }
+    if self.has_body() {
+      write!(write_stream, "Content-Length: {}\r\n\r\n", self.body.len())?;
+      if with_body {
+        write_stream.write_all(&self.body)?;
+      }
+    } else {
+      write!(write_stream, "\r\n")?;
+    }
+    write_stream.flush()
+  }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads one CRLF (or bare LF) terminated line without its line ending.
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+  let mut line = String::new();
+  if reader.read_line(&mut line)? == 0 {
+    return Err(io::Error::new(
+      io::ErrorKind::UnexpectedEof,
+      "connection closed before the message was complete",
+    ));
+  }
+  while line.ends_with('\n') || line.ends_with('\r') {
+    line.pop();
+  }
+
+  Ok(line)
+}
+
+fn too_large(max_size: usize) -> io::Error {
+  invalid_data(format!("body is larger than {} bytes", max_size))
+}
+
+/// Appends exactly `len` bytes to `body`, growing it only as data arrives so
+/// a bogus length can't allocate up front.
+fn read_exactly(reader: &mut impl BufRead, body: &mut Vec<u8>, len: usize) -> io::Result<()> {
+  let read = reader.by_ref().take(len as u64).read_to_end(body)?;
+  if read < len {
+    return Err(io::Error::new(
+      io::ErrorKind::UnexpectedEof,
+      "connection closed before the body was complete",
+    ));
+  }
+  Ok(())
+}
+
+fn read_chunked(reader: &mut impl BufRead, body: &mut Vec<u8>, max_size: usize) -> io::Result<()> {
+  loop {
+    let line = read_line(reader)?;
+    let size = line.split(';').next().unwrap_or_default().trim();
+    let size = usize::from_str_radix(size, 16)
+      .map_err(|_| invalid_data(format!("invalid chunk size: {:?}", line)))?;
+    if size == 0 {
+      break;
+    }
+    if body
+      .len()
+      .checked_add(size)
+      .is_none_or(|end| end > max_size)
+    {
+      return Err(too_large(max_size));
+    }
+    read_exactly(reader, body, size)?;
+    read_line(reader)?;
+  }
+  // Skip trailers up to the terminating empty line.
+  while !read_line(reader)?.is_empty() {}
+
+  Ok(())
+}
+
+impl<'a> From<HttpResponse<'a>> for Response {
+  fn from(res: HttpResponse<'a>) -> Self {
+    let mut headers: Vec<(String, String)> = res
+      .headers
+      .unwrap_or_default()
+      .into_iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect();
+    headers.sort();
+
+    Response {
+      version: res.version.into(),
+      status_code: res.status_code.parse().unwrap_or(500),
+      status_text: res.status_text.into(),
+      headers,
+      body: res.msg_body.unwrap_or_default().into_bytes(),
+    }
+  }
+}
+
+impl From<Response> for Vec<u8> {
+  fn from(res: Response) -> Self {
+    let mut buf = Vec::with_capacity(res.body.len() + 128);
+    // Writing into a `Vec` cannot fail.
+    let _ = res.write_to(&mut buf);
+    buf
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -149,4 +620,89 @@ mod tests {
         .to_string();
     assert_eq!(actual_str, res_str);
   }
+
+  #[test]
+  fn test_response_builder() {
+    let res = Response::new()
+      .status(201)
+      .header("Content-Type", "image/png")
+      .body(vec![0x89, b'P', b'N', b'G']);
+
+    assert_eq!(res.status_code(), 201);
+    assert_eq!(res.status_text(), "Created");
+    assert_eq!(res.header_value("content-type"), Some("image/png"));
+    assert_eq!(res.body_bytes(), &[0x89, b'P', b'N', b'G']);
+  }
+
+  #[test]
+  fn test_response_json() {
+    let res = Response::new()
+      .header("Content-Type", "text/html")
+      .json(&vec![1, 2, 3]);
+
+    assert_eq!(res.header_value("Content-Type"), Some("application/json"));
+    assert_eq!(res.headers().len(), 1);
+    assert_eq!(res.body_bytes(), b"[1,2,3]");
+  }
+
+  #[test]
+  fn test_response_write_to() {
+    let res = Response::new()
+      .status(404)
+      .header("Set-Cookie", "a=1")
+      .header("Set-Cookie", "b=2")
+      .header("Content-Length", "999")
+      .body(&b"\xffxx"[..]);
+    let bytes: Vec<u8> = res.into();
+
+    assert_eq!(
+      bytes,
+      b"HTTP/1.1 404 Not Found\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nContent-Length: 3\r\n\r\n\xffxx"
+    );
+  }
+
+  #[test]
+  fn test_http_date() {
+    let time = UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+    assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+  }
+
+  #[test]
+  fn test_response_from_http_response() {
+    let res: Response = HttpResponse::new("404", None, Some("xxx".into())).into();
+
+    assert_eq!(res.status_code(), 404);
+    assert_eq!(res.header_value("Content-type"), Some("text/html"));
+    assert_eq!(res.body_bytes(), b"xxx");
+  }
+
+  #[test]
+  fn test_read_body_limited() {
+    let read = |raw: &str, max_size| {
+      let mut reader = raw.as_bytes();
+      let mut res = Response::read_head(&mut reader)?;
+      res.read_body_limited(&mut reader, max_size).map(|_| res)
+    };
+
+    let res = read("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello", 5).unwrap();
+    assert_eq!(res.body_bytes(), b"hello");
+    let err = read(
+      "HTTP/1.1 200 OK\r\nContent-Length: 99999999999\r\n\r\n",
+      1024,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = read("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello", 1024).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+    let err = read(&format!("{}1\r\na\r\nffffffffffffffff\r\n", chunked), 1024).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = read(&format!("{}3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n", chunked), 5).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = read("HTTP/1.0 200 OK\r\n\r\nhello world", 5).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+  }
 }
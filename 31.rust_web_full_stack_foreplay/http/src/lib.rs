@@ -1,2 +1,7 @@
+pub mod compression;
+pub mod cookie;
+pub mod form;
+pub mod http_client;
 pub mod http_request;
 pub mod http_response;
+pub mod websocket;
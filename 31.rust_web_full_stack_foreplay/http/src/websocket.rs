@@ -0,0 +1,541 @@
+//! WebSocket (RFC 6455) handshake and framing.
+//!
+//! [`handshake`] checks an upgrade request and builds the `101` response;
+//! [`WebSocket`] then reads and writes messages over the same connection.
+
+use super::http_request::{HttpRequest, Method};
+use super::http_response::Response;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Status codes for [`Message::Close`].
+pub mod close_code {
+  pub const NORMAL: u16 = 1000;
+  pub const GOING_AWAY: u16 = 1001;
+  pub const PROTOCOL_ERROR: u16 = 1002;
+  pub const UNSUPPORTED_DATA: u16 = 1003;
+  pub const INVALID_PAYLOAD: u16 = 1007;
+  pub const POLICY_VIOLATION: u16 = 1008;
+  pub const MESSAGE_TOO_BIG: u16 = 1009;
+  pub const INTERNAL_ERROR: u16 = 1011;
+}
+
+/// The `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+  let mut sha1 = Sha1::new();
+  sha1.update(key.trim().as_bytes());
+  sha1.update(GUID.as_bytes());
+  BASE64.encode(sha1.finalize())
+}
+
+/// Whether `req` asks to switch the connection to WebSocket.
+pub fn is_upgrade_request(req: &HttpRequest) -> bool {
+  let has_token = |name: &str, token: &str| {
+    req
+      .header(name)
+      .map(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+      .unwrap_or(false)
+  };
+  has_token("Connection", "upgrade") && has_token("Upgrade", "websocket")
+}
+
+/// Validates an upgrade request. Returns the `101 Switching Protocols`
+/// response to send before switching to [`WebSocket`], or the error response
+/// to send instead.
+pub fn handshake(req: &HttpRequest) -> Result<Response, Response> {
+  let bad_request = |msg: &str| {
+    Response::new()
+      .status(400)
+      .header("Content-Type", "text/plain")
+      .body(msg.to_string())
+  };
+
+  if req.method != Method::Get || !is_upgrade_request(req) {
+    return Err(bad_request("Not a WebSocket upgrade request"));
+  }
+  if req.header("Sec-WebSocket-Version") != Some("13") {
+    return Err(
+      Response::new()
+        .status(426)
+        .header("Sec-WebSocket-Version", "13")
+        .header("Content-Type", "text/plain")
+        .body("Unsupported WebSocket version"),
+    );
+  }
+  let key = match req.header("Sec-WebSocket-Key") {
+    Some(key) if BASE64.decode(key).map(|k| k.len() == 16).unwrap_or(false) => key,
+    _ => return Err(bad_request("Invalid Sec-WebSocket-Key")),
+  };
+
+  Ok(
+    Response::new()
+      .status(101)
+      .header("Upgrade", "websocket")
+      .header("Connection", "Upgrade")
+      .header("Sec-WebSocket-Accept", accept_key(key)),
+  )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+  Text(String),
+  Binary(Vec<u8>),
+  Ping(Vec<u8>),
+  Pong(Vec<u8>),
+  /// A close code and reason, or `None` for a close frame without a body.
+  Close(Option<(u16, String)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opcode {
+  Continuation,
+  Text,
+  Binary,
+  Close,
+  Ping,
+  Pong,
+}
+
+impl Opcode {
+  fn from_u8(op: u8) -> Option<Opcode> {
+    match op {
+      0x0 => Some(Opcode::Continuation),
+      0x1 => Some(Opcode::Text),
+      0x2 => Some(Opcode::Binary),
+      0x8 => Some(Opcode::Close),
+      0x9 => Some(Opcode::Ping),
+      0xA => Some(Opcode::Pong),
+      _ => None,
+    }
+  }
+
+  fn as_u8(self) -> u8 {
+    match self {
+      Opcode::Continuation => 0x0,
+      Opcode::Text => 0x1,
+      Opcode::Binary => 0x2,
+      Opcode::Close => 0x8,
+      Opcode::Ping => 0x9,
+      Opcode::Pong => 0xA,
+    }
+  }
+
+  fn is_control(self) -> bool {
+    matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+  }
+}
+
+/// Which end of the connection we are. Clients mask every frame they send,
+/// servers never do, and each side rejects frames that break the rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+  Server,
+  Client,
+}
+
+/// A WebSocket connection over any byte stream.
+///
+/// Pings are answered and close frames are echoed automatically; both are
+/// still returned from [`WebSocket::read_message`] so callers can react.
+/// Protocol violations close the connection with the matching code and are
+/// returned as `InvalidData` errors.
+pub struct WebSocket<S: Read + Write> {
+  stream: BufReader<S>,
+  role: Role,
+  max_message_size: usize,
+  /// The opcode and payload of a fragmented message being received.
+  fragments: Option<(Opcode, Vec<u8>)>,
+  close_sent: bool,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+  pub fn new(stream: S, role: Role) -> Self {
+    Self::from_buffered(BufReader::new(stream), role)
+  }
+
+  /// Wraps a reader that may already hold bytes read past the handshake.
+  pub fn from_buffered(stream: BufReader<S>, role: Role) -> Self {
+    WebSocket {
+      stream,
+      role,
+      max_message_size: 16 * 1024 * 1024,
+      fragments: None,
+      close_sent: false,
+    }
+  }
+
+  pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+    self.max_message_size = max_message_size;
+    self
+  }
+
+  pub fn get_ref(&self) -> &S {
+    self.stream.get_ref()
+  }
+
+  pub fn get_mut(&mut self) -> &mut S {
+    self.stream.get_mut()
+  }
+
+  /// Whether we already sent a close frame; nothing else may be sent after.
+  pub fn is_closing(&self) -> bool {
+    self.close_sent
+  }
+
+  /// Waits until data arrives, without consuming any. Returns `Ok(false)` if
+  /// the stream's read timeout expired first, which makes it safe to poll
+  /// with a short timeout and do other work in between.
+  pub fn poll(&mut self) -> io::Result<bool> {
+    match self.stream.fill_buf() {
+      Ok(_) => Ok(true),
+      Err(err)
+        if matches!(
+          err.kind(),
+          io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ) =>
+      {
+        Ok(false)
+      }
+      Err(err) => Err(err),
+    }
+  }
+
+  /// Reads the next complete message, joining fragments.
+  pub fn read_message(&mut self) -> io::Result<Message> {
+    loop {
+      let (fin, opcode, payload) = self.read_frame()?;
+
+      if opcode.is_control() {
+        return self.on_control(opcode, payload);
+      }
+      let (opcode, payload) = match (self.fragments.take(), opcode) {
+        (None, Opcode::Continuation) => {
+          return Err(self.fail(close_code::PROTOCOL_ERROR, "unexpected continuation frame"))
+        }
+        (None, opcode) => (opcode, payload),
+        (Some((first, mut data)), Opcode::Continuation) => {
+          if data.len() + payload.len() > self.max_message_size {
+            return Err(self.fail(close_code::MESSAGE_TOO_BIG, "message too big"));
+          }
+          data.extend_from_slice(&payload);
+          (first, data)
+        }
+        (Some(_), _) => {
+          return Err(self.fail(close_code::PROTOCOL_ERROR, "expected a continuation frame"))
+        }
+      };
+      if !fin {
+        self.fragments = Some((opcode, payload));
+        continue;
+      }
+
+      return match opcode {
+        Opcode::Text => match String::from_utf8(payload) {
+          Ok(text) => Ok(Message::Text(text)),
+          Err(_) => Err(self.fail(close_code::INVALID_PAYLOAD, "text is not valid UTF-8")),
+        },
+        _ => Ok(Message::Binary(payload)),
+      };
+    }
+  }
+
+  fn on_control(&mut self, opcode: Opcode, payload: Vec<u8>) -> io::Result<Message> {
+    match opcode {
+      Opcode::Ping => {
+        if !self.close_sent {
+          self.write_frame(Opcode::Pong, &payload)?;
+        }
+        Ok(Message::Ping(payload))
+      }
+      Opcode::Pong => Ok(Message::Pong(payload)),
+      _ => {
+        let close = match payload.len() {
+          0 => None,
+          1 => return Err(self.fail(close_code::PROTOCOL_ERROR, "invalid close frame")),
+          _ => {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let valid_code = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+            match String::from_utf8(payload[2..].to_vec()) {
+              Ok(reason) if valid_code => Some((code, reason)),
+              _ => return Err(self.fail(close_code::PROTOCOL_ERROR, "invalid close frame")),
+            }
+          }
+        };
+        if !self.close_sent {
+          let code = close.as_ref().map(|(code, _)| *code);
+          self.close_sent = true;
+          self.write_frame(Opcode::Close, &close_payload(code, ""))?;
+        }
+        Ok(Message::Close(close))
+      }
+    }
+  }
+
+  /// Sends `message` as a single frame.
+  pub fn send(&mut self, message: Message) -> io::Result<()> {
+    if self.close_sent {
+      return Err(io::Error::new(
+        io::ErrorKind::NotConnected,
+        "the WebSocket is closing",
+      ));
+    }
+    match message {
+      Message::Text(text) => self.write_frame(Opcode::Text, text.as_bytes()),
+      Message::Binary(data) => self.write_frame(Opcode::Binary, &data),
+      Message::Ping(data) => self.write_frame(Opcode::Ping, &data),
+      Message::Pong(data) => self.write_frame(Opcode::Pong, &data),
+      Message::Close(close) => {
+        self.close_sent = true;
+        let payload = match close {
+          Some((code, reason)) => close_payload(Some(code), &reason),
+          None => Vec::new(),
+        };
+        self.write_frame(Opcode::Close, &payload)
+      }
+    }
+  }
+
+  /// Starts the closing handshake. Keep reading until the peer's
+  /// [`Message::Close`] arrives to finish it.
+  pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+    self.send(Message::Close(Some((code, reason.to_string()))))
+  }
+
+  fn read_frame(&mut self) -> io::Result<(bool, Opcode, Vec<u8>)> {
+    let mut head = [0; 2];
+    self.stream.read_exact(&mut head)?;
+    let fin = head[0] & 0x80 != 0;
+    if head[0] & 0x70 != 0 {
+      return Err(self.fail(close_code::PROTOCOL_ERROR, "reserved bits are set"));
+    }
+    let opcode = match Opcode::from_u8(head[0] & 0x0F) {
+      Some(opcode) => opcode,
+      None => return Err(self.fail(close_code::PROTOCOL_ERROR, "unknown opcode")),
+    };
+    let masked = head[1] & 0x80 != 0;
+    if masked != (self.role == Role::Server) {
+      return Err(self.fail(close_code::PROTOCOL_ERROR, "wrong frame masking"));
+    }
+
+    let len = match head[1] & 0x7F {
+      126 => {
+        let mut len = [0; 2];
+        self.stream.read_exact(&mut len)?;
+        u16::from_be_bytes(len) as u64
+      }
+      127 => {
+        let mut len = [0; 8];
+        self.stream.read_exact(&mut len)?;
+        u64::from_be_bytes(len)
+      }
+      len => len as u64,
+    };
+    if opcode.is_control() && (len > 125 || !fin) {
+      return Err(self.fail(close_code::PROTOCOL_ERROR, "invalid control frame"));
+    }
+    if len > self.max_message_size as u64 {
+      return Err(self.fail(close_code::MESSAGE_TOO_BIG, "message too big"));
+    }
+
+    let mut mask = [0; 4];
+    if masked {
+      self.stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; len as usize];
+    self.stream.read_exact(&mut payload)?;
+    if masked {
+      apply_mask(&mut payload, mask);
+    }
+
+    Ok((fin, opcode, payload))
+  }
+
+  fn write_frame(&mut self, opcode: Opcode, payload: &[u8]) -> io::Result<()> {
+    let mask_bit = match self.role {
+      Role::Client => 0x80,
+      Role::Server => 0,
+    };
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode.as_u8());
+    match payload.len() {
+      len @ 0..=125 => frame.push(mask_bit | len as u8),
+      len @ 126..=0xFFFF => {
+        frame.push(mask_bit | 126);
+        frame.extend_from_slice(&(len as u16).to_be_bytes());
+      }
+      len => {
+        frame.push(mask_bit | 127);
+        frame.extend_from_slice(&(len as u64).to_be_bytes());
+      }
+    }
+    if self.role == Role::Client {
+      let mask = (RandomState::new().build_hasher().finish() as u32).to_be_bytes();
+      frame.extend_from_slice(&mask);
+      let start = frame.len();
+      frame.extend_from_slice(payload);
+      apply_mask(&mut frame[start..], mask);
+    } else {
+      frame.extend_from_slice(payload);
+    }
+
+    let stream = self.stream.get_mut();
+    stream.write_all(&frame)?;
+    stream.flush()
+  }
+
+  /// Sends a close frame with `code`, if we haven't sent one yet, and
+  /// returns the error to report.
+  fn fail(&mut self, code: u16, reason: &str) -> io::Error {
+    if !self.close_sent {
+      self.close_sent = true;
+      let _ = self.write_frame(Opcode::Close, &close_payload(Some(code), reason));
+    }
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+  }
+}
+
+fn close_payload(code: Option<u16>, reason: &str) -> Vec<u8> {
+  match code {
+    Some(code) => {
+      let mut payload = code.to_be_bytes().to_vec();
+      // Control frames are capped at 125 bytes.
+      let mut end = reason.len().min(123);
+      while !reason.is_char_boundary(end) {
+        end -= 1;
+      }
+      payload.extend_from_slice(&reason.as_bytes()[..end]);
+      payload
+    }
+    None => Vec::new(),
+  }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+  for (i, byte) in payload.iter_mut().enumerate() {
+    *byte ^= mask[i % 4];
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+
+  /// A stream that reads from `input` and collects everything written.
+  struct Pipe {
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+  }
+
+  impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      self.input.read(buf)
+    }
+  }
+
+  impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn server(input: Vec<u8>) -> WebSocket<Pipe> {
+    let pipe = Pipe {
+      input: Cursor::new(input),
+      output: Vec::new(),
+    };
+    WebSocket::new(pipe, Role::Server)
+  }
+
+  /// Encodes a frame the way a client would, masked.
+  fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    let mut payload = payload.to_vec();
+    apply_mask(&mut payload, mask);
+    frame.extend(payload);
+    frame
+  }
+
+  #[test]
+  fn test_accept_key() {
+    // The example from RFC 6455, section 1.3.
+    assert_eq!(
+      accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+      "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+
+    let req: HttpRequest = "GET /ws HTTP/1.1\r\nConnection: keep-alive, Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n".to_string().into();
+    let res = handshake(&req).unwrap();
+    assert_eq!(res.status_code(), 101);
+    assert_eq!(
+      res.header_value("Sec-WebSocket-Accept"),
+      Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+  }
+
+  #[test]
+  fn test_fragments_and_ping() {
+    let mut input = client_frame(false, 0x1, b"Hel");
+    input.extend(client_frame(true, 0x9, b"hi"));
+    input.extend(client_frame(true, 0x0, b"lo"));
+    input.extend(client_frame(true, 0x8, &[0x03, 0xE8]));
+    let mut ws = server(input);
+
+    assert_eq!(ws.read_message().unwrap(), Message::Ping(b"hi".to_vec()));
+    assert_eq!(ws.read_message().unwrap(), Message::Text("Hello".into()));
+    assert_eq!(
+      ws.read_message().unwrap(),
+      Message::Close(Some((1000, String::new())))
+    );
+    // The pong and the echoed close, unmasked.
+    assert_eq!(ws.get_ref().output, b"\x8A\x02hi\x88\x02\x03\xE8");
+    assert!(ws.send(Message::Text("late".into())).is_err());
+  }
+
+  #[test]
+  fn test_protocol_errors() {
+    // Unmasked frame from a client.
+    let mut ws = server(b"\x81\x02hi".to_vec());
+    assert_eq!(
+      ws.read_message().unwrap_err().kind(),
+      io::ErrorKind::InvalidData
+    );
+    assert_eq!(&ws.get_ref().output[2..4], &1002u16.to_be_bytes());
+
+    let mut ws = server(client_frame(true, 0x1, b"\xff"));
+    assert!(ws.read_message().is_err());
+    assert_eq!(&ws.get_ref().output[2..4], &1007u16.to_be_bytes());
+
+    let mut ws = server(client_frame(true, 0x2, &[0; 100])).max_message_size(10);
+    assert!(ws.read_message().is_err());
+    assert_eq!(&ws.get_ref().output[2..4], &1009u16.to_be_bytes());
+  }
+
+  #[test]
+  fn test_client_round_trip() {
+    let mut client = WebSocket::new(
+      Pipe {
+        input: Cursor::new(Vec::new()),
+        output: Vec::new(),
+      },
+      Role::Client,
+    );
+    let long = "x".repeat(70_000);
+    client.send(Message::Text(long.clone())).unwrap();
+
+    let mut ws = server(client.get_ref().output.clone());
+    assert_eq!(ws.read_message().unwrap(), Message::Text(long));
+  }
+}
@@ -0,0 +1,194 @@
+use http::compression::identity_etag;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A file from the public directory, kept in memory.
+#[derive(Debug)]
+pub struct Asset {
+  pub bytes: Vec<u8>,
+  pub content_type: &'static str,
+  /// A strong ETag derived from the content, quotes included.
+  pub etag: String,
+  pub modified: SystemTime,
+}
+
+struct Entry {
+  asset: Arc<Asset>,
+  len: u64,
+  checked: Instant,
+}
+
+/// An in-memory cache of the files under a directory.
+///
+/// Files are loaded on first use. A cached file is checked against its
+/// modification time and size at most once per poll interval, and reloaded if
+/// either changed, so edits show up without restarting the server.
+pub struct AssetCache {
+  root: PathBuf,
+  poll_interval: Duration,
+  entries: RwLock<HashMap<PathBuf, Entry>>,
+}
+
+impl AssetCache {
+  pub fn new(root: impl Into<PathBuf>) -> Self {
+    AssetCache {
+      root: root.into(),
+      poll_interval: Duration::from_secs(1),
+      entries: RwLock::new(HashMap::new()),
+    }
+  }
+
+  pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+    self.poll_interval = poll_interval;
+    self
+  }
+
+  /// Returns the file at `path`, relative to the root. Paths that would
+  /// leave the root, such as `../secret`, are never found.
+  pub fn get(&self, path: &str) -> Option<Arc<Asset>> {
+    let path = self.resolve(path)?;
+    {
+      let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+      if let Some(entry) = entries.get(&path) {
+        if entry.checked.elapsed() < self.poll_interval {
+          return Some(Arc::clone(&entry.asset));
+        }
+      }
+    }
+
+    let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+    let meta = match fs::metadata(&path) {
+      Ok(meta) if meta.is_file() => meta,
+      _ => {
+        entries.remove(&path);
+        return None;
+      }
+    };
+    let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    if let Some(entry) = entries.get_mut(&path) {
+      if entry.asset.modified == modified && entry.len == meta.len() {
+        entry.checked = Instant::now();
+        return Some(Arc::clone(&entry.asset));
+      }
+    }
+
+    let bytes = match fs::read(&path) {
+      Ok(bytes) => bytes,
+      Err(_) => {
+        entries.remove(&path);
+        return None;
+      }
+    };
+    let asset = Arc::new(Asset {
+      content_type: content_type(&path),
+      etag: etag(&bytes),
+      bytes,
+      modified,
+    });
+    entries.insert(
+      path,
+      Entry {
+        asset: Arc::clone(&asset),
+        len: meta.len(),
+        checked: Instant::now(),
+      },
+    );
+    Some(asset)
+  }
+
+  fn resolve(&self, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path
+      .components()
+      .all(|c| matches!(c, Component::Normal(_)))
+      .then(|| self.root.join(path))
+  }
+}
+
+fn etag(bytes: &[u8]) -> String {
+  let hash = Sha256::digest(bytes);
+  let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+  format!("\"{}\"", hex)
+}
+
+/// Whether an `If-None-Match` header matches `etag`, using the weak
+/// comparison RFC 9110 asks for. Tags of compressed variants, such as
+/// `"abc-gzip"`, match the identity `"abc"` too.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+  let etag = etag.trim_start_matches("W/");
+  if_none_match
+    .split(',')
+    .map(str::trim)
+    .any(|tag| tag == "*" || identity_etag(tag.trim_start_matches("W/")) == etag)
+}
+
+fn content_type(path: &Path) -> &'static str {
+  let ext = path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .unwrap_or_default()
+    .to_ascii_lowercase();
+  match ext.as_str() {
+    "html" | "htm" => "text/html; charset=utf-8",
+    "css" => "text/css; charset=utf-8",
+    "js" | "mjs" => "text/javascript; charset=utf-8",
+    "json" => "application/json",
+    "txt" => "text/plain; charset=utf-8",
+    "svg" => "image/svg+xml",
+    "ico" => "image/x-icon",
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "webp" => "image/webp",
+    "woff" => "font/woff",
+    "woff2" => "font/woff2",
+    "wasm" => "application/wasm",
+    _ => "application/octet-stream",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+
+  #[test]
+  fn test_reload_and_etag() {
+    let dir = env::temp_dir().join(format!("httpserver-assets-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("favicon.ico"), b"\x00\x01\xff").unwrap();
+    let cache = AssetCache::new(&dir).poll_interval(Duration::ZERO);
+
+    let icon = cache.get("favicon.ico").unwrap();
+    assert_eq!(icon.bytes, b"\x00\x01\xff");
+    assert_eq!(icon.content_type, "image/x-icon");
+    assert!(Arc::ptr_eq(&icon, &cache.get("favicon.ico").unwrap()));
+
+    fs::write(dir.join("favicon.ico"), b"new icon").unwrap();
+    let reloaded = cache.get("favicon.ico").unwrap();
+    assert_eq!(reloaded.bytes, b"new icon");
+    assert_ne!(reloaded.etag, icon.etag);
+
+    fs::remove_file(dir.join("favicon.ico")).unwrap();
+    assert!(cache.get("favicon.ico").is_none());
+    assert!(cache.get("../favicon.ico").is_none());
+    assert!(cache.get("/etc/passwd").is_none());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_etag_matches() {
+    assert!(etag_matches("\"a\", \"b\"", "\"b\""));
+    assert!(etag_matches("W/\"a\"", "\"a\""));
+    assert!(etag_matches("*", "\"a\""));
+    assert!(!etag_matches("\"ab\"", "\"a\""));
+    assert!(etag_matches("\"a-gzip\"", "\"a\""));
+    assert!(etag_matches("W/\"a-deflate\"", "\"a\""));
+    assert!(!etag_matches("\"a-br\"", "\"a\""));
+  }
+}
@@ -0,0 +1,404 @@
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+  Off,
+  Error,
+  Warn,
+  Info,
+  /// Also logs every accepted connection.
+  Debug,
+}
+
+/// Command line options. Every option except `--config` overrides the value
+/// from the configuration file.
+#[derive(Debug, Default, Parser)]
+#[command(
+  name = "httpserver",
+  about = "A small HTTP server for the shipping demo"
+)]
+pub struct Cli {
+  /// TOML configuration file.
+  #[arg(short, long, value_name = "FILE")]
+  pub config: Option<PathBuf>,
+  /// Address to listen on, e.g. `0.0.0.0:3000`. Repeat to listen on several.
+  #[arg(short, long = "listen", value_name = "ADDR")]
+  pub listen: Vec<String>,
+  /// Directory static files are served from.
+  #[arg(long, value_name = "DIR", env = "PUBLIC_PATH")]
+  pub public_dir: Option<PathBuf>,
+  /// Directory `orders.json` is stored in.
+  #[arg(long, value_name = "DIR", env = "DATA_PATH")]
+  pub data_dir: Option<PathBuf>,
+  #[arg(long, value_name = "N")]
+  pub workers: Option<usize>,
+  #[arg(long, value_name = "BYTES")]
+  pub max_request_size: Option<usize>,
+  #[arg(long, value_name = "SECS")]
+  pub read_timeout_secs: Option<u64>,
+  #[arg(long, value_name = "SECS")]
+  pub write_timeout_secs: Option<u64>,
+  #[arg(long, value_enum)]
+  pub log_level: Option<LogLevel>,
+}
+
+/// The server settings, read from a TOML file such as:
+///
+/// ```toml
+/// listen = ["127.0.0.1:3000", "[::1]:3000"]
+/// public_dir = "public"
+/// data_dir = "data"
+/// workers = 8
+/// max_request_size = 1048576
+/// read_timeout_secs = 5
+/// write_timeout_secs = 5
+/// asset_poll_interval_ms = 1000
+/// log_level = "info"
+///
+/// [tls]
+/// listen = ["0.0.0.0:3443"]
+/// redirect_listen = ["0.0.0.0:3080"]
+///
+/// [[tls.certificates]]
+/// cert = "certs/example.com.pem"
+/// key = "certs/example.com-key.pem"
+/// server_names = ["example.com", "*.example.com"]
+///
+/// [[proxy]]
+/// path = "/tutor"
+/// upstreams = ["127.0.0.1:3001", "127.0.0.1:3002"]
+/// strip_prefix = true
+/// health_check_path = "/health"
+/// ```
+///
+/// Missing keys keep their defaults. Relative paths are resolved against the
+/// directory of the file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  pub listen: Vec<String>,
+  pub public_dir: PathBuf,
+  pub data_dir: PathBuf,
+  pub workers: usize,
+  pub max_request_size: usize,
+  pub read_timeout_secs: u64,
+  pub write_timeout_secs: u64,
+  pub asset_poll_interval_ms: u64,
+  pub log_level: LogLevel,
+  pub tls: Option<TlsConfig>,
+  pub proxy: Vec<ProxyConfig>,
+}
+
+/// HTTPS listeners and the certificates they serve.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+  pub listen: Vec<String>,
+  /// Plain HTTP listeners that redirect every request to the first HTTPS
+  /// listener.
+  pub redirect_listen: Vec<String>,
+  /// The certificate is picked by the name the client asks for via SNI. The
+  /// first one is used for clients that send no name or an unknown one.
+  pub certificates: Vec<CertificateConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CertificateConfig {
+  /// PEM file with the certificate chain, leaf first.
+  pub cert: PathBuf,
+  /// PEM file with the private key.
+  pub key: PathBuf,
+  /// Host names to serve this certificate for; `*.example.com` matches one
+  /// level of subdomains.
+  #[serde(default)]
+  pub server_names: Vec<String>,
+}
+
+/// A path prefix whose requests are forwarded to other servers.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+  /// Requests to this path and everything below it are forwarded.
+  pub path: String,
+  /// `host:port` of every upstream; requests are spread round-robin.
+  pub upstreams: Vec<String>,
+  /// Forward `/tutor/courses` as `/courses` instead of the full path.
+  pub strip_prefix: bool,
+  pub connect_timeout_secs: u64,
+  pub timeout_secs: u64,
+  /// How long an upstream that refused a connection is skipped when there is
+  /// no health check path.
+  pub cool_down_secs: u64,
+  /// With a health check path, failed upstreams stay out of rotation until
+  /// they pass the check again.
+  pub health_check_path: Option<String>,
+  pub health_check_interval_secs: u64,
+}
+
+impl Default for ProxyConfig {
+  fn default() -> Self {
+    ProxyConfig {
+      path: String::new(),
+      upstreams: Vec::new(),
+      strip_prefix: false,
+      connect_timeout_secs: 5,
+      timeout_secs: 30,
+      cool_down_secs: 10,
+      health_check_path: None,
+      health_check_interval_secs: 10,
+    }
+  }
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    Config {
+      listen: vec!["localhost:3000".into()],
+      public_dir: manifest_dir.join("public"),
+      data_dir: manifest_dir.join("data"),
+      workers: 8,
+      max_request_size: 1024 * 1024,
+      read_timeout_secs: 5,
+      write_timeout_secs: 5,
+      asset_poll_interval_ms: 1000,
+      log_level: LogLevel::Info,
+      tls: None,
+      proxy: Vec::new(),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+  Read(PathBuf, io::Error),
+  Parse(PathBuf, toml::de::Error),
+  Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConfigError::Read(path, err) => write!(f, "Could not read {}: {}", path.display(), err),
+      ConfigError::Parse(path, err) => write!(f, "Invalid config {}: {}", path.display(), err),
+      ConfigError::Invalid(msg) => write!(f, "Invalid config: {}", msg),
+    }
+  }
+}
+
+impl Config {
+  /// Builds the configuration from the defaults, the file named by
+  /// `--config` and the command line, in that order, and validates it.
+  pub fn load(cli: Cli) -> Result<Config, ConfigError> {
+    let mut config = match &cli.config {
+      Some(path) => Self::from_file(path)?,
+      None => Config::default(),
+    };
+    config.apply(cli);
+    config.validate()?;
+    Ok(config)
+  }
+
+  pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+    let content =
+      fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+    let mut config: Config =
+      toml::from_str(&content).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?;
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    config.public_dir = base.join(&config.public_dir);
+    config.data_dir = base.join(&config.data_dir);
+    for cert in config
+      .tls
+      .iter_mut()
+      .flat_map(|tls| tls.certificates.iter_mut())
+    {
+      cert.cert = base.join(&cert.cert);
+      cert.key = base.join(&cert.key);
+    }
+    Ok(config)
+  }
+
+  fn apply(&mut self, cli: Cli) {
+    if !cli.listen.is_empty() {
+      self.listen = cli.listen;
+    }
+    if let Some(public_dir) = cli.public_dir {
+      self.public_dir = public_dir;
+    }
+    if let Some(data_dir) = cli.data_dir {
+      self.data_dir = data_dir;
+    }
+    if let Some(workers) = cli.workers {
+      self.workers = workers;
+    }
+    if let Some(max_request_size) = cli.max_request_size {
+      self.max_request_size = max_request_size;
+    }
+    if let Some(read_timeout_secs) = cli.read_timeout_secs {
+      self.read_timeout_secs = read_timeout_secs;
+    }
+    if let Some(write_timeout_secs) = cli.write_timeout_secs {
+      self.write_timeout_secs = write_timeout_secs;
+    }
+    if let Some(log_level) = cli.log_level {
+      self.log_level = log_level;
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), ConfigError> {
+    let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+
+    if self.listen.is_empty() && self.tls.is_none() {
+      return invalid("`listen` needs at least one address".into());
+    }
+    if let Some(tls) = &self.tls {
+      if tls.listen.is_empty() {
+        return invalid("`tls.listen` needs at least one address".into());
+      }
+      if tls.certificates.is_empty() {
+        return invalid("`tls.certificates` needs at least one certificate".into());
+      }
+    }
+    let tls = self.tls.clone().unwrap_or_default();
+    let addrs = self
+      .listen
+      .iter()
+      .chain(&tls.listen)
+      .chain(&tls.redirect_listen);
+    for addr in addrs {
+      if addr.to_socket_addrs().is_err() {
+        return invalid(format!("`{}` is not a valid listen address", addr));
+      }
+    }
+    for (key, dir) in [
+      ("public_dir", &self.public_dir),
+      ("data_dir", &self.data_dir),
+    ] {
+      if !dir.is_dir() {
+        return invalid(format!("{} `{}` is not a directory", key, dir.display()));
+      }
+    }
+    if !(1..=1024).contains(&self.workers) {
+      return invalid("`workers` must be between 1 and 1024".into());
+    }
+    if self.max_request_size == 0 {
+      return invalid("`max_request_size` must be greater than 0".into());
+    }
+    if self.read_timeout_secs == 0 || self.write_timeout_secs == 0 {
+      return invalid("timeouts must be at least one second".into());
+    }
+    for proxy in self.proxy.iter() {
+      if !proxy.path.starts_with('/') {
+        return invalid(format!("proxy path `{}` must start with `/`", proxy.path));
+      }
+      if proxy.upstreams.is_empty() {
+        return invalid(format!(
+          "proxy `{}` needs at least one upstream",
+          proxy.path
+        ));
+      }
+      if let Some(addr) = proxy
+        .upstreams
+        .iter()
+        .find(|addr| addr.to_socket_addrs().is_err())
+      {
+        return invalid(format!("`{}` is not a valid upstream address", addr));
+      }
+      if proxy.connect_timeout_secs == 0
+        || proxy.timeout_secs == 0
+        || proxy.health_check_interval_secs == 0
+      {
+        return invalid(format!(
+          "proxy `{}` timeouts must be at least one second",
+          proxy.path
+        ));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// The port redirect listeners send clients to.
+  pub fn https_port(&self) -> Option<u16> {
+    let addr = self.tls.as_ref()?.listen.first()?;
+    Some(addr.to_socket_addrs().ok()?.next()?.port())
+  }
+
+  pub fn read_timeout(&self) -> Duration {
+    Duration::from_secs(self.read_timeout_secs)
+  }
+
+  pub fn write_timeout(&self) -> Duration {
+    Duration::from_secs(self.write_timeout_secs)
+  }
+
+  pub fn asset_poll_interval(&self) -> Duration {
+    Duration::from_millis(self.asset_poll_interval_ms)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+
+  #[test]
+  fn test_file_and_cli_overrides() {
+    let dir = env::temp_dir().join(format!("httpserver-config-{}", process::id()));
+    fs::create_dir_all(dir.join("www")).unwrap();
+    let path = dir.join("httpserver.toml");
+    fs::write(
+      &path,
+      "listen = [\"127.0.0.1:0\"]\npublic_dir = \"www\"\ndata_dir = \".\"\nworkers = 2\nlog_level = \"warn\"\n",
+    )
+    .unwrap();
+
+    let cli = Cli::parse_from([
+      "httpserver",
+      "--config",
+      path.to_str().unwrap(),
+      "--workers",
+      "4",
+      "-l",
+      "127.0.0.1:0",
+      "-l",
+      "[::1]:0",
+    ]);
+    let config = Config::load(cli).unwrap();
+    assert_eq!(config.listen, ["127.0.0.1:0", "[::1]:0"]);
+    assert_eq!(config.public_dir, dir.join("www"));
+    assert_eq!(config.workers, 4);
+    assert_eq!(config.log_level, LogLevel::Warn);
+    assert_eq!(config.read_timeout_secs, 5);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_validation() {
+    let config = Config {
+      workers: 0,
+      ..Config::default()
+    };
+    assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+
+    let config = Config {
+      listen: vec!["nowhere".into()],
+      ..Config::default()
+    };
+    assert!(config.validate().is_err());
+
+    let err = toml::from_str::<Config>("port = 80").unwrap_err();
+    assert!(err.to_string().contains("port"));
+  }
+}
@@ -1,83 +1,351 @@
+use super::assets::{etag_matches, Asset, AssetCache};
+use super::router::{Params, Router};
+use super::sse::{Event, EventSource, EventStream};
+use super::store::{OrderStatus, OrderStore, StoreError};
+use super::websocket::{Session, WebSocketHandler};
+use http::websocket::{close_code, Message};
 use http::{http_request::*, http_response::*};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::env;
-use std::fs;
-
-#[derive(Deserialize, Serialize)]
-pub struct OrderStatus {
-  order_id: i32,
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+pub trait Handler: Send + Sync {
+  fn handle(&self, req: &HttpRequest, params: &Params) -> Response;
+}
+
+impl<F> Handler for F
+where
+  F: Fn(&HttpRequest, &Params) -> Response + Send + Sync,
+{
+  fn handle(&self, req: &HttpRequest, params: &Params) -> Response {
+    self(req, params)
+  }
+}
+
+/// The JSON body of every API error response.
+#[derive(Serialize)]
+struct ApiError {
+  error: String,
+}
+
+fn api_error(status: u16, error: impl Into<String>) -> Response {
+  Response::new().status(status).json(&ApiError {
+    error: error.into(),
+  })
+}
+
+impl From<StoreError> for Response {
+  fn from(err: StoreError) -> Self {
+    match err {
+      StoreError::NotFound(_) => api_error(404, err.to_string()),
+      StoreError::IdsExhausted | StoreError::Io(_) | StoreError::Corrupt(_) => {
+        eprintln!("{}", err);
+        api_error(500, "Internal Server Error")
+      }
+    }
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewOrder {
   order_date: String,
   order_status: String,
 }
 
-pub trait Handler {
-  fn handle(req: &HttpRequest) -> HttpResponse;
-  fn load_file(file_name: &str) -> Option<String> {
-    let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
-    let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-    let path = format!("{}/{}", public_path, file_name);
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OrderChanges {
+  order_date: Option<String>,
+  order_status: Option<String>,
+}
+
+/// A change to the orders, as pushed to `/ws/orders` and `/events/orders`
+/// clients.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum OrderEvent<'a> {
+  Created { order: &'a OrderStatus },
+  Updated { order: &'a OrderStatus },
+  Deleted { order_id: i32 },
+}
 
-    fs::read_to_string(path).ok()
+impl OrderEvent<'_> {
+  fn kind(&self) -> &'static str {
+    match self {
+      OrderEvent::Created { .. } => "created",
+      OrderEvent::Updated { .. } => "updated",
+      OrderEvent::Deleted { .. } => "deleted",
+    }
   }
 }
 
-pub struct APIHandler;
+/// How many past events are kept for event stream clients that reconnect.
+const HISTORY_LEN: usize = 100;
 
-impl APIHandler {
-  pub fn load_json() -> Vec<OrderStatus> {
-    let default_path = format!("{}/data", env!("CARGO_MANIFEST_DIR"));
-    let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-    let path = format!("{}/{}", public_path, "orders.json");
-    let content = fs::read_to_string(path).unwrap();
-    let orders: Vec<OrderStatus> = serde_json::from_str(content.as_str()).unwrap();
+/// Pushes every change made through the API to the connected WebSocket and
+/// event stream clients, so pages don't have to poll.
+#[derive(Default)]
+pub struct OrderFeed {
+  sessions: Mutex<Vec<Session>>,
+  streams: Mutex<FeedStreams>,
+}
+
+/// The event stream clients and the recent events they may have missed.
+#[derive(Default)]
+struct FeedStreams {
+  clients: Vec<EventStream>,
+  history: VecDeque<Event>,
+  next_id: u64,
+}
 
-    orders
+impl OrderFeed {
+  fn publish(&self, event: &OrderEvent) {
+    let text = match serde_json::to_string(event) {
+      Ok(text) => text,
+      Err(_) => return,
+    };
+    {
+      let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+      streams.next_id += 1;
+      let event = Event::new(text.as_str())
+        .id(streams.next_id.to_string())
+        .event(event.kind());
+      if streams.history.len() == HISTORY_LEN {
+        streams.history.pop_front();
+      }
+      streams.history.push_back(event.clone());
+      streams.clients.retain(|stream| stream.send(event.clone()));
+    }
+    let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+    sessions.retain(|session| session.send(Message::Text(text.clone())));
   }
 }
 
-impl Handler for APIHandler {
-  fn handle(req: &HttpRequest) -> HttpResponse {
-    let Resource::Path(s) = &req.resource;
-    let route: Vec<&str> = s.split("/").collect();
+impl EventSource for OrderFeed {
+  /// Replays the events a reconnecting client missed. If they are no longer
+  /// in the history, a `reset` event tells the client to reload the orders.
+  fn on_connect(&self, stream: &EventStream, last_event_id: Option<&str>) {
+    let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(last_id) = last_event_id {
+      let oldest = streams.next_id + 1 - streams.history.len() as u64;
+      match last_id.parse::<u64>() {
+        Ok(last_id) if last_id >= oldest - 1 && last_id <= streams.next_id => {
+          let missed = (streams.next_id - last_id) as usize;
+          let skip = streams.history.len() - missed;
+          for event in streams.history.iter().skip(skip) {
+            stream.send(event.clone());
+          }
+        }
+        _ => {
+          let reset = Event::new("{}")
+            .id(streams.next_id.to_string())
+            .event("reset");
+          stream.send(reset);
+        }
+      }
+    }
+    streams.clients.push(stream.clone());
+  }
 
-    match route[2] {
-      "shipping" if route.len() > 3 && route[3] == "orders" => {
-        let body = serde_json::to_string(&Self::load_json()).unwrap();
-        let mut headers = HashMap::new();
+  fn on_disconnect(&self, stream_id: u64) {
+    let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+    streams.clients.retain(|s| s.id() != stream_id);
+  }
+}
+
+impl WebSocketHandler for OrderFeed {
+  fn on_open(&self, session: &Session) {
+    let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+    sessions.push(session.clone());
+  }
 
-        headers.insert("Content-Type", "application/json");
+  /// The feed only goes one way.
+  fn on_message(&self, session: &Session, _: Message) {
+    session.close(close_code::UNSUPPORTED_DATA, "this feed is read-only");
+  }
 
-        HttpResponse::new("200", Some(headers), Some(body))
-      }
-      _ => HttpResponse::new("404", None, Self::load_file("404.html")),
+  fn on_close(&self, session: &Session) {
+    let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+    sessions.retain(|s| s.id() != session.id());
+  }
+}
+
+/// The `/api/shipping/orders` CRUD endpoints and the `/ws/orders` and
+/// `/events/orders` feeds of changes.
+pub struct APIHandler {
+  store: OrderStore,
+  feed: Arc<OrderFeed>,
+}
+
+impl APIHandler {
+  pub fn new(store: OrderStore) -> Self {
+    APIHandler {
+      store,
+      feed: Arc::new(OrderFeed::default()),
     }
   }
+
+  /// Registers every endpoint on `router`.
+  pub fn register(self, router: &mut Router) {
+    router.websocket("/ws/orders", Arc::clone(&self.feed));
+    router.event_stream("/events/orders", Arc::clone(&self.feed));
+    let api = Arc::new(self);
+    let handler = |f: fn(&APIHandler, &HttpRequest, &Params) -> Result<Response, Response>| {
+      let api = Arc::clone(&api);
+      move |req: &HttpRequest, params: &Params| f(&api, req, params).unwrap_or_else(|res| res)
+    };
+    router
+      .get("/api/shipping/orders", handler(Self::get_orders))
+      .post("/api/shipping/orders", handler(Self::create_order))
+      .get("/api/shipping/orders/:id", handler(Self::get_order))
+      .put("/api/shipping/orders/:id", handler(Self::replace_order))
+      .patch("/api/shipping/orders/:id", handler(Self::update_order))
+      .delete("/api/shipping/orders/:id", handler(Self::delete_order));
+  }
+
+  fn get_orders(&self, _: &HttpRequest, _: &Params) -> Result<Response, Response> {
+    Ok(Response::new().json(&self.store.list()?))
+  }
+
+  fn get_order(&self, _: &HttpRequest, params: &Params) -> Result<Response, Response> {
+    Ok(Response::new().json(&self.store.get(order_id(params)?)?))
+  }
+
+  fn create_order(&self, req: &HttpRequest, _: &Params) -> Result<Response, Response> {
+    let new: NewOrder = json_body(req)?;
+    let order = self.store.create(
+      non_empty("order_date", new.order_date)?,
+      non_empty("order_status", new.order_status)?,
+    )?;
+    self.feed.publish(&OrderEvent::Created { order: &order });
+
+    Ok(
+      Response::new()
+        .status(201)
+        .header(
+          "Location",
+          format!("/api/shipping/orders/{}", order.order_id),
+        )
+        .json(&order),
+    )
+  }
+
+  /// `PUT` replaces the whole order, so both fields are required.
+  fn replace_order(&self, req: &HttpRequest, params: &Params) -> Result<Response, Response> {
+    let id = order_id(params)?;
+    let new: NewOrder = json_body(req)?;
+    let order_date = non_empty("order_date", new.order_date)?;
+    let order_status = non_empty("order_status", new.order_status)?;
+    let order = self.store.update(id, |order| {
+      order.order_date = order_date;
+      order.order_status = order_status;
+    })?;
+    self.feed.publish(&OrderEvent::Updated { order: &order });
+
+    Ok(Response::new().json(&order))
+  }
+
+  /// `PATCH` only changes the fields present in the body.
+  fn update_order(&self, req: &HttpRequest, params: &Params) -> Result<Response, Response> {
+    let id = order_id(params)?;
+    let changes: OrderChanges = json_body(req)?;
+    let order_date = changes
+      .order_date
+      .map(|d| non_empty("order_date", d))
+      .transpose()?;
+    let order_status = changes
+      .order_status
+      .map(|s| non_empty("order_status", s))
+      .transpose()?;
+    let order = self.store.update(id, |order| {
+      if let Some(order_date) = order_date {
+        order.order_date = order_date;
+      }
+      if let Some(order_status) = order_status {
+        order.order_status = order_status;
+      }
+    })?;
+    self.feed.publish(&OrderEvent::Updated { order: &order });
+
+    Ok(Response::new().json(&order))
+  }
+
+  fn delete_order(&self, _: &HttpRequest, params: &Params) -> Result<Response, Response> {
+    let id = order_id(params)?;
+    self.store.delete(id)?;
+    self.feed.publish(&OrderEvent::Deleted { order_id: id });
+    Ok(Response::new().status(204))
+  }
+}
+
+fn order_id(params: &Params) -> Result<i32, Response> {
+  let id = params.get("id").unwrap_or_default();
+  id.parse()
+    .map_err(|_| api_error(400, format!("Invalid order id: {}", id)))
+}
+
+fn json_body<T: DeserializeOwned>(req: &HttpRequest) -> Result<T, Response> {
+  let is_json = req
+    .header("Content-Type")
+    .map(|t| t.to_ascii_lowercase().starts_with("application/json"))
+    .unwrap_or(false);
+  if !is_json {
+    return Err(api_error(415, "Expected an application/json body"));
+  }
+  serde_json::from_slice(&req.msg_body)
+    .map_err(|err| api_error(400, format!("Invalid body: {}", err)))
 }
 
-pub struct StaticHandler;
+fn non_empty(field: &str, value: String) -> Result<String, Response> {
+  if value.trim().is_empty() {
+    return Err(api_error(400, format!("{} must not be empty", field)));
+  }
+  Ok(value)
+}
+
+/// Serves files from the public directory through an [`AssetCache`].
+#[derive(Clone)]
+pub struct StaticHandler {
+  assets: Arc<AssetCache>,
+}
+
+impl StaticHandler {
+  pub fn new(assets: AssetCache) -> Self {
+    StaticHandler {
+      assets: Arc::new(assets),
+    }
+  }
+
+  /// The `404.html` page, for [`Router::not_found`].
+  pub fn not_found(&self) -> impl Handler {
+    let assets = Arc::clone(&self.assets);
+    move |_: &HttpRequest, _: &Params| not_found(assets.get("404.html").as_deref())
+  }
+}
 
 impl Handler for StaticHandler {
-  fn handle(req: &HttpRequest) -> HttpResponse {
-    let Resource::Path(s) = &req.resource;
-    let route: Vec<&str> = s.split("/").collect();
-
-    match route[1] {
-      "" => HttpResponse::new("200", None, Self::load_file("index.html")),
-      path => match Self::load_file(path) {
-        Some(content) => {
-          let mut headers = HashMap::new();
-          if path.ends_with(".css") {
-            headers.insert("Content-Type", "text/css");
-          } else if path.ends_with(".js") {
-            headers.insert("Content-Type", "text/javascript");
-          } else {
-            headers.insert("Content-Type", "text/html");
-          }
-          HttpResponse::new("200", Some(headers), Some(content))
-        }
-        None => HttpResponse::new("404", None, Self::load_file("404.html")),
-      },
+  fn handle(&self, req: &HttpRequest, params: &Params) -> Response {
+    let path = match params.get("path").unwrap_or_default() {
+      "" => "index.html",
+      path => path,
+    };
+    let asset = match self.assets.get(path) {
+      Some(asset) => asset,
+      None => return not_found(self.assets.get("404.html").as_deref()),
+    };
+
+    // `no-cache` still lets browsers keep the file, but makes them revalidate
+    // it, which is cheap thanks to the ETag and picks up edits right away.
+    let res = Response::new()
+      .header("ETag", asset.etag.as_str())
+      .header("Last-Modified", http_date(asset.modified))
+      .header("Cache-Control", "no-cache");
+    match req.header("If-None-Match") {
+      Some(tags) if etag_matches(tags, &asset.etag) => res.status(304),
+      _ => res
+        .header("Content-Type", asset.content_type)
+        .body(asset.bytes.clone()),
     }
   }
 }
@@ -85,7 +353,113 @@ impl Handler for StaticHandler {
 pub struct NotFoundHandler;
 
 impl Handler for NotFoundHandler {
-  fn handle(_: &HttpRequest) -> HttpResponse<'static> {
-    HttpResponse::new("404", None, Self::load_file("404.html"))
+  fn handle(&self, _: &HttpRequest, _: &Params) -> Response {
+    not_found(None)
+  }
+}
+
+/// A `404` response with `page` as its body, or plain text without one.
+fn not_found(page: Option<&Asset>) -> Response {
+  match page {
+    Some(page) => Response::new()
+      .status(404)
+      .header("Content-Type", page.content_type)
+      .body(page.bytes.clone()),
+    None => Response::new()
+      .status(404)
+      .header("Content-Type", "text/plain")
+      .body("Not Found"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+  use std::process;
+  use std::sync::mpsc;
+
+  fn send(router: &Router, method: &str, path: &str, body: &str) -> Response {
+    router.route(
+      &format!(
+        "{} {} HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}",
+        method, path, body
+      )
+      .into(),
+    )
+  }
+
+  #[test]
+  fn test_orders_api() {
+    let dir = env::temp_dir().join(format!("httpserver-api-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut router = Router::new();
+    APIHandler::new(OrderStore::new(dir.join("orders.json"))).register(&mut router);
+
+    let res = send(
+      &router,
+      "POST",
+      "/api/shipping/orders",
+      r#"{"order_date":"2022/11/5","order_status":"New"}"#,
+    );
+    assert_eq!(res.status_code(), 201);
+    assert_eq!(res.header_value("Location"), Some("/api/shipping/orders/0"));
+
+    let res = send(
+      &router,
+      "PATCH",
+      "/api/shipping/orders/0",
+      r#"{"order_status":"Done"}"#,
+    );
+    let order: serde_json::Value = res.parse_json().unwrap();
+    assert_eq!(order["order_status"], "Done");
+    assert_eq!(order["order_date"], "2022/11/5");
+
+    let res = send(
+      &router,
+      "PUT",
+      "/api/shipping/orders/0",
+      r#"{"order_status":"New"}"#,
+    );
+    assert_eq!(res.status_code(), 400);
+    let res = send(&router, "GET", "/api/shipping/orders/abc", "");
+    assert_eq!(res.status_code(), 400);
+
+    assert_eq!(
+      send(&router, "DELETE", "/api/shipping/orders/0", "").status_code(),
+      204
+    );
+    let res = send(&router, "GET", "/api/shipping/orders/0", "");
+    assert_eq!(res.status_code(), 404);
+    let error: serde_json::Value = res.parse_json().unwrap();
+    assert_eq!(error["error"], "Order 0 not found");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_feed_replay() {
+    let feed = OrderFeed::default();
+    let order = OrderStatus {
+      order_id: 1,
+      order_date: "2022/11/5".into(),
+      order_status: "New".into(),
+    };
+    feed.publish(&OrderEvent::Created { order: &order });
+    feed.publish(&OrderEvent::Deleted { order_id: 1 });
+
+    let connect = |last_event_id| {
+      let (sender, events) = mpsc::channel();
+      feed.on_connect(&EventStream::new(sender), Some(last_event_id));
+      events.try_iter().collect::<Vec<_>>()
+    };
+    let missed = connect("1");
+    assert_eq!(missed.len(), 1);
+    assert_eq!(missed[0], feed.streams.lock().unwrap().history[1]);
+    assert!(connect("2").is_empty());
+
+    let reset = Event::new("{}").id("2").event("reset");
+    assert_eq!(connect("18446744073709551615"), [reset]);
+    assert_eq!(connect("oops").len(), 1);
   }
 }
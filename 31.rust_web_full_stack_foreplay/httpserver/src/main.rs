@@ -1,11 +1,99 @@
+mod assets;
+mod config;
 mod handler;
+mod middleware;
+mod pool;
+mod proxy;
 mod router;
 mod server;
+mod sse;
+mod store;
+mod tls;
+mod websocket;
 
+use assets::AssetCache;
+use clap::Parser;
+use config::{Cli, Config, LogLevel, ProxyConfig};
+use handler::{APIHandler, StaticHandler};
+use middleware::{AccessLog, CatchPanic, Cors, RequestId};
+use proxy::Proxy;
+use router::Router;
 use server::Server;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+use store::OrderStore;
+
+fn proxy(config: &ProxyConfig) -> Proxy {
+  let mut proxy = Proxy::new(config.upstreams.iter().cloned())
+    .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+    .timeout(Duration::from_secs(config.timeout_secs))
+    .cool_down(Duration::from_secs(config.cool_down_secs));
+  if config.strip_prefix {
+    proxy = proxy.strip_prefix(&config.path);
+  }
+  if let Some(path) = &config.health_check_path {
+    let interval = Duration::from_secs(config.health_check_interval_secs);
+    proxy = proxy.health_check(path, interval);
+  }
+  proxy
+}
 
 fn main() {
-  let server = Server::new("localhost:3000");
-  println!("Server is running on http://localhost:3000");
-  server.run();
+  let config = match Config::load(Cli::parse()) {
+    Ok(config) => config,
+    Err(err) => {
+      eprintln!("{}", err);
+      process::exit(2);
+    }
+  };
+
+  let assets = AssetCache::new(&config.public_dir).poll_interval(config.asset_poll_interval());
+  let static_handler = StaticHandler::new(assets);
+  let mut router = Router::new();
+  for proxy_config in config.proxy.iter() {
+    let pattern = format!("{}/*path", proxy_config.path.trim_end_matches('/'));
+    router.proxy(&pattern, proxy(proxy_config));
+  }
+  APIHandler::new(OrderStore::new(config.data_dir.join("orders.json"))).register(&mut router);
+  router
+    .get("/*path", static_handler.clone())
+    .not_found(static_handler.not_found())
+    .wrap(RequestId);
+  if config.log_level >= LogLevel::Info {
+    router.wrap(AccessLog);
+  }
+  router
+    .wrap(CatchPanic)
+    .wrap(Cors::new().allow_origin("http://localhost:8080"));
+
+  let mut server = Server::new(router)
+    .workers(config.workers)
+    .read_timeout(config.read_timeout())
+    .write_timeout(config.write_timeout())
+    .max_request_size(config.max_request_size)
+    .log_level(config.log_level);
+  for addr in config.listen.iter() {
+    server = server.listen(addr);
+  }
+  if let Some(tls) = &config.tls {
+    let tls_config = match tls::server_config(&tls.certificates) {
+      Ok(tls_config) => tls_config,
+      Err(err) => {
+        eprintln!("{}", err);
+        process::exit(2);
+      }
+    };
+    for addr in tls.listen.iter() {
+      server = server.listen_tls(addr, Arc::clone(&tls_config));
+    }
+    let https_port = config.https_port().unwrap_or(443);
+    for addr in tls.redirect_listen.iter() {
+      server = server.redirect_to_https(addr, https_port);
+    }
+  }
+  if let Err(err) = server.run() {
+    eprintln!("Server error: {}", err);
+    process::exit(1);
+  }
 }
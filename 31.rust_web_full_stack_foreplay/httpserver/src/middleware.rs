@@ -0,0 +1,296 @@
+use super::router::Router;
+use http::{http_request::*, http_response::Response};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Code that runs around every request.
+///
+/// A middleware gets the request and the rest of the chain as `next`. It can
+/// change the request before calling `next.run(req)`, change the response
+/// afterwards, or answer on its own without calling `next` at all.
+pub trait Middleware: Send + Sync {
+  fn handle(&self, req: HttpRequest, next: Next) -> Response;
+}
+
+impl<F> Middleware for F
+where
+  F: Fn(HttpRequest, Next) -> Response + Send + Sync,
+{
+  fn handle(&self, req: HttpRequest, next: Next) -> Response {
+    self(req, next)
+  }
+}
+
+/// The part of the chain that comes after the current middleware, ending in
+/// the router.
+pub struct Next<'a> {
+  middleware: &'a [Arc<dyn Middleware>],
+  router: &'a Router,
+}
+
+impl<'a> Next<'a> {
+  pub fn new(middleware: &'a [Arc<dyn Middleware>], router: &'a Router) -> Self {
+    Next { middleware, router }
+  }
+
+  pub fn run(self, req: HttpRequest) -> Response {
+    match self.middleware.split_first() {
+      Some((first, rest)) => first.handle(req, Next::new(rest, self.router)),
+      None => self.router.route(&req),
+    }
+  }
+}
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Gives every request an `X-Request-Id`, keeping one the client already
+/// sent, and echoes it on the response so logs on both sides can be matched.
+///
+/// A client id is only kept if it is short and made of `[A-Za-z0-9._-]`, so
+/// it can't smuggle spaces or `key=value` pairs into the access log.
+pub struct RequestId;
+
+fn is_valid_request_id(id: &str) -> bool {
+  !id.is_empty()
+    && id.len() <= 128
+    && id
+      .bytes()
+      .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+impl RequestId {
+  fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let millis = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_millis())
+      .unwrap_or(0);
+    format!(
+      "{:x}-{:x}-{:x}",
+      millis,
+      process::id(),
+      COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+  }
+}
+
+impl Middleware for RequestId {
+  fn handle(&self, mut req: HttpRequest, next: Next) -> Response {
+    let id = match req.header(REQUEST_ID_HEADER) {
+      Some(id) if is_valid_request_id(id) => id.to_string(),
+      _ => Self::generate(),
+    };
+    req
+      .headers
+      .retain(|k, _| !k.eq_ignore_ascii_case(REQUEST_ID_HEADER));
+    req.headers.insert(REQUEST_ID_HEADER.into(), id.clone());
+
+    next.run(req).set_header(REQUEST_ID_HEADER, id)
+  }
+}
+
+/// Writes one `key=value` line per request to stdout with the method, path,
+/// status, body size and latency.
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+  fn handle(&self, req: HttpRequest, next: Next) -> Response {
+    let started = Instant::now();
+    let method = req.method;
+    let Resource::Path(path) = &req.resource;
+    let path = path.clone();
+    let request_id = req
+      .header(REQUEST_ID_HEADER)
+      .filter(|id| is_valid_request_id(id))
+      .unwrap_or("-")
+      .to_string();
+
+    let res = next.run(req);
+    println!(
+      "method={} path={:?} status={} bytes={} latency_ms={:.3} request_id={}",
+      method.as_str(),
+      path,
+      res.status_code(),
+      res.body_bytes().len(),
+      started.elapsed().as_secs_f64() * 1000.0,
+      request_id
+    );
+    res
+  }
+}
+
+/// Turns a panic anywhere further down the chain into a `500` instead of a
+/// dropped connection.
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+  fn handle(&self, req: HttpRequest, next: Next) -> Response {
+    match panic::catch_unwind(AssertUnwindSafe(move || next.run(req))) {
+      Ok(res) => res,
+      Err(_) => Response::new()
+        .status(500)
+        .header("Content-Type", "text/plain")
+        .body("Internal Server Error"),
+    }
+  }
+}
+
+/// Answers CORS preflight requests and adds `Access-Control-Allow-Origin` to
+/// responses for allowed origins.
+pub struct Cors {
+  allowed_origins: Vec<String>,
+  allowed_methods: Vec<Method>,
+  allowed_headers: Vec<String>,
+  max_age: u32,
+}
+
+impl Default for Cors {
+  fn default() -> Self {
+    Cors {
+      allowed_origins: Vec::new(),
+      allowed_methods: vec![
+        Method::Get,
+        Method::Post,
+        Method::Put,
+        Method::Patch,
+        Method::Delete,
+      ],
+      allowed_headers: vec!["Content-Type".into(), "Authorization".into()],
+      max_age: 3600,
+    }
+  }
+}
+
+impl Cors {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Allows requests from `origin`, e.g. `http://localhost:8080`. `*` allows
+  /// every origin.
+  pub fn allow_origin(mut self, origin: &str) -> Self {
+    self.allowed_origins.push(origin.into());
+    self
+  }
+
+  fn is_allowed(&self, origin: &str) -> bool {
+    self
+      .allowed_origins
+      .iter()
+      .any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+  }
+}
+
+impl Middleware for Cors {
+  fn handle(&self, req: HttpRequest, next: Next) -> Response {
+    let origin = match req.header("Origin") {
+      Some(origin) if self.is_allowed(origin) => origin.to_string(),
+      // Not a cross-origin request, or one we don't allow: the browser
+      // enforces the block when the CORS headers are missing.
+      _ => return next.run(req),
+    };
+
+    if req.method == Method::Options && req.header("Access-Control-Request-Method").is_some() {
+      let methods: Vec<&str> = self.allowed_methods.iter().map(|m| m.as_str()).collect();
+      return Response::new()
+        .status(204)
+        .header("Access-Control-Allow-Origin", origin)
+        .header("Access-Control-Allow-Methods", methods.join(", "))
+        .header(
+          "Access-Control-Allow-Headers",
+          self.allowed_headers.join(", "),
+        )
+        .header("Access-Control-Max-Age", self.max_age.to_string())
+        .header("Vary", "Origin");
+    }
+
+    next
+      .run(req)
+      .set_header("Access-Control-Allow-Origin", origin)
+      .header("Vary", "Origin")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::router::Params;
+
+  fn router() -> Router {
+    let mut router = Router::new();
+    router
+      .get("/", |req: &HttpRequest, _: &Params| {
+        Response::new().body(
+          req
+            .header(REQUEST_ID_HEADER)
+            .unwrap_or_default()
+            .to_string(),
+        )
+      })
+      .get("/panic", |_: &HttpRequest, _: &Params| -> Response {
+        panic!("handler failed")
+      });
+    router
+  }
+
+  fn run(middleware: Vec<Arc<dyn Middleware>>, req: &str) -> Response {
+    let router = router();
+    Next::new(&middleware, &router).run(req.to_string().into())
+  }
+
+  #[test]
+  fn test_request_id() {
+    let res = run(vec![Arc::new(RequestId)], "GET / HTTP/1.1\r\n\r\n");
+    let id = res.header_value(REQUEST_ID_HEADER).unwrap();
+    assert!(!id.is_empty());
+    assert_eq!(res.body_bytes(), id.as_bytes());
+
+    let res = run(
+      vec![Arc::new(RequestId)],
+      "GET / HTTP/1.1\r\nx-request-id: abc\r\n\r\n",
+    );
+    assert_eq!(res.header_value(REQUEST_ID_HEADER), Some("abc"));
+    assert_eq!(res.body_bytes(), b"abc");
+
+    let res = run(
+      vec![Arc::new(RequestId)],
+      "GET / HTTP/1.1\r\nx-request-id: abc status=500\r\n\r\n",
+    );
+    let id = res.header_value(REQUEST_ID_HEADER).unwrap();
+    assert_ne!(id, "abc status=500");
+    assert!(is_valid_request_id(id));
+  }
+
+  #[test]
+  fn test_catch_panic() {
+    let res = run(
+      vec![Arc::new(CatchPanic), Arc::new(AccessLog)],
+      "GET /panic HTTP/1.1\r\n\r\n",
+    );
+    assert_eq!(res.status_code(), 500);
+  }
+
+  #[test]
+  fn test_cors() {
+    let cors = || -> Vec<Arc<dyn Middleware>> {
+      vec![Arc::new(Cors::new().allow_origin("http://localhost:8080"))]
+    };
+
+    let res = run(
+      cors(),
+      "OPTIONS / HTTP/1.1\r\nOrigin: http://localhost:8080\r\nAccess-Control-Request-Method: PUT\r\n\r\n",
+    );
+    assert_eq!(res.status_code(), 204);
+    assert_eq!(
+      res.header_value("Access-Control-Allow-Origin"),
+      Some("http://localhost:8080")
+    );
+
+    let res = run(cors(), "GET / HTTP/1.1\r\nOrigin: http://evil.com\r\n\r\n");
+    assert_eq!(res.status_code(), 200);
+    assert_eq!(res.header_value("Access-Control-Allow-Origin"), None);
+  }
+}
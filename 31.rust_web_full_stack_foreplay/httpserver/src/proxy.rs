@@ -0,0 +1,443 @@
+use http::http_request::{HttpRequest, Method, Resource};
+use http::http_response::Response;
+use std::fmt::Write as _;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Headers that only apply to one connection and are never forwarded.
+const HOP_BY_HOP: [&str; 8] = [
+  "Connection",
+  "Keep-Alive",
+  "Proxy-Authenticate",
+  "Proxy-Authorization",
+  "Proxy-Connection",
+  "TE",
+  "Trailer",
+  "Upgrade",
+];
+
+/// Where a proxied request came from, for the `X-Forwarded-*` headers.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientInfo {
+  pub addr: Option<IpAddr>,
+  /// `http` or `https`.
+  pub proto: &'static str,
+}
+
+struct Upstream {
+  addr: String,
+  /// When the upstream last failed, while it is out of rotation.
+  down_since: Mutex<Option<Instant>>,
+}
+
+/// Forwards requests to a set of upstream servers, registered with
+/// [`Router::proxy`](super::router::Router::proxy).
+///
+/// Upstreams are picked round-robin. An upstream that refuses connections is
+/// skipped for the `cool_down` period and then tried again. With a health
+/// check configured, failed upstreams are instead skipped until they pass the
+/// check again. Bodies are streamed in both directions, so they are not
+/// limited by the server's `max_request_size`.
+///
+/// ```ignore
+/// let tutor = Proxy::new(["127.0.0.1:3001", "127.0.0.1:3002"])
+///   .strip_prefix("/tutor")
+///   .health_check("/health", Duration::from_secs(10));
+/// router.proxy("/tutor/*path", tutor);
+/// ```
+pub struct Proxy {
+  upstreams: Vec<Upstream>,
+  next: AtomicUsize,
+  strip_prefix: Option<String>,
+  connect_timeout: Duration,
+  timeout: Duration,
+  cool_down: Duration,
+  health_check: Option<(String, Duration)>,
+}
+
+impl Proxy {
+  pub fn new<S: Into<String>>(upstreams: impl IntoIterator<Item = S>) -> Self {
+    Proxy {
+      upstreams: upstreams
+        .into_iter()
+        .map(|addr| Upstream {
+          addr: addr.into(),
+          down_since: Mutex::new(None),
+        })
+        .collect(),
+      next: AtomicUsize::new(0),
+      strip_prefix: None,
+      connect_timeout: Duration::from_secs(5),
+      timeout: Duration::from_secs(30),
+      cool_down: Duration::from_secs(10),
+      health_check: None,
+    }
+  }
+
+  /// Removes `prefix` from the path before forwarding, so `/tutor/courses`
+  /// reaches the upstream as `/courses`.
+  pub fn strip_prefix(mut self, prefix: &str) -> Self {
+    self.strip_prefix = Some(prefix.trim_end_matches('/').into());
+    self
+  }
+
+  pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+    self.connect_timeout = timeout;
+    self
+  }
+
+  /// How long to wait for the upstream to accept or send data. A response
+  /// that stalls for longer is cut off.
+  pub fn timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// How long an upstream that refused a connection is skipped when there is
+  /// no health check to bring it back.
+  pub fn cool_down(mut self, cool_down: Duration) -> Self {
+    self.cool_down = cool_down;
+    self
+  }
+
+  /// Sends `GET path` to every upstream each `interval`. Upstreams that don't
+  /// answer with a `2xx` or `3xx` status are taken out of rotation.
+  pub fn health_check(mut self, path: &str, interval: Duration) -> Self {
+    self.health_check = Some((path.into(), interval));
+    self
+  }
+
+  /// Starts the health check thread, if any. It stops once the proxy is
+  /// dropped.
+  pub fn spawn_health_checks(proxy: &Arc<Proxy>) {
+    let Some((_, interval)) = &proxy.health_check else {
+      return;
+    };
+    let interval = *interval;
+    let proxy = Arc::downgrade(proxy);
+    thread::spawn(move || loop {
+      thread::sleep(interval);
+      match Weak::upgrade(&proxy) {
+        Some(proxy) => proxy.check_upstreams(),
+        None => break,
+      }
+    });
+  }
+
+  fn check_upstreams(&self) {
+    let Some((path, _)) = &self.health_check else {
+      return;
+    };
+    for upstream in self.upstreams.iter() {
+      let result = self.connect(&upstream.addr).and_then(|mut stream| {
+        write!(
+          stream,
+          "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+          path, upstream.addr
+        )?;
+        Response::read_head(&mut BufReader::new(stream))
+      });
+      match result {
+        Ok(res) if (200..400).contains(&res.status_code()) => self.mark(upstream, true, ""),
+        Ok(res) => self.mark(upstream, false, &format!("status {}", res.status_code())),
+        Err(err) => self.mark(upstream, false, &err.to_string()),
+      }
+    }
+  }
+
+  fn mark(&self, upstream: &Upstream, healthy: bool, reason: &str) {
+    let mut down_since = upstream
+      .down_since
+      .lock()
+      .unwrap_or_else(|e| e.into_inner());
+    let was_down = down_since.is_some();
+    *down_since = (!healthy).then(Instant::now);
+    match (was_down, healthy) {
+      (true, true) => eprintln!("Upstream {} is back up", upstream.addr),
+      (false, false) => eprintln!("Upstream {} is down: {}", upstream.addr, reason),
+      _ => {}
+    }
+  }
+
+  /// Whether `upstream` is in rotation. Without a health check, a failed
+  /// upstream gets another try once it has cooled down.
+  fn is_available(&self, upstream: &Upstream) -> bool {
+    let down_since = upstream
+      .down_since
+      .lock()
+      .unwrap_or_else(|e| e.into_inner());
+    down_since.is_none_or(|since| self.health_check.is_none() && since.elapsed() >= self.cool_down)
+  }
+
+  /// The upstreams in rotation, starting with the next one in turn.
+  fn candidates(&self) -> impl Iterator<Item = &Upstream> {
+    let start = self.next.fetch_add(1, Ordering::Relaxed);
+    let len = self.upstreams.len();
+    (0..len)
+      .map(move |i| &self.upstreams[(start + i) % len])
+      .filter(|upstream| self.is_available(upstream))
+  }
+
+  fn connect(&self, addr: &str) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+      match TcpStream::connect_timeout(&addr, self.connect_timeout) {
+        Ok(stream) => {
+          stream.set_read_timeout(Some(self.timeout))?;
+          stream.set_write_timeout(Some(self.timeout))?;
+          return Ok(stream);
+        }
+        Err(err) => last_err = Some(err),
+      }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::other("address resolved to nothing")))
+  }
+
+  /// Forwards `req`, whose body is still unread in `client`, and streams the
+  /// response back. Returns whether the client connection can be kept open.
+  pub fn forward<C: Read + Write>(
+    &self,
+    req: HttpRequest,
+    client: &mut BufReader<C>,
+    info: ClientInfo,
+    keep_alive: bool,
+  ) -> io::Result<bool> {
+    let mut upstream = None;
+    let mut failed = false;
+    for candidate in self.candidates() {
+      match self.connect(&candidate.addr) {
+        Ok(stream) => {
+          if self.health_check.is_none() {
+            self.mark(candidate, true, "");
+          }
+          upstream = Some(stream);
+          break;
+        }
+        Err(err) => {
+          failed = true;
+          self.mark(candidate, false, &err.to_string());
+        }
+      }
+    }
+    let Some(mut upstream) = upstream else {
+      let status = if failed { 502 } else { 503 };
+      error(status, "No upstream available").write_to(client.get_mut())?;
+      return Ok(false);
+    };
+
+    let content_length = req
+      .header("Content-Length")
+      .and_then(|len| len.parse::<u64>().ok())
+      .unwrap_or(0);
+    let sent = upstream
+      .write_all(self.request_head(&req, info).as_bytes())
+      .and_then(|_| io::copy(&mut client.by_ref().take(content_length), &mut upstream));
+    match sent {
+      Ok(copied) if copied == content_length => {}
+      // The client went away in the middle of its body.
+      Ok(_) => return Ok(false),
+      Err(err) => {
+        gateway_error(&err).write_to(client.get_mut())?;
+        return Ok(false);
+      }
+    }
+
+    let mut upstream = BufReader::new(upstream);
+    let head = match Response::read_head(&mut upstream) {
+      Ok(head) => head,
+      Err(err) => {
+        gateway_error(&err).write_to(client.get_mut())?;
+        return Ok(false);
+      }
+    };
+
+    let has_body = head.has_body() && req.method != Method::Head;
+    let content_length = head
+      .header_value("Content-Length")
+      .and_then(|len| len.parse::<u64>().ok());
+    // Chunked bodies are relayed as they are. The upstream closes the
+    // connection after the last chunk, since we asked it to.
+    let chunked = head
+      .header_value("Transfer-Encoding")
+      .map(|te| te.to_ascii_lowercase().contains("chunked"))
+      .unwrap_or(false);
+    let keep_alive = keep_alive && (!has_body || content_length.is_some() || chunked);
+
+    let mut res = Response::new().status(head.status_code());
+    for (k, v) in head.headers() {
+      if !is_hop_by_hop(k, head.header_value("Connection")) {
+        res = res.header(k.as_str(), v.as_str());
+      }
+    }
+    res
+      .header(
+        "Connection",
+        if keep_alive { "keep-alive" } else { "close" },
+      )
+      .write_head(client.get_mut())?;
+    if has_body {
+      match content_length {
+        Some(len) if !chunked => io::copy(&mut upstream.take(len), client.get_mut())?,
+        _ => io::copy(&mut upstream, client.get_mut())?,
+      };
+    }
+    client.get_mut().flush()?;
+
+    Ok(keep_alive)
+  }
+
+  /// The path the upstream sees, with the prefix stripped if configured.
+  fn upstream_path(&self, path: &str) -> String {
+    match self
+      .strip_prefix
+      .as_deref()
+      .and_then(|p| path.strip_prefix(p))
+    {
+      Some(rest) if rest.starts_with('/') => rest.into(),
+      Some(rest) if rest.is_empty() || rest.starts_with('?') => format!("/{}", rest),
+      _ => path.into(),
+    }
+  }
+
+  fn request_head(&self, req: &HttpRequest, info: ClientInfo) -> String {
+    let Resource::Path(path) = &req.resource;
+    let path = self.upstream_path(path);
+    let mut head = format!("{} {} HTTP/1.1\r\n", req.method.as_str(), path);
+    let connection = req.header("Connection");
+    for (k, v) in req.headers.iter() {
+      let forwarded = k.eq_ignore_ascii_case("X-Forwarded-For")
+        || k.eq_ignore_ascii_case("X-Forwarded-Proto")
+        || k.eq_ignore_ascii_case("X-Forwarded-Host");
+      if !forwarded && !is_hop_by_hop(k, connection) {
+        let _ = write!(head, "{}: {}\r\n", k, v.trim());
+      }
+    }
+
+    let forwarded_for = match (req.header("X-Forwarded-For"), info.addr) {
+      (Some(prev), Some(addr)) => Some(format!("{}, {}", prev, addr)),
+      (Some(prev), None) => Some(prev.to_string()),
+      (None, addr) => addr.map(|addr| addr.to_string()),
+    };
+    if let Some(forwarded_for) = forwarded_for {
+      let _ = write!(head, "X-Forwarded-For: {}\r\n", forwarded_for);
+    }
+    let _ = write!(head, "X-Forwarded-Proto: {}\r\n", info.proto);
+    if let Some(host) = req.header("Host") {
+      let _ = write!(head, "X-Forwarded-Host: {}\r\n", host);
+    }
+    head.push_str("Connection: close\r\n\r\n");
+    head
+  }
+}
+
+/// Whether `name` is a hop-by-hop header, either a standard one or one listed
+/// in the `Connection` header.
+fn is_hop_by_hop(name: &str, connection: Option<&str>) -> bool {
+  HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+    || connection
+      .unwrap_or_default()
+      .split(',')
+      .any(|h| h.trim().eq_ignore_ascii_case(name))
+}
+
+fn gateway_error(err: &io::Error) -> Response {
+  eprintln!("Proxy error: {}", err);
+  match err.kind() {
+    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => error(504, "Upstream timed out"),
+    _ => error(502, "Bad gateway"),
+  }
+}
+
+fn error(status: u16, msg: &str) -> Response {
+  Response::new()
+    .status(status)
+    .header("Content-Type", "text/plain")
+    .header("Connection", "close")
+    .body(msg)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Cursor;
+  use std::net::{Ipv4Addr, TcpListener};
+
+  fn request(raw: &str) -> HttpRequest {
+    raw.to_string().into()
+  }
+
+  /// An address nothing listens on, so connecting fails right away.
+  fn dead_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+  }
+
+  #[test]
+  fn test_upstream_path() {
+    let proxy = Proxy::new(["127.0.0.1:3001"]).strip_prefix("/tutor/");
+    assert_eq!(proxy.upstream_path("/tutor/courses"), "/courses");
+    assert_eq!(proxy.upstream_path("/tutor"), "/");
+    assert_eq!(proxy.upstream_path("/tutor?page=2"), "/?page=2");
+    assert_eq!(proxy.upstream_path("/tutorial"), "/tutorial");
+    assert_eq!(proxy.upstream_path("/other"), "/other");
+
+    let proxy = Proxy::new(["127.0.0.1:3001"]);
+    assert_eq!(proxy.upstream_path("/tutor/courses"), "/tutor/courses");
+  }
+
+  #[test]
+  fn test_is_hop_by_hop() {
+    assert!(is_hop_by_hop("keep-alive", None));
+    assert!(is_hop_by_hop("X-Secret", Some("close, x-secret")));
+    assert!(!is_hop_by_hop("Content-Type", Some("close, X-Secret")));
+  }
+
+  #[test]
+  fn test_request_head() {
+    let proxy = Proxy::new(["127.0.0.1:3001"]).strip_prefix("/tutor");
+    let req = request(
+      "GET /tutor/courses HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\nX-Forwarded-For: 10.0.0.1\r\nAccept: */*\r\n\r\n",
+    );
+    let info = ClientInfo {
+      addr: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+      proto: "https",
+    };
+    let head = proxy.request_head(&req, info);
+
+    assert!(head.starts_with("GET /courses HTTP/1.1\r\n"));
+    assert!(head.contains("Accept: */*\r\n"));
+    assert!(!head.contains("X-Secret"));
+    assert!(!head.contains("keep-alive"));
+    assert!(head.contains("X-Forwarded-For: 10.0.0.1, 127.0.0.1\r\n"));
+    assert!(head.contains("X-Forwarded-Proto: https\r\n"));
+    assert!(head.contains("X-Forwarded-Host: example.com\r\n"));
+    assert!(head.ends_with("Connection: close\r\n\r\n"));
+  }
+
+  #[test]
+  fn test_dead_upstream_cools_down() {
+    let proxy = Proxy::new([dead_addr()]).cool_down(Duration::from_millis(200));
+    let info = ClientInfo {
+      addr: None,
+      proto: "http",
+    };
+    let status = || {
+      let mut client = BufReader::new(Cursor::new(Vec::new()));
+      let req = request("GET / HTTP/1.1\r\n\r\n");
+      assert!(!proxy.forward(req, &mut client, info, true).unwrap());
+      let out = client.into_inner().into_inner();
+      Response::read_from(&mut &out[..]).unwrap().status_code()
+    };
+
+    // A refused connection is a bad gateway; after that the upstream is
+    // skipped, so there is none to try.
+    assert_eq!(status(), 502);
+    assert_eq!(status(), 503);
+    thread::sleep(Duration::from_millis(250));
+    assert_eq!(status(), 502);
+  }
+}
@@ -1,31 +1,373 @@
-use super::handler::{APIHandler, Handler, NotFoundHandler, StaticHandler};
-use http::http_request::*;
-use std::net::TcpStream;
+use super::handler::{Handler, NotFoundHandler};
+use super::middleware::{Middleware, Next};
+use super::proxy::Proxy;
+use super::sse::EventSource;
+use super::websocket::WebSocketHandler;
+use http::{http_request::*, http_response::Response};
+use std::sync::Arc;
 
-pub struct Router {}
+/// Values captured from the path by `:name` and `*name` segments.
+#[derive(Debug, Default, PartialEq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .0
+      .iter()
+      .find(|(k, _)| k == name)
+      .map(|(_, v)| v.as_str())
+  }
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+  Static(String),
+  Param(String),
+  /// Matches the rest of the path, including an empty rest.
+  Wildcard(String),
+}
+
+struct Route {
+  method: Method,
+  segments: Vec<Segment>,
+  handler: Arc<dyn Handler>,
+}
+
+/// A route whose handler takes over the connection instead of returning a
+/// response.
+struct Endpoint<H: ?Sized> {
+  segments: Vec<Segment>,
+  handler: Arc<H>,
+}
+
+fn find_endpoint<H: ?Sized>(endpoints: &[Endpoint<H>], req: &HttpRequest) -> Option<Arc<H>> {
+  let Resource::Path(path) = &req.resource;
+  let path = path.split(['?', '#']).next().unwrap_or_default();
+  let segments = split_path(path);
+  endpoints
+    .iter()
+    .find(|endpoint| match_segments(&endpoint.segments, &segments).is_some())
+    .map(|endpoint| Arc::clone(&endpoint.handler))
+}
+
+/// Matches `path` against a parsed pattern and collects its params.
+fn match_segments(segments: &[Segment], path: &[&str]) -> Option<Params> {
+  let mut params = Vec::new();
+  for (i, segment) in segments.iter().enumerate() {
+    match segment {
+      Segment::Wildcard(name) => {
+        params.push((name.clone(), path.get(i..).unwrap_or_default().join("/")));
+        return Some(Params(params));
+      }
+      Segment::Static(s) if path.get(i) == Some(&s.as_str()) => {}
+      Segment::Param(name) => match path.get(i) {
+        Some(value) if !value.is_empty() => params.push((name.clone(), value.to_string())),
+        _ => return None,
+      },
+      _ => return None,
+    }
+  }
+
+  (path.len() == segments.len()).then_some(Params(params))
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+  split_path(pattern)
+    .into_iter()
+    .map(|s| {
+      if let Some(name) = s.strip_prefix(':') {
+        Segment::Param(name.into())
+      } else if let Some(name) = s.strip_prefix('*') {
+        Segment::Wildcard(name.into())
+      } else {
+        Segment::Static(s.into())
+      }
+    })
+    .collect()
+}
+
+/// Dispatches requests to handlers registered per method and path pattern.
+///
+/// Patterns are made of `/`-separated segments: plain text matches itself,
+/// `:name` matches any single segment and `*name` matches the rest of the
+/// path. Routes are tried in the order they were registered.
+///
+/// ```ignore
+/// let mut router = Router::new();
+/// router.get("/api/shipping/orders/:id", get_order);
+/// router.get("/*path", StaticHandler);
+/// ```
+pub struct Router {
+  routes: Vec<Route>,
+  websockets: Vec<Endpoint<dyn WebSocketHandler>>,
+  event_streams: Vec<Endpoint<dyn EventSource>>,
+  proxies: Vec<Endpoint<Proxy>>,
+  not_found: Arc<dyn Handler>,
+  middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Default for Router {
+  fn default() -> Self {
+    Router {
+      routes: Vec::new(),
+      websockets: Vec::new(),
+      event_streams: Vec::new(),
+      proxies: Vec::new(),
+      not_found: Arc::new(NotFoundHandler),
+      middleware: Vec::new(),
+    }
+  }
+}
 
 impl Router {
-  pub fn route(req: HttpRequest, stream: &mut TcpStream) {
-    match req.method {
-      Method::Get => match &req.resource {
-        Resource::Path(s) => {
-          let route: Vec<&str> = s.split("/").collect();
-          match route[1] {
-            "api" => {
-              let res = APIHandler::handle(&req);
-              let _ = res.send_response(stream);
-            }
-            _ => {
-              let res = StaticHandler::handle(&req);
-              let _ = res.send_response(stream);
-            }
-          }
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn get(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+    self.add(Method::Get, pattern, handler)
+  }
+
+  pub fn post(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+    self.add(Method::Post, pattern, handler)
+  }
+
+  pub fn put(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+    self.add(Method::Put, pattern, handler)
+  }
+
+  pub fn patch(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+    self.add(Method::Patch, pattern, handler)
+  }
+
+  pub fn delete(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+    self.add(Method::Delete, pattern, handler)
+  }
+
+  pub fn add(
+    &mut self,
+    method: Method,
+    pattern: &str,
+    handler: impl Handler + 'static,
+  ) -> &mut Self {
+    self.routes.push(Route {
+      method,
+      segments: parse_pattern(pattern),
+      handler: Arc::new(handler),
+    });
+    self
+  }
+
+  /// Accepts WebSocket upgrade requests on `pattern`. Upgrades bypass the
+  /// middleware; plain requests to the same path go to the regular routes.
+  pub fn websocket(
+    &mut self,
+    pattern: &str,
+    handler: impl WebSocketHandler + 'static,
+  ) -> &mut Self {
+    self.websockets.push(Endpoint {
+      segments: parse_pattern(pattern),
+      handler: Arc::new(handler),
+    });
+    self
+  }
+
+  /// Finds the WebSocket handler for an upgrade request.
+  pub fn websocket_route(&self, req: &HttpRequest) -> Option<Arc<dyn WebSocketHandler>> {
+    find_endpoint(&self.websockets, req)
+  }
+
+  /// Streams Server-Sent Events from `source` to `GET` requests on
+  /// `pattern`. Like WebSocket upgrades, these bypass the middleware.
+  pub fn event_stream(&mut self, pattern: &str, source: impl EventSource + 'static) -> &mut Self {
+    self.event_streams.push(Endpoint {
+      segments: parse_pattern(pattern),
+      handler: Arc::new(source),
+    });
+    self
+  }
+
+  /// Finds the event source for a `GET` request.
+  pub fn event_stream_route(&self, req: &HttpRequest) -> Option<Arc<dyn EventSource>> {
+    if req.method != Method::Get {
+      return None;
+    }
+    find_endpoint(&self.event_streams, req)
+  }
+
+  /// Forwards every request on `pattern` to `proxy`'s upstreams. Proxied
+  /// requests bypass the middleware and are matched before any other route.
+  pub fn proxy(&mut self, pattern: &str, proxy: Proxy) -> &mut Self {
+    let proxy = Arc::new(proxy);
+    Proxy::spawn_health_checks(&proxy);
+    self.proxies.push(Endpoint {
+      segments: parse_pattern(pattern),
+      handler: proxy,
+    });
+    self
+  }
+
+  /// Finds the proxy for a request.
+  pub fn proxy_route(&self, req: &HttpRequest) -> Option<Arc<Proxy>> {
+    find_endpoint(&self.proxies, req)
+  }
+
+  /// Replaces the handler for requests no route matches.
+  pub fn not_found(&mut self, handler: impl Handler + 'static) -> &mut Self {
+    self.not_found = Arc::new(handler);
+    self
+  }
+
+  /// Runs `middleware` around every request. Middleware added first is the
+  /// outermost, so it sees the request first and the response last.
+  pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+    self.middleware.push(Arc::new(middleware));
+    self
+  }
+
+  /// Passes `req` through the middleware chain and then to its route.
+  pub fn handle(&self, req: HttpRequest) -> Response {
+    Next::new(&self.middleware, self).run(req)
+  }
+
+  /// Finds the handler for `req` and runs it, skipping the middleware.
+  ///
+  /// If the path matches but the method doesn't, the response is a
+  /// `405 Method Not Allowed` listing the allowed methods in `Allow`; an
+  /// `OPTIONS` request to such a path gets that list with a `204`.
+  ///
+  /// A `HEAD` request without a route of its own is answered by the `GET`
+  /// route, as RFC 9110 §9.3.2 asks. The body is left in place so its length
+  /// can still be sent; [`Response::write_without_body`] drops it.
+  pub fn route(&self, req: &HttpRequest) -> Response {
+    let Resource::Path(path) = &req.resource;
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let segments = split_path(path);
+
+    let mut allowed: Vec<&str> = Vec::new();
+    let mut get = None;
+    for route in self.routes.iter() {
+      if let Some(params) = match_segments(&route.segments, &segments) {
+        if route.method == req.method {
+          return route.handler.handle(req, &params);
         }
-      },
-      _ => {
-        let res = NotFoundHandler::handle(&req);
-        let _ = res.send_response(stream);
+        if route.method == Method::Get {
+          allowed.push(Method::Head.as_str());
+          get = get.or(Some((route, params)));
+        }
+        allowed.push(route.method.as_str());
       }
     }
+
+    if let (Method::Head, Some((route, params))) = (req.method, get) {
+      return route.handler.handle(req, &params);
+    }
+    if allowed.is_empty() {
+      return self.not_found.handle(req, &Params::default());
+    }
+    allowed.push("OPTIONS");
+    allowed.sort_unstable();
+    allowed.dedup();
+    let allow = allowed.join(", ");
+    if req.method == Method::Options {
+      Response::new().status(204).header("Allow", allow)
+    } else {
+      Response::new()
+        .status(405)
+        .header("Allow", allow)
+        .header("Content-Type", "text/plain")
+        .body("Method Not Allowed")
+    }
+  }
+}
+
+/// Splits a path into its segments, ignoring the leading and any trailing
+/// slash, so `/api/orders/` becomes `["api", "orders"]`.
+fn split_path(path: &str) -> Vec<&str> {
+  let path = path.trim_start_matches('/');
+  let path = path.strip_suffix('/').unwrap_or(path);
+  if path.is_empty() {
+    Vec::new()
+  } else {
+    path.split('/').collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn request(method: &str, path: &str) -> HttpRequest {
+    format!("{} {} HTTP/1.1\r\n\r\n", method, path).into()
+  }
+
+  fn echo(name: &'static str) -> impl Handler {
+    move |_: &HttpRequest, params: &Params| {
+      Response::new().body(format!("{}:{}", name, params.get(name).unwrap_or("-")))
+    }
+  }
+
+  fn router() -> Router {
+    let mut router = Router::new();
+    router
+      .get("/api/shipping/orders/:id", echo("id"))
+      .delete("/api/shipping/orders/:id", echo("id"))
+      .get("/assets/*path", echo("path"));
+    router
+  }
+
+  #[test]
+  fn test_params_and_wildcards() {
+    let router = router();
+
+    let res = router.route(&request("GET", "/api/shipping/orders/42?x=1"));
+    assert_eq!(res.body_bytes(), b"id:42");
+    let res = router.route(&request("GET", "/assets/css/index.css"));
+    assert_eq!(res.body_bytes(), b"path:css/index.css");
+    let res = router.route(&request("GET", "/assets"));
+    assert_eq!(res.body_bytes(), b"path:");
+  }
+
+  #[test]
+  fn test_not_found() {
+    let router = router();
+
+    for path in [
+      "/api",
+      "/api/shipping/orders",
+      "/api/shipping/orders/1/x",
+      "*",
+    ] {
+      assert_eq!(router.route(&request("GET", path)).status_code(), 404);
+    }
+  }
+
+  #[test]
+  fn test_method_not_allowed() {
+    let router = router();
+
+    let res = router.route(&request("POST", "/api/shipping/orders/1"));
+    assert_eq!(res.status_code(), 405);
+    assert_eq!(
+      res.header_value("Allow"),
+      Some("DELETE, GET, HEAD, OPTIONS")
+    );
+    let res = router.route(&request("OPTIONS", "/api/shipping/orders/1"));
+    assert_eq!(res.status_code(), 204);
+  }
+
+  #[test]
+  fn test_head_falls_back_to_get() {
+    let router = router();
+
+    let res = router.route(&request("HEAD", "/api/shipping/orders/7"));
+    assert_eq!(res.status_code(), 200);
+    assert_eq!(res.body_bytes(), b"id:7");
+    let mut buf = Vec::new();
+    res.write_without_body(&mut buf).unwrap();
+    assert!(String::from_utf8(buf)
+      .unwrap()
+      .ends_with("Content-Length: 4\r\n\r\n"));
   }
 }
@@ -1,29 +1,966 @@
-use super::router::Router;
-use http::http_request::HttpRequest;
-use std::{io::Read, net::TcpListener};
+use super::config::LogLevel;
+use super::pool::ThreadPool;
+use super::proxy::ClientInfo;
+use super::router::{Params, Router};
+use super::sse;
+use super::websocket::{self, Connection};
+use http::compression;
+use http::http_request::{HttpRequest, Method, Resource, Version};
+use http::http_response::Response;
+use http::websocket::{handshake, is_upgrade_request, Role, WebSocket};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-pub struct Server<'a> {
-  addr: &'a str,
+/// How many requests a single keep-alive connection may send before the
+/// server closes it.
+const MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+/// Threads serving upgraded connections. WebSockets and event streams stay
+/// open for as long as the client likes, so each gets a thread of its own
+/// instead of holding a pool worker; a few open tabs would otherwise leave
+/// none for plain requests.
+#[derive(Clone)]
+struct Upgrades {
+  threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+  log_level: LogLevel,
+}
+
+impl Upgrades {
+  fn new(log_level: LogLevel) -> Self {
+    Upgrades {
+      threads: Arc::new(Mutex::new(Vec::new())),
+      log_level,
+    }
+  }
+
+  fn spawn(&self, job: impl FnOnce() -> io::Result<()> + Send + 'static) {
+    let log_level = self.log_level;
+    let mut threads = self.threads.lock().unwrap();
+    threads.retain(|thread| !thread.is_finished());
+    threads.push(thread::spawn(move || {
+      if let Err(err) = job() {
+        if log_level >= LogLevel::Warn {
+          eprintln!("Connection error: {}", err);
+        }
+      }
+    }));
+  }
+
+  /// Waits for every upgraded connection to finish.
+  fn join(&self) {
+    let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+    for thread in threads {
+      let _ = thread.join();
+    }
+  }
+}
+
+/// A TLS connection that sends `close_notify` when it is dropped, on
+/// whichever thread finishes with it.
+struct TlsStream(StreamOwned<ServerConnection, TcpStream>);
+
+impl Read for TlsStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.0.read(buf)
+  }
+}
+
+impl Write for TlsStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.0.flush()
+  }
+}
+
+impl Connection for TlsStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    self.0.sock.set_read_timeout(timeout)
+  }
+}
+
+impl Drop for TlsStream {
+  fn drop(&mut self) {
+    self.0.conn.send_close_notify();
+    let _ = self.0.flush();
+  }
+}
+
+/// What a listener does with its connections.
+#[derive(Clone)]
+enum Kind {
+  Http,
+  Https(Arc<ServerConfig>),
+  /// Answers every request with a redirect to HTTPS on the given port.
+  RedirectToHttps(u16),
+}
+
+pub struct Server {
+  listeners: Vec<(String, Kind)>,
+  router: Arc<Router>,
+  workers: usize,
+  read_timeout: Duration,
+  write_timeout: Duration,
+  max_request_size: usize,
+  log_level: LogLevel,
+  shutdown: Arc<AtomicBool>,
+}
+
+/// The per-connection settings every worker needs.
+#[derive(Clone, Copy)]
+struct Limits {
+  read_timeout: Duration,
+  write_timeout: Duration,
+  max_request_size: usize,
 }
 
-impl<'a> Server<'a> {
-  pub fn new(addr: &'a str) -> Server {
-    Server { addr }
+impl Server {
+  pub fn new(router: Router) -> Server {
+    Server {
+      listeners: Vec::new(),
+      router: Arc::new(router),
+      workers: 8,
+      read_timeout: Duration::from_secs(5),
+      write_timeout: Duration::from_secs(5),
+      max_request_size: 1024 * 1024,
+      log_level: LogLevel::Info,
+      shutdown: Arc::new(AtomicBool::new(false)),
+    }
+  }
+
+  /// Adds an address to listen on. Call it more than once to listen on
+  /// several addresses with one shared thread pool.
+  pub fn listen(mut self, addr: &str) -> Self {
+    self.listeners.push((addr.into(), Kind::Http));
+    self
+  }
+
+  /// Adds an HTTPS address to listen on, see [`crate::tls::server_config`].
+  pub fn listen_tls(mut self, addr: &str, config: Arc<ServerConfig>) -> Self {
+    self.listeners.push((addr.into(), Kind::Https(config)));
+    self
+  }
+
+  /// Adds a plain HTTP address whose only job is to send clients to the
+  /// HTTPS listener on `https_port`.
+  pub fn redirect_to_https(mut self, addr: &str, https_port: u16) -> Self {
+    self
+      .listeners
+      .push((addr.into(), Kind::RedirectToHttps(https_port)));
+    self
+  }
+
+  pub fn workers(mut self, workers: usize) -> Self {
+    self.workers = workers;
+    self
   }
 
-  pub fn run(&self) {
-    let listener = TcpListener::bind(self.addr).unwrap();
-    let mut buffer = [0; 1024];
+  pub fn read_timeout(mut self, read_timeout: Duration) -> Self {
+    self.read_timeout = read_timeout;
+    self
+  }
+
+  pub fn write_timeout(mut self, write_timeout: Duration) -> Self {
+    self.write_timeout = write_timeout;
+    self
+  }
+
+  pub fn max_request_size(mut self, max_request_size: usize) -> Self {
+    self.max_request_size = max_request_size;
+    self
+  }
+
+  pub fn log_level(mut self, log_level: LogLevel) -> Self {
+    self.log_level = log_level;
+    self
+  }
+
+  /// Binds every listener and serves connections until the process receives
+  /// SIGINT or SIGTERM.
+  pub fn run(&self) -> io::Result<()> {
+    let mut listeners = Vec::new();
+    for (addr, kind) in self.listeners.iter() {
+      let listener = TcpListener::bind(addr)?;
+      if self.log_level >= LogLevel::Info {
+        let scheme = match kind {
+          Kind::Https(_) => "https",
+          _ => "http",
+        };
+        println!(
+          "Server is running on {}://{}",
+          scheme,
+          listener.local_addr()?
+        );
+      }
+      listeners.push((listener, kind.clone()));
+    }
+    let local_addrs = listeners
+      .iter()
+      .map(|(l, _)| l.local_addr())
+      .collect::<io::Result<Vec<_>>>()?;
+
+    let shutdown = Arc::clone(&self.shutdown);
+    let log_level = self.log_level;
+    let handler = ctrlc::set_handler(move || {
+      if log_level >= LogLevel::Info {
+        println!("Shutting down, waiting for open connections...");
+      }
+      Self::stop(&shutdown, &local_addrs);
+    });
+    if let Err(err) = handler {
+      eprintln!("Could not install the shutdown handler: {}", err);
+    }
+
+    self.serve(listeners)
+  }
+
+  /// Accepts connections on every listener and hands them to the thread pool.
+  /// Returns once shutdown was requested and every in-flight connection has
+  /// finished.
+  fn serve(&self, listeners: Vec<(TcpListener, Kind)>) -> io::Result<()> {
+    let pool = ThreadPool::new(self.workers);
+    let upgrades = Upgrades::new(self.log_level);
+    let limits = Limits {
+      read_timeout: self.read_timeout,
+      write_timeout: self.write_timeout,
+      max_request_size: self.max_request_size,
+    };
+
+    thread::scope(|scope| {
+      for (listener, kind) in listeners {
+        let (pool, upgrades) = (&pool, &upgrades);
+        scope.spawn(move || self.accept(listener, kind, pool, upgrades, limits));
+      }
+    });
+    // Workers may still upgrade connections until the pool has drained.
+    drop(pool);
+    upgrades.join();
 
+    Ok(())
+  }
+
+  fn accept(
+    &self,
+    listener: TcpListener,
+    kind: Kind,
+    pool: &ThreadPool,
+    upgrades: &Upgrades,
+    limits: Limits,
+  ) {
+    let router = match kind {
+      Kind::RedirectToHttps(port) => Arc::new(redirect_router(port)),
+      _ => Arc::clone(&self.router),
+    };
     for stream in listener.incoming() {
-      let mut stream = stream.unwrap();
+      if self.shutdown.load(Ordering::SeqCst) {
+        break;
+      }
+      match stream {
+        Ok(stream) => {
+          if self.log_level >= LogLevel::Debug {
+            if let Ok(peer) = stream.peer_addr() {
+              println!("Accepted connection from {}", peer);
+            }
+          }
+          let router = Arc::clone(&router);
+          let shutdown = Arc::clone(&self.shutdown);
+          let kind = kind.clone();
+          let upgrades = upgrades.clone();
+          let log_level = self.log_level;
+          pool.execute(move || {
+            let result = match kind {
+              Kind::Https(config) => {
+                handle_tls_connection(stream, config, &router, limits, &shutdown, &upgrades)
+              }
+              _ => handle_connection(stream, &router, limits, &shutdown, &upgrades),
+            };
+            if let Err(err) = result {
+              if log_level >= LogLevel::Warn {
+                eprintln!("Connection error: {}", err);
+              }
+            }
+          });
+        }
+        Err(err) if self.log_level >= LogLevel::Error => {
+          eprintln!("Failed to accept connection: {}", err)
+        }
+        Err(_) => {}
+      }
+    }
+  }
+
+  /// Flags the server as shutting down and wakes up the blocking accept loops
+  /// with a throwaway connection each.
+  fn stop(shutdown: &AtomicBool, local_addrs: &[SocketAddr]) {
+    shutdown.store(true, Ordering::SeqCst);
+    for addr in local_addrs {
+      let _ = TcpStream::connect(addr);
+    }
+  }
+}
+
+/// A router without routes whose fallback redirects to the same host and
+/// path over HTTPS. `308` keeps the method and body of the request.
+fn redirect_router(https_port: u16) -> Router {
+  let mut router = Router::new();
+  router.not_found(move |req: &HttpRequest, _: &Params| {
+    let host = match req.header("Host").map(strip_port) {
+      Some(host) if !host.is_empty() => host,
+      _ => {
+        return Response::new()
+          .status(400)
+          .header("Content-Type", "text/plain")
+          .body("Missing Host header")
+      }
+    };
+    let Resource::Path(path) = &req.resource;
+    let location = match https_port {
+      443 => format!("https://{}{}", host, path),
+      port => format!("https://{}:{}{}", host, port, path),
+    };
+    Response::new().status(308).header("Location", location)
+  });
+  router
+}
+
+/// `example.com:80` becomes `example.com`, `[::1]:80` becomes `[::1]`.
+fn strip_port(host: &str) -> &str {
+  match host.rfind(':') {
+    Some(i) if !host[i..].contains(']') => &host[..i],
+    _ => host,
+  }
+}
+
+fn handle_tls_connection(
+  stream: TcpStream,
+  config: Arc<ServerConfig>,
+  router: &Router,
+  limits: Limits,
+  shutdown: &Arc<AtomicBool>,
+  upgrades: &Upgrades,
+) -> io::Result<()> {
+  set_timeouts(&stream, limits)?;
+  let client = ClientInfo {
+    addr: stream.peer_addr().ok().map(|addr| addr.ip()),
+    proto: "https",
+  };
+  let conn = ServerConnection::new(config).map_err(io::Error::other)?;
+  let tls = TlsStream(StreamOwned::new(conn, stream));
+  serve_requests(tls, client, router, limits, shutdown, upgrades)
+}
+
+fn handle_connection(
+  stream: TcpStream,
+  router: &Router,
+  limits: Limits,
+  shutdown: &Arc<AtomicBool>,
+  upgrades: &Upgrades,
+) -> io::Result<()> {
+  set_timeouts(&stream, limits)?;
+  let client = ClientInfo {
+    addr: stream.peer_addr().ok().map(|addr| addr.ip()),
+    proto: "http",
+  };
+  serve_requests(stream, client, router, limits, shutdown, upgrades)
+}
+
+fn set_timeouts(stream: &TcpStream, limits: Limits) -> io::Result<()> {
+  stream.set_read_timeout(Some(limits.read_timeout))?;
+  stream.set_write_timeout(Some(limits.write_timeout))
+}
+
+/// Serves requests from `stream` until the client or the keep-alive rules
+/// close the connection, or hands it to `upgrades` once it is upgraded.
+fn serve_requests(
+  stream: impl Connection + Send + 'static,
+  client: ClientInfo,
+  router: &Router,
+  limits: Limits,
+  shutdown: &Arc<AtomicBool>,
+  upgrades: &Upgrades,
+) -> io::Result<()> {
+  let mut reader = BufReader::new(stream);
+
+  for served in 1..=MAX_REQUESTS_PER_CONNECTION {
+    let mut req = match read_head(&mut reader, limits.max_request_size) {
+      Ok(Some(req)) => req,
+      // The client closed the connection or went idle.
+      Ok(None) => return Ok(()),
+      Err(err) if is_timeout(&err) => return Ok(()),
+      Err(err) => return bad_request(&err).write_to(reader.get_mut()),
+    };
+    let keep_alive = wants_keep_alive(&req)
+      && served < MAX_REQUESTS_PER_CONNECTION
+      && !shutdown.load(Ordering::SeqCst);
+    // Proxied bodies are streamed, so they are forwarded before the body is
+    // read.
+    if let Some(proxy) = router.proxy_route(&req) {
+      if proxy.forward(req, &mut reader, client, keep_alive)? {
+        continue;
+      }
+      break;
+    }
+    match read_body(&mut reader, &mut req, limits.max_request_size) {
+      Ok(()) => {}
+      Err(err) if is_timeout(&err) => return Ok(()),
+      Err(err) => return bad_request(&err).write_to(reader.get_mut()),
+    }
+    if is_upgrade_request(&req) {
+      if let Some(handler) = router.websocket_route(&req) {
+        return match handshake(&req) {
+          Ok(res) => {
+            res.write_to(reader.get_mut())?;
+            let ws = WebSocket::from_buffered(reader, Role::Server);
+            let shutdown = Arc::clone(shutdown);
+            upgrades.spawn(move || {
+              websocket::serve(ws, handler.as_ref(), limits.read_timeout, &shutdown)
+            });
+            Ok(())
+          }
+          Err(res) => res
+            .set_header("Connection", "close")
+            .write_to(reader.get_mut()),
+        };
+      }
+    }
+    if let Some(source) = router.event_stream_route(&req) {
+      let shutdown = Arc::clone(shutdown);
+      upgrades.spawn(move || {
+        sse::serve(
+          reader.get_mut(),
+          source.as_ref(),
+          &req,
+          sse::HEARTBEAT_INTERVAL,
+          &shutdown,
+        )
+      });
+      return Ok(());
+    }
+    let accept_encoding = req.header("Accept-Encoding").map(String::from);
+    let method = req.method;
+    let res = router
+      .handle(req)
+      .compress(accept_encoding.as_deref())
+      .set_header(
+        "Connection",
+        if keep_alive { "keep-alive" } else { "close" },
+      );
+    if method == Method::Head {
+      res.write_without_body(reader.get_mut())?;
+    } else {
+      res.write_to(reader.get_mut())?;
+    }
+
+    if !keep_alive {
+      break;
+    }
+  }
+
+  Ok(())
+}
+
+/// Reads the request line and headers of the next request, leaving the body
+/// unread. Returns `Ok(None)` if the client closed the connection before
+/// sending anything.
+///
+/// Errors of kind `OutOfMemory` mean the request exceeded `max_size`; other
+/// errors mean it was malformed.
+fn read_head(reader: &mut impl BufRead, max_size: usize) -> io::Result<Option<HttpRequest>> {
+  let mut head = Vec::new();
+  loop {
+    let limit = (max_size + 1).saturating_sub(head.len()) as u64;
+    let read = reader.by_ref().take(limit).read_until(b'\n', &mut head)?;
+    if read == 0 {
+      if head.is_empty() {
+        return Ok(None);
+      }
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "connection closed in the middle of a request",
+      ));
+    }
+    if head.len() > max_size {
+      return Err(too_large());
+    }
+    // Tolerate stray empty lines before the request line.
+    if head == b"\r\n" || head == b"\n" {
+      head.clear();
+    } else if head.ends_with(b"\r\n\r\n") || head.ends_with(b"\n\n") {
+      break;
+    }
+  }
+
+  let req: HttpRequest = String::from_utf8(head)
+    .map_err(|_| invalid_data("request head is not valid UTF-8"))?
+    .into();
+  if req.header("Transfer-Encoding").is_some() {
+    return Err(invalid_data("chunked request bodies are not supported"));
+  }
+  if let Some(len) = req.header("Content-Length") {
+    len
+      .parse::<usize>()
+      .map_err(|_| invalid_data("invalid Content-Length"))?;
+  }
+
+  Ok(Some(req))
+}
+
+/// Reads the body announced by the `Content-Length` of a head returned by
+/// [`read_head`], and undoes its `Content-Encoding` so handlers always see
+/// the plain body. The decoded body is held to `max_size` too.
+fn read_body(reader: &mut impl BufRead, req: &mut HttpRequest, max_size: usize) -> io::Result<()> {
+  let content_length = match req.header("Content-Length") {
+    Some(len) => len
+      .parse::<usize>()
+      .map_err(|_| invalid_data("invalid Content-Length"))?,
+    None => 0,
+  };
+  if content_length > max_size {
+    return Err(too_large());
+  }
+  req.msg_body = vec![0; content_length];
+  reader.read_exact(&mut req.msg_body)?;
+
+  if let Some(encoding) = req.header("Content-Encoding") {
+    req.msg_body = compression::decode(encoding, &req.msg_body, max_size)?;
+    req.headers.retain(|k, _| {
+      !k.eq_ignore_ascii_case("Content-Encoding") && !k.eq_ignore_ascii_case("Content-Length")
+    });
+    req
+      .headers
+      .insert("Content-Length".into(), req.msg_body.len().to_string());
+  }
+
+  Ok(())
+}
+
+fn wants_keep_alive(req: &HttpRequest) -> bool {
+  match req.header("Connection") {
+    Some(conn) if conn.eq_ignore_ascii_case("close") => false,
+    Some(conn) if conn.eq_ignore_ascii_case("keep-alive") => true,
+    _ => req.version == Version::V1_1,
+  }
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+  matches!(
+    err.kind(),
+    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+  )
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The response to a request that could not be read.
+fn bad_request(err: &io::Error) -> Response {
+  let status = match err.kind() {
+    io::ErrorKind::OutOfMemory => 413,
+    io::ErrorKind::Unsupported => 415,
+    _ => 400,
+  };
+  Response::new()
+    .status(status)
+    .header("Content-Type", "text/plain")
+    .header("Connection", "close")
+    .body(err.to_string())
+}
+
+fn too_large() -> io::Error {
+  io::Error::new(io::ErrorKind::OutOfMemory, "request is too large")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::CertificateConfig;
+  use crate::proxy::Proxy;
+  use crate::tls;
+  use http::form::MultipartOptions;
+  use http::http_client::HttpClient;
+  use http::websocket::{close_code, Message};
+  use rustls::crypto::ring::default_provider;
+  use rustls::{ClientConfig, ClientConnection, RootCertStore};
+  use rustls_pki_types::{CertificateDer, ServerName};
+  use std::env;
+  use std::fs;
+  use std::process;
+  use std::time::Instant;
+
+  fn read_request(reader: &mut impl BufRead, max_size: usize) -> io::Result<Option<HttpRequest>> {
+    let Some(mut req) = read_head(reader, max_size)? else {
+      return Ok(None);
+    };
+    read_body(reader, &mut req, max_size)?;
+    Ok(Some(req))
+  }
+
+  #[test]
+  fn test_read_request() {
+    let raw = b"POST /api HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+    let req = read_request(&mut &raw[..], 1024).unwrap().unwrap();
+    assert_eq!(req.msg_body, b"hello");
+
+    assert!(read_request(&mut &b""[..], 1024).unwrap().is_none());
+
+    let bad = b"GET /\xff HTTP/1.1\r\n\r\n";
+    let err = read_request(&mut &bad[..], 1024).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = read_request(&mut &raw[..], 16).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::OutOfMemory);
 
-      stream.read(&mut buffer).unwrap();
+    let body = "order_status=Done".repeat(10);
+    let gzip = compression::encode(compression::Encoding::Gzip, body.as_bytes()).unwrap();
+    let mut raw = format!(
+      "POST /api HTTP/1.1\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+      gzip.len()
+    )
+    .into_bytes();
+    raw.extend_from_slice(&gzip);
+    let req = read_request(&mut &raw[..], 1024).unwrap().unwrap();
+    assert_eq!(req.msg_body, body.as_bytes());
+    assert_eq!(req.header("Content-Encoding"), None);
+    assert_eq!(req.header("Content-Length"), Some("170"));
+    let err = read_request(&mut &raw[..], 100).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
 
-      let req: HttpRequest = String::from_utf8(buffer.to_vec()).unwrap().into();
-      println!("{:?} {:?} {:?}", req.method, req.version, req.resource);
+    let br = b"POST /api HTTP/1.1\r\nContent-Encoding: br\r\nContent-Length: 2\r\n\r\nxx";
+    let err = read_request(&mut &br[..], 1024).unwrap_err();
+    assert_eq!(bad_request(&err).status_code(), 415);
+  }
+
+  #[test]
+  fn test_slow_client_does_not_block_others() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut router = Router::new();
+    router.get("/", |_: &HttpRequest, _: &Params| Response::new());
+    let server = Arc::new(Server::new(router));
+    let running = Arc::clone(&server);
+    let handle = thread::spawn(move || running.serve(vec![(listener, Kind::Http)]));
+
+    // A client that connects but never finishes its request.
+    let mut slow = TcpStream::connect(addr).unwrap();
+    slow.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+
+    let started = Instant::now();
+    let mut client = HttpClient::new();
+    let res = client.get(&format!("http://{}/", addr)).unwrap();
+    assert_eq!(res.status_code(), 200);
+    assert!(started.elapsed() < server.read_timeout);
+
+    drop(client);
+    Server::stop(&server.shutdown, &[addr]);
+    drop(slow);
+    handle.join().unwrap().unwrap();
+  }
+
+  #[test]
+  fn test_binary_multipart_upload() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut router = Router::new();
+    router.post("/upload", |req: &HttpRequest, _: &Params| {
+      let options = MultipartOptions {
+        memory_limit: 4,
+        ..MultipartOptions::default()
+      };
+      let multipart = req.multipart(&options).unwrap().unwrap();
+      let file = multipart.get("file").unwrap();
+      Response::new().body(file.bytes().unwrap())
+    });
+    let server = Arc::new(Server::new(router));
+    let running = Arc::clone(&server);
+    let handle = thread::spawn(move || running.serve(vec![(listener, Kind::Http)]));
+
+    let image: &[u8] = b"\x89PNG\r\n\x1a\n\x00\xff\xfe";
+    let mut body = b"--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\nContent-Type: image/png\r\n\r\n".to_vec();
+    body.extend_from_slice(image);
+    body.extend_from_slice(b"\r\n--XyZ--\r\n");
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+      stream,
+      "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+      body.len()
+    )
+    .unwrap();
+    stream.write_all(&body).unwrap();
+    let res = Response::read_from(&mut BufReader::new(stream)).unwrap();
+    assert_eq!(res.status_code(), 200);
+    assert_eq!(res.body_bytes(), image);
+
+    Server::stop(&server.shutdown, &[addr]);
+    handle.join().unwrap().unwrap();
+  }
+
+  /// Writes a fresh self-signed certificate for `name` to `dir` and returns
+  /// its config and DER encoding.
+  fn self_signed(dir: &std::path::Path, name: &str) -> (CertificateConfig, Vec<u8>) {
+    let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    let cert = dir.join(format!("{}.pem", name));
+    let key = dir.join(format!("{}-key.pem", name));
+    fs::write(&cert, generated.cert.pem()).unwrap();
+    fs::write(&key, generated.key_pair.serialize_pem()).unwrap();
+    let config = CertificateConfig {
+      cert,
+      key,
+      server_names: vec![name.to_string()],
+    };
+    (config, generated.cert.der().to_vec())
+  }
+
+  #[test]
+  fn test_tls_with_sni_and_redirect() {
+    let dir = env::temp_dir().join(format!("httpserver-tls-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (localhost, localhost_der) = self_signed(&dir, "localhost");
+    let (other, other_der) = self_signed(&dir, "other.test");
+    let tls_config = tls::server_config(&[localhost, other]).unwrap();
+
+    let https = TcpListener::bind("127.0.0.1:0").unwrap();
+    let https_addr = https.local_addr().unwrap();
+    let redirect = TcpListener::bind("127.0.0.1:0").unwrap();
+    let redirect_addr = redirect.local_addr().unwrap();
+    let mut router = Router::new();
+    router.get("/", |_: &HttpRequest, _: &Params| {
+      Response::new().body("secure")
+    });
+    let server = Arc::new(Server::new(router));
+    let running = Arc::clone(&server);
+    let handle = thread::spawn(move || {
+      running.serve(vec![
+        (https, Kind::Https(tls_config)),
+        (redirect, Kind::RedirectToHttps(8443)),
+      ])
+    });
+
+    let mut roots = RootCertStore::empty();
+    roots
+      .add(CertificateDer::from(localhost_der.clone()))
+      .unwrap();
+    roots.add(CertificateDer::from(other_der.clone())).unwrap();
+    let client_config = Arc::new(
+      ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth(),
+    );
+    for (name, der) in [("localhost", &localhost_der), ("other.test", &other_der)] {
+      let conn = ClientConnection::new(
+        Arc::clone(&client_config),
+        ServerName::try_from(name).unwrap(),
+      )
+      .unwrap();
+      let mut tls = StreamOwned::new(conn, TcpStream::connect(https_addr).unwrap());
+      tls
+        .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+      let res = Response::read_from(&mut BufReader::new(&mut tls)).unwrap();
+      assert_eq!(res.body_bytes(), b"secure");
+      let peer = tls.conn.peer_certificates().unwrap();
+      assert_eq!(peer[0].as_ref(), der.as_slice());
+    }
+
+    let mut plain = TcpStream::connect(redirect_addr).unwrap();
+    plain
+      .write_all(b"POST /api?x=1 HTTP/1.1\r\nHost: example.com:3080\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let res = Response::read_from(&mut BufReader::new(plain)).unwrap();
+    assert_eq!(res.status_code(), 308);
+    assert_eq!(
+      res.header_value("Location"),
+      Some("https://example.com:8443/api?x=1")
+    );
+
+    Server::stop(&server.shutdown, &[https_addr, redirect_addr]);
+    handle.join().unwrap().unwrap();
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_strip_port() {
+    assert_eq!(strip_port("example.com:80"), "example.com");
+    assert_eq!(strip_port("example.com"), "example.com");
+    assert_eq!(strip_port("[::1]:80"), "[::1]");
+    assert_eq!(strip_port("[::1]"), "[::1]");
+  }
+
+  struct Echo;
+
+  impl websocket::WebSocketHandler for Echo {
+    fn on_message(&self, session: &websocket::Session, message: Message) {
+      session.send(message);
+    }
+  }
+
+  #[test]
+  fn test_websocket_echo() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut router = Router::new();
+    router.websocket("/echo", Echo);
+    let server = Arc::new(Server::new(router));
+    let running = Arc::clone(&server);
+    let handle = thread::spawn(move || running.serve(vec![(listener, Kind::Http)]));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+      .write_all(
+        b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+      )
+      .unwrap();
+    let mut reader = BufReader::new(stream);
+    let res = Response::read_from(&mut reader).unwrap();
+    assert_eq!(res.status_code(), 101);
+    assert_eq!(
+      res.header_value("Sec-WebSocket-Accept"),
+      Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+
+    let mut ws = WebSocket::from_buffered(reader, Role::Client);
+    ws.send(Message::Text("hello".into())).unwrap();
+    assert_eq!(ws.read_message().unwrap(), Message::Text("hello".into()));
+    ws.send(Message::Ping(b"p".to_vec())).unwrap();
+    assert_eq!(ws.read_message().unwrap(), Message::Pong(b"p".to_vec()));
+
+    // Shutting down closes open WebSockets with "going away".
+    Server::stop(&server.shutdown, &[addr]);
+    assert_eq!(
+      ws.read_message().unwrap(),
+      Message::Close(Some((
+        close_code::GOING_AWAY,
+        "server is shutting down".into()
+      )))
+    );
+    handle.join().unwrap().unwrap();
+  }
 
-      Router::route(req, &mut stream);
+  #[test]
+  fn test_upgrades_do_not_hold_workers() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut router = Router::new();
+    router.get("/", |_: &HttpRequest, _: &Params| Response::new());
+    router.websocket("/echo", Echo);
+    router.event_stream("/events", crate::handler::OrderFeed::default());
+    let server = Arc::new(Server::new(router).workers(2));
+    let running = Arc::clone(&server);
+    let handle = thread::spawn(move || running.serve(vec![(listener, Kind::Http)]));
+
+    // An open WebSocket or event stream for every worker.
+    let websocket: &[u8] = b"GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+    let events: &[u8] = b"GET /events HTTP/1.1\r\n\r\n";
+    let sockets: Vec<_> = [websocket, events, websocket, events]
+      .iter()
+      .map(|req| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(req).unwrap();
+        let mut reader = BufReader::new(stream);
+        let res = Response::read_head(&mut reader).unwrap();
+        assert!([101, 200].contains(&res.status_code()));
+        reader
+      })
+      .collect();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+      .set_read_timeout(Some(Duration::from_secs(2)))
+      .unwrap();
+    stream
+      .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+      .unwrap();
+    let res = Response::read_from(&mut BufReader::new(stream)).unwrap();
+    assert_eq!(res.status_code(), 200);
+
+    drop(sockets);
+    Server::stop(&server.shutdown, &[addr]);
+    handle.join().unwrap().unwrap();
+  }
+
+  /// An upstream that answers every request with its name, the path, the
+  /// `X-Forwarded-For` header and the body it received, either with a
+  /// `Content-Length` or chunked.
+  fn echo_upstream(name: &'static str, chunked: bool) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut reader = BufReader::new(stream.unwrap());
+        let req = read_request(&mut reader, 1024).unwrap().unwrap();
+        let Resource::Path(path) = &req.resource;
+        let body = format!(
+          "{} {} {} {}",
+          name,
+          path,
+          req.header("X-Forwarded-For").unwrap_or_default(),
+          String::from_utf8_lossy(&req.msg_body)
+        );
+        let res = match chunked {
+          true => format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            body.len(),
+            body
+          ),
+          false => format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+          ),
+        };
+        reader.get_mut().write_all(res.as_bytes()).unwrap();
+      }
+    });
+    addr
+  }
+
+  #[test]
+  fn test_proxy_round_robin() {
+    let a = echo_upstream("a", false);
+    let b = echo_upstream("b", true);
+    let dead = TcpListener::bind("127.0.0.1:0")
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let proxy = Proxy::new([a.to_string(), dead.to_string(), b.to_string()])
+      .strip_prefix("/tutor")
+      .health_check("/health", Duration::from_secs(3600));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut router = Router::new();
+    router.proxy("/tutor/*path", proxy);
+    let server = Arc::new(Server::new(router));
+    let running = Arc::clone(&server);
+    let handle = thread::spawn(move || running.serve(vec![(listener, Kind::Http)]));
+
+    let mut client = HttpClient::new();
+    let url = format!("http://{}/tutor/courses?page=2", addr);
+    let mut names = Vec::new();
+    for _ in 0..4 {
+      let res = client
+        .send(http::http_request::Method::Post, &url, &[], "hi")
+        .unwrap();
+      assert_eq!(res.status_code(), 200);
+      let body = String::from_utf8(res.body_bytes().to_vec()).unwrap();
+      let (name, rest) = body.split_once(' ').unwrap();
+      assert_eq!(rest, "/courses?page=2 127.0.0.1 hi");
+      names.push(name.to_string());
     }
+    // The dead upstream is skipped once it refused a connection.
+    assert_eq!(names, ["a", "b", "b", "a"]);
+
+    drop(client);
+    Server::stop(&server.shutdown, &[addr]);
+    handle.join().unwrap().unwrap();
   }
 }
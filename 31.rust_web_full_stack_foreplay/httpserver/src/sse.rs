@@ -0,0 +1,226 @@
+use http::http_request::HttpRequest;
+use http::http_response::Response;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the stream loop checks for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Idle time after which a comment is sent, so proxies keep the connection
+/// open and dead clients are noticed.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// One Server-Sent Event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+  id: Option<String>,
+  event: Option<String>,
+  data: String,
+}
+
+impl Event {
+  pub fn new(data: impl Into<String>) -> Self {
+    Event {
+      data: data.into(),
+      ..Event::default()
+    }
+  }
+
+  /// The id the browser sends back as `Last-Event-ID` when it reconnects.
+  pub fn id(mut self, id: impl Into<String>) -> Self {
+    self.id = Some(id.into());
+    self
+  }
+
+  /// The event type, which `EventSource.addEventListener` listens for.
+  /// Without one the browser fires `message`.
+  pub fn event(mut self, event: impl Into<String>) -> Self {
+    self.event = Some(event.into());
+    self
+  }
+
+  fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+    // Line breaks would end the field early, so they are dropped from the
+    // single-line fields and split into several `data:` lines.
+    let single_line = |s: &str| s.replace(['\r', '\n'], "");
+    if let Some(event) = &self.event {
+      writeln!(w, "event: {}", single_line(event))?;
+    }
+    if let Some(id) = &self.id {
+      writeln!(w, "id: {}", single_line(id).replace('\0', ""))?;
+    }
+    for line in self.data.split('\n') {
+      writeln!(w, "data: {}", line.strip_suffix('\r').unwrap_or(line))?;
+    }
+    writeln!(w)
+  }
+}
+
+/// An endpoint that streams events, registered with
+/// [`Router::event_stream`](super::router::Router::event_stream).
+pub trait EventSource: Send + Sync {
+  /// Called when a client connects. `last_event_id` is the id of the last
+  /// event a reconnecting client saw, so missed events can be replayed.
+  fn on_connect(&self, stream: &EventStream, last_event_id: Option<&str>);
+
+  /// Called with the id of a stream whose client went away.
+  fn on_disconnect(&self, _stream_id: u64) {}
+}
+
+impl<S: EventSource + ?Sized> EventSource for Arc<S> {
+  fn on_connect(&self, stream: &EventStream, last_event_id: Option<&str>) {
+    (**self).on_connect(stream, last_event_id)
+  }
+
+  fn on_disconnect(&self, stream_id: u64) {
+    (**self).on_disconnect(stream_id)
+  }
+}
+
+/// A handle to one connected client. It can be cloned and kept to send
+/// events from other threads; the stream ends once every handle is dropped.
+#[derive(Clone)]
+pub struct EventStream {
+  id: u64,
+  sender: Sender<Event>,
+}
+
+impl EventStream {
+  pub(crate) fn new(sender: Sender<Event>) -> Self {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    EventStream {
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      sender,
+    }
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Queues `event` for the client. Returns `false` once the client is gone.
+  pub fn send(&self, event: Event) -> bool {
+    self.sender.send(event).is_ok()
+  }
+}
+
+/// Sends the `text/event-stream` head and then the events from `source`
+/// until the client disconnects, the source drops every handle or the server
+/// shuts down.
+pub fn serve(
+  w: &mut impl Write,
+  source: &dyn EventSource,
+  req: &HttpRequest,
+  heartbeat: Duration,
+  shutdown: &AtomicBool,
+) -> io::Result<()> {
+  Response::new()
+    .header("Content-Type", "text/event-stream")
+    .header("Cache-Control", "no-cache")
+    .header("Connection", "close")
+    .write_head(w)?;
+
+  // The source keeps clones of the stream; once it drops them all, the
+  // channel disconnects and the response ends.
+  let (sender, events) = mpsc::channel();
+  let id = {
+    let stream = EventStream::new(sender);
+    source.on_connect(&stream, req.header("Last-Event-ID"));
+    stream.id()
+  };
+
+  let mut last_write = Instant::now();
+  let result = loop {
+    if shutdown.load(Ordering::SeqCst) {
+      break Ok(());
+    }
+    let written = match events.recv_timeout(POLL_INTERVAL) {
+      Ok(event) => event.write_to(w),
+      Err(RecvTimeoutError::Timeout) if last_write.elapsed() >= heartbeat => {
+        w.write_all(b": heartbeat\n\n")
+      }
+      Err(RecvTimeoutError::Timeout) => continue,
+      Err(RecvTimeoutError::Disconnected) => break Ok(()),
+    };
+    if let Err(err) = written.and_then(|_| w.flush()) {
+      break Err(err);
+    }
+    last_write = Instant::now();
+  };
+
+  source.on_disconnect(id);
+  match result {
+    // The client went away, which is how event streams usually end.
+    Err(err)
+      if matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+      ) =>
+    {
+      Ok(())
+    }
+    result => result,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  #[test]
+  fn test_event_format() {
+    let mut out = Vec::new();
+    Event::new("line 1\nline 2")
+      .id("7")
+      .event("update\n")
+      .write_to(&mut out)
+      .unwrap();
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "event: update\nid: 7\ndata: line 1\ndata: line 2\n\n"
+    );
+  }
+
+  /// Replays everything after `Last-Event-ID`, then sends one more event
+  /// from another thread after a pause.
+  struct Counter;
+
+  impl EventSource for Counter {
+    fn on_connect(&self, stream: &EventStream, last_event_id: Option<&str>) {
+      let from = last_event_id.and_then(|id| id.parse().ok()).unwrap_or(0) + 1;
+      for id in from..=3 {
+        stream.send(Event::new(format!("#{}", id)).id(id.to_string()));
+      }
+      let stream = stream.clone();
+      thread::spawn(move || {
+        thread::sleep(Duration::from_millis(80));
+        stream.send(Event::new("late"));
+      });
+    }
+  }
+
+  #[test]
+  fn test_resume_and_heartbeat() {
+    let req: HttpRequest = "GET /events HTTP/1.1\r\nLast-Event-ID: 2\r\n\r\n"
+      .to_string()
+      .into();
+    let mut out = Vec::new();
+    serve(
+      &mut out,
+      &Counter,
+      &req,
+      Duration::from_millis(20),
+      &AtomicBool::new(false),
+    )
+    .unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    let (head, body) = out.split_once("\r\n\r\n").unwrap();
+    assert!(head.contains("Content-Type: text/event-stream"));
+    assert!(body.starts_with("id: 3\ndata: #3\n\n: heartbeat\n\n"));
+    assert!(body.ends_with("data: late\n\n"));
+  }
+}
@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct OrderStatus {
+  pub order_id: i32,
+  pub order_date: String,
+  pub order_status: String,
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+  NotFound(i32),
+  /// Every order id has been handed out.
+  IdsExhausted,
+  Io(io::Error),
+  Corrupt(serde_json::Error),
+}
+
+impl fmt::Display for StoreError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StoreError::NotFound(id) => write!(f, "Order {} not found", id),
+      StoreError::IdsExhausted => write!(f, "The order store has run out of ids"),
+      StoreError::Io(err) => write!(f, "Could not access the order store: {}", err),
+      StoreError::Corrupt(err) => write!(f, "The order store is corrupt: {}", err),
+    }
+  }
+}
+
+impl From<io::Error> for StoreError {
+  fn from(err: io::Error) -> Self {
+    StoreError::Io(err)
+  }
+}
+
+impl From<serde_json::Error> for StoreError {
+  fn from(err: serde_json::Error) -> Self {
+    StoreError::Corrupt(err)
+  }
+}
+
+/// The contents of the store file. `next_id` only ever grows, so the id of a
+/// deleted order is never handed out again.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct OrderFile {
+  next_id: i32,
+  orders: Vec<OrderStatus>,
+}
+
+/// Files written before `next_id` was stored hold just the array of orders.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredOrders {
+  Current(OrderFile),
+  Legacy(Vec<OrderStatus>),
+}
+
+impl From<StoredOrders> for OrderFile {
+  fn from(stored: StoredOrders) -> Self {
+    match stored {
+      StoredOrders::Current(file) => file,
+      StoredOrders::Legacy(orders) => OrderFile {
+        next_id: orders
+          .iter()
+          .map(|o| o.order_id.saturating_add(1))
+          .max()
+          .unwrap_or(0),
+        orders,
+      },
+    }
+  }
+}
+
+/// Orders persisted as JSON in a single file.
+///
+/// Every operation holds an exclusive lock on a `.lock` file next to the
+/// data, so several server processes can share one file, and writes go to a
+/// temporary file that is renamed over the original, so a crash never leaves
+/// a half-written file behind.
+pub struct OrderStore {
+  path: PathBuf,
+  lock_path: PathBuf,
+  // `File::lock` is per process on some platforms, so threads of this
+  // process also need to take turns.
+  guard: Mutex<()>,
+}
+
+impl OrderStore {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    let path = path.into();
+    let mut lock_path = path.clone().into_os_string();
+    lock_path.push(".lock");
+
+    OrderStore {
+      path,
+      lock_path: lock_path.into(),
+      guard: Mutex::new(()),
+    }
+  }
+
+  pub fn list(&self) -> Result<Vec<OrderStatus>, StoreError> {
+    self.with_orders(|file| Ok((file.orders.clone(), false)))
+  }
+
+  pub fn get(&self, id: i32) -> Result<OrderStatus, StoreError> {
+    self.with_orders(|file| {
+      let order = file.orders.iter().find(|o| o.order_id == id);
+      Ok((order.cloned().ok_or(StoreError::NotFound(id))?, false))
+    })
+  }
+
+  /// Stores a new order under the next id that was never used.
+  pub fn create(
+    &self,
+    order_date: String,
+    order_status: String,
+  ) -> Result<OrderStatus, StoreError> {
+    self.with_orders(|file| {
+      let order = OrderStatus {
+        order_id: file.next_id,
+        order_date,
+        order_status,
+      };
+      file.next_id = file
+        .next_id
+        .checked_add(1)
+        .ok_or(StoreError::IdsExhausted)?;
+      file.orders.push(order.clone());
+      Ok((order, true))
+    })
+  }
+
+  /// Applies `change` to the order with `id` and saves the result.
+  pub fn update(
+    &self,
+    id: i32,
+    change: impl FnOnce(&mut OrderStatus),
+  ) -> Result<OrderStatus, StoreError> {
+    self.with_orders(|file| {
+      let order = file
+        .orders
+        .iter_mut()
+        .find(|o| o.order_id == id)
+        .ok_or(StoreError::NotFound(id))?;
+      change(order);
+      order.order_id = id;
+      Ok((order.clone(), true))
+    })
+  }
+
+  pub fn delete(&self, id: i32) -> Result<(), StoreError> {
+    self.with_orders(|file| {
+      let len = file.orders.len();
+      file.orders.retain(|o| o.order_id != id);
+      if file.orders.len() == len {
+        return Err(StoreError::NotFound(id));
+      }
+      Ok(((), true))
+    })
+  }
+
+  /// Loads the orders under the lock and runs `f` on them. `f` returns its
+  /// result and whether the orders changed and must be written back.
+  fn with_orders<T>(
+    &self,
+    f: impl FnOnce(&mut OrderFile) -> Result<(T, bool), StoreError>,
+  ) -> Result<T, StoreError> {
+    let _guard = self.guard.lock().unwrap_or_else(|e| e.into_inner());
+    let lock = File::options()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(&self.lock_path)?;
+    lock.lock()?;
+
+    let mut file = match fs::read_to_string(&self.path) {
+      Ok(content) if content.trim().is_empty() => OrderFile::default(),
+      Ok(content) => serde_json::from_str::<StoredOrders>(&content)?.into(),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => OrderFile::default(),
+      Err(err) => return Err(err.into()),
+    };
+    let (result, changed) = f(&mut file)?;
+    if changed {
+      write_atomically(&self.path, &serde_json::to_vec_pretty(&file)?)?;
+    }
+
+    Ok(result)
+  }
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+  let mut tmp_path = path.to_path_buf().into_os_string();
+  tmp_path.push(format!(".tmp.{}", process::id()));
+  let tmp_path = PathBuf::from(tmp_path);
+
+  let result = (|| {
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+  })();
+  if result.is_err() {
+    let _ = fs::remove_file(&tmp_path);
+  }
+
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+
+  fn temp_store(name: &str) -> (OrderStore, PathBuf) {
+    let dir = env::temp_dir().join(format!("httpserver-store-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    (OrderStore::new(dir.join("orders.json")), dir)
+  }
+
+  #[test]
+  fn test_crud() {
+    let (store, dir) = temp_store("crud");
+
+    let first = store
+      .create("2022/11/5".into(), "In progress".into())
+      .unwrap();
+    let second = store.create("2022/11/6".into(), "New".into()).unwrap();
+    assert_eq!((first.order_id, second.order_id), (0, 1));
+
+    let updated = store.update(0, |o| o.order_status = "Done".into()).unwrap();
+    assert_eq!(updated.order_status, "Done");
+    assert_eq!(store.get(0).unwrap(), updated);
+
+    store.delete(1).unwrap();
+    assert!(matches!(store.delete(1), Err(StoreError::NotFound(1))));
+    assert_eq!(store.list().unwrap(), vec![updated.clone()]);
+
+    // The deleted order's id is not reused.
+    let third = store.create("2022/11/7".into(), "New".into()).unwrap();
+    assert_eq!(third.order_id, 2);
+    store.delete(2).unwrap();
+
+    // A second store on the same file sees the persisted data.
+    let reopened = OrderStore::new(dir.join("orders.json"));
+    assert_eq!(reopened.list().unwrap(), vec![updated]);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_next_id() {
+    let (store, dir) = temp_store("next-id");
+    let order = |id| {
+      format!(
+        r#"{{"order_id": {}, "order_date": "2022/11/5", "order_status": "New"}}"#,
+        id
+      )
+    };
+
+    // A file from before `next_id` was stored continues after the largest id.
+    fs::write(dir.join("orders.json"), format!("[{}]", order(4))).unwrap();
+    assert_eq!(store.create("".into(), "New".into()).unwrap().order_id, 5);
+
+    fs::write(
+      dir.join("orders.json"),
+      format!(r#"{{"next_id": {}, "orders": []}}"#, i32::MAX),
+    )
+    .unwrap();
+    assert!(matches!(
+      store.create("".into(), "New".into()),
+      Err(StoreError::IdsExhausted)
+    ));
+    assert_eq!(store.list().unwrap(), vec![]);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_corrupt_file() {
+    let (store, dir) = temp_store("corrupt");
+    fs::write(dir.join("orders.json"), "[{").unwrap();
+
+    assert!(matches!(store.list(), Err(StoreError::Corrupt(_))));
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
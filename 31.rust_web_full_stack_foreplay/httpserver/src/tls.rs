@@ -0,0 +1,104 @@
+use super::config::CertificateConfig;
+use rustls::crypto::ring::{default_provider, sign};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use rustls_pki_types::pem::{self, PemObject};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum TlsError {
+  Pem(PathBuf, pem::Error),
+  Rustls(PathBuf, rustls::Error),
+  NoCertificates,
+}
+
+impl fmt::Display for TlsError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TlsError::Pem(path, err) => write!(f, "Could not load {}: {}", path.display(), err),
+      TlsError::Rustls(path, err) => write!(f, "Invalid key {}: {}", path.display(), err),
+      TlsError::NoCertificates => write!(f, "No TLS certificate configured"),
+    }
+  }
+}
+
+/// Picks the certificate by the SNI name in the client hello, falling back
+/// to the first configured certificate.
+#[derive(Debug)]
+struct SniResolver {
+  by_name: HashMap<String, Arc<CertifiedKey>>,
+  default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for SniResolver {
+  fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+    let name = match client_hello.server_name() {
+      Some(name) => name.to_ascii_lowercase(),
+      None => return Some(Arc::clone(&self.default)),
+    };
+    let wildcard = name
+      .split_once('.')
+      .map(|(_, parent)| format!("*.{}", parent));
+    let key = self
+      .by_name
+      .get(&name)
+      .or_else(|| wildcard.and_then(|w| self.by_name.get(&w)))
+      .unwrap_or(&self.default);
+    Some(Arc::clone(key))
+  }
+}
+
+/// Loads every certificate and builds the rustls configuration shared by all
+/// HTTPS listeners.
+pub fn server_config(certificates: &[CertificateConfig]) -> Result<Arc<ServerConfig>, TlsError> {
+  let mut by_name = HashMap::new();
+  let mut default = None;
+  for cert in certificates {
+    let key = Arc::new(load_certified_key(&cert.cert, &cert.key)?);
+    for name in cert.server_names.iter() {
+      by_name
+        .entry(name.to_ascii_lowercase())
+        .or_insert_with(|| Arc::clone(&key));
+    }
+    default.get_or_insert(key);
+  }
+  let resolver = SniResolver {
+    by_name,
+    default: default.ok_or(TlsError::NoCertificates)?,
+  };
+
+  let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+    .with_safe_default_protocol_versions()
+    .expect("the ring provider supports the default protocol versions")
+    .with_no_client_auth()
+    .with_cert_resolver(Arc::new(resolver));
+  config.alpn_protocols = vec![b"http/1.1".to_vec()];
+  Ok(Arc::new(config))
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, TlsError> {
+  let certs = CertificateDer::pem_file_iter(cert_path)
+    .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+    .map_err(|err| TlsError::Pem(cert_path.to_path_buf(), err))?;
+  if certs.is_empty() {
+    return Err(TlsError::Pem(
+      cert_path.to_path_buf(),
+      pem::Error::NoItemsFound,
+    ));
+  }
+  let key = PrivateKeyDer::from_pem_file(key_path)
+    .map_err(|err| TlsError::Pem(key_path.to_path_buf(), err))?;
+  let key =
+    sign::any_supported_type(&key).map_err(|err| TlsError::Rustls(key_path.to_path_buf(), err))?;
+
+  let certified = CertifiedKey::new(certs, key);
+  certified
+    .keys_match()
+    .map_err(|err| TlsError::Rustls(key_path.to_path_buf(), err))?;
+  Ok(certified)
+}
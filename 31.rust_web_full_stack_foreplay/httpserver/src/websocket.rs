@@ -0,0 +1,161 @@
+use http::websocket::{close_code, Message, WebSocket};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the connection loop checks for outgoing messages and shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Idle time after which the server pings the client. A client that stays
+/// silent for twice as long is disconnected.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A message-based WebSocket endpoint, registered with
+/// [`Router::websocket`](super::router::Router::websocket).
+///
+/// Every connection runs on a thread of its own, outside the worker pool. Pings, pongs and the
+/// closing handshake are handled before the callbacks are called.
+pub trait WebSocketHandler: Send + Sync {
+  fn on_open(&self, _session: &Session) {}
+
+  /// Called with every complete `Text` or `Binary` message.
+  fn on_message(&self, session: &Session, message: Message);
+
+  fn on_close(&self, _session: &Session) {}
+}
+
+impl<H: WebSocketHandler + ?Sized> WebSocketHandler for Arc<H> {
+  fn on_open(&self, session: &Session) {
+    (**self).on_open(session)
+  }
+
+  fn on_message(&self, session: &Session, message: Message) {
+    (**self).on_message(session, message)
+  }
+
+  fn on_close(&self, session: &Session) {
+    (**self).on_close(session)
+  }
+}
+
+/// A handle to one WebSocket connection. It can be cloned and kept, e.g. to
+/// push messages from other threads.
+#[derive(Clone)]
+pub struct Session {
+  id: u64,
+  sender: Sender<Message>,
+}
+
+impl Session {
+  fn new(sender: Sender<Message>) -> Self {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    Session {
+      id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+      sender,
+    }
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Queues `message` for the client. Returns `false` once the connection
+  /// is gone.
+  pub fn send(&self, message: Message) -> bool {
+    self.sender.send(message).is_ok()
+  }
+
+  pub fn close(&self, code: u16, reason: &str) -> bool {
+    self.send(Message::Close(Some((code, reason.into()))))
+  }
+}
+
+/// A stream whose read timeout can be changed, so the connection loop can
+/// poll.
+pub trait Connection: Read + Write {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    TcpStream::set_read_timeout(self, timeout)
+  }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    (**self).set_read_timeout(timeout)
+  }
+}
+
+/// Runs `handler` on an upgraded connection until either side closes it or
+/// the server shuts down.
+pub fn serve<S: Connection>(
+  ws: WebSocket<S>,
+  handler: &dyn WebSocketHandler,
+  read_timeout: Duration,
+  shutdown: &AtomicBool,
+) -> io::Result<()> {
+  let (sender, outbox) = mpsc::channel();
+  let session = Session::new(sender);
+  handler.on_open(&session);
+  let result = run(ws, handler, &session, outbox, read_timeout, shutdown);
+  handler.on_close(&session);
+  result
+}
+
+fn run<S: Connection>(
+  mut ws: WebSocket<S>,
+  handler: &dyn WebSocketHandler,
+  session: &Session,
+  outbox: Receiver<Message>,
+  read_timeout: Duration,
+  shutdown: &AtomicBool,
+) -> io::Result<()> {
+  let mut last_seen = Instant::now();
+  let mut pinged = false;
+
+  loop {
+    if shutdown.load(Ordering::SeqCst) && !ws.is_closing() {
+      ws.close(close_code::GOING_AWAY, "server is shutting down")?;
+    }
+    while !ws.is_closing() {
+      match outbox.try_recv() {
+        Ok(message) => ws.send(message)?,
+        Err(_) => break,
+      }
+    }
+
+    ws.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    if !ws.poll()? {
+      let idle = last_seen.elapsed();
+      if idle >= PING_INTERVAL * 2 || (ws.is_closing() && idle >= read_timeout) {
+        // The client is gone, or never answered our close frame.
+        return Ok(());
+      }
+      if idle >= PING_INTERVAL && !pinged && !ws.is_closing() {
+        ws.send(Message::Ping(Vec::new()))?;
+        pinged = true;
+      }
+      continue;
+    }
+
+    ws.get_mut().set_read_timeout(Some(read_timeout))?;
+    let message = match ws.read_message() {
+      Ok(message) => message,
+      Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+      Err(err) => return Err(err),
+    };
+    last_seen = Instant::now();
+    pinged = false;
+    match message {
+      Message::Text(_) | Message::Binary(_) if !ws.is_closing() => {
+        handler.on_message(session, message)
+      }
+      Message::Close(_) => return Ok(()),
+      _ => {}
+    }
+  }
+}
@@ -8,9 +8,9 @@ fn main() {
     let mut stream = stream.unwrap();
     let mut buffer = [0; 2048];
 
-    stream.read(&mut buffer).unwrap();
+    let n = stream.read(&mut buffer).unwrap();
     println!("{:?}", stream);
 
-    stream.write(&mut buffer).unwrap();
+    stream.write_all(&buffer[..n]).unwrap();
   }
 }
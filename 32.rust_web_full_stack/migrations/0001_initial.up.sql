@@ -0,0 +1,41 @@
+create table if not exists teacher (
+  id serial primary key,
+  name varchar(30) not null,
+  profile varchar(140)
+);
+
+create table if not exists course (
+  id serial primary key,
+  teacher_id int not null,
+  name varchar(140) not null,
+  time timestamp default now(),
+  description varchar(2000),
+  format varchar(30),
+  structure varchar(200),
+  duration varchar(30),
+  price int,
+  language varchar(30),
+  level varchar(30)
+);
+
+-- Tables created from the old course.sql have no foreign key yet, and may
+-- hold courses whose teacher is gone. Rather than deleting them behind the
+-- operator's back, the migration stops and names them, so they can be
+-- reassigned or removed by hand before trying again.
+do $$
+declare
+  orphans text;
+begin
+  select string_agg(id::text, ', ' order by id) into orphans
+    from course
+    where not exists (select 1 from teacher where teacher.id = course.teacher_id);
+  if orphans is not null then
+    raise exception 'courses % belong to teachers that no longer exist', orphans;
+  end if;
+end $$;
+
+alter table course
+  add constraint course_teacher_id_fkey foreign key (teacher_id)
+  references teacher (id) on delete cascade;
+
+create index if not exists course_teacher_id_idx on course (teacher_id);
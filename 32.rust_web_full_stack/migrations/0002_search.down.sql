@@ -0,0 +1,2 @@
+drop index if exists teacher_search_idx;
+drop index if exists course_search_idx;
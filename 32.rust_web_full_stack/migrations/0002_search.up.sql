@@ -0,0 +1,7 @@
+-- The expressions must match the ones in the list queries for the indexes
+-- to be used.
+create index if not exists course_search_idx on course
+  using gin (to_tsvector('simple', name || ' ' || coalesce(description, '')));
+
+create index if not exists teacher_search_idx on teacher
+  using gin (to_tsvector('simple', name || ' ' || coalesce(profile, '')));
@@ -0,0 +1,13 @@
+create table if not exists app_user (
+  id serial primary key,
+  username varchar(50) not null unique,
+  password_hash varchar(200) not null,
+  teacher_id int unique references teacher (id) on delete set null
+);
+
+-- Refresh tokens that haven't been used or revoked yet, by their `jti`.
+create table if not exists refresh_token (
+  id varchar(64) primary key,
+  user_id int not null references app_user (id) on delete cascade,
+  expires_at timestamp not null
+);
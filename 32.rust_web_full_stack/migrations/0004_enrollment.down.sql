@@ -0,0 +1,4 @@
+drop table if exists enrollment;
+alter table app_user drop column if exists student_id;
+drop table if exists student;
+alter table course drop column if exists capacity;
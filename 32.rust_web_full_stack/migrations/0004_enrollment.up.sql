@@ -0,0 +1,22 @@
+-- No capacity means no limit.
+alter table course add column if not exists capacity int check (capacity > 0);
+
+create table if not exists student (
+  id serial primary key,
+  name varchar(30) not null
+);
+
+alter table app_user
+  add column if not exists student_id int unique references student (id) on delete set null;
+
+create table if not exists enrollment (
+  id serial primary key,
+  course_id int not null references course (id) on delete cascade,
+  student_id int not null references student (id) on delete cascade,
+  status varchar(20) not null check (status in ('enrolled', 'waitlisted', 'dropped')),
+  created_at timestamp not null default now(),
+  updated_at timestamp not null default now(),
+  unique (course_id, student_id)
+);
+
+create index if not exists enrollment_student_id_idx on enrollment (student_id);
@@ -0,0 +1,6 @@
+drop table if exists review;
+alter table course
+  drop column if exists average_rating,
+  drop column if exists rating_total,
+  drop column if exists review_count;
+alter table app_user drop column if exists is_moderator;
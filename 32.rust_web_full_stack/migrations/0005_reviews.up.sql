@@ -0,0 +1,27 @@
+-- Moderators are appointed by hand, with
+-- `update app_user set is_moderator = true where username = '...'`.
+alter table app_user add column if not exists is_moderator boolean not null default false;
+
+-- Totals of the course's visible reviews, kept up to date as reviews are
+-- posted, hidden and shown again.
+alter table course
+  add column if not exists review_count int not null default 0,
+  add column if not exists rating_total int not null default 0,
+  add column if not exists average_rating double precision
+    generated always as (rating_total::double precision / nullif(review_count, 0)) stored;
+
+create table if not exists review (
+  id serial primary key,
+  course_id int not null references course (id) on delete cascade,
+  student_id int not null references student (id) on delete cascade,
+  rating int not null check (rating between 1 and 5),
+  body varchar(2000) not null,
+  reply varchar(2000),
+  replied_at timestamp,
+  hidden_at timestamp,
+  hidden_reason varchar(200),
+  created_at timestamp not null default now(),
+  unique (course_id, student_id)
+);
+
+create index if not exists review_course_id_idx on review (course_id, created_at);
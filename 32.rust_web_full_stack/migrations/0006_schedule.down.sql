@@ -0,0 +1,3 @@
+drop table if exists teacher_availability;
+drop table if exists course_session;
+drop table if exists course_schedule;
@@ -0,0 +1,34 @@
+create table if not exists course_schedule (
+  id serial primary key,
+  course_id int not null references course (id) on delete cascade,
+  time_zone varchar(64) not null,
+  -- The first session's wall-clock start in `time_zone`.
+  starts_at timestamp not null,
+  duration_minutes int not null check (duration_minutes between 1 and 720),
+  repeat varchar(10) not null check (repeat in ('none', 'daily', 'weekly')),
+  occurrences int not null check (occurrences between 1 and 200)
+);
+
+-- One row per occurrence of a schedule, in UTC.
+create table if not exists course_session (
+  id serial primary key,
+  schedule_id int not null references course_schedule (id) on delete cascade,
+  course_id int not null references course (id) on delete cascade,
+  starts_at timestamp not null,
+  ends_at timestamp not null,
+  check (ends_at > starts_at)
+);
+
+create index if not exists course_session_course_id_idx on course_session (course_id, starts_at);
+
+create table if not exists teacher_availability (
+  id serial primary key,
+  teacher_id int not null references teacher (id) on delete cascade,
+  time_zone varchar(64) not null,
+  weekday int not null check (weekday between 1 and 7),
+  start_time time not null,
+  end_time time not null,
+  check (end_time > start_time)
+);
+
+create index if not exists teacher_availability_teacher_id_idx on teacher_availability (teacher_id);
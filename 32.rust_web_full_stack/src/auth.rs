@@ -0,0 +1,258 @@
+use crate::db_access::UserRepository;
+use crate::error::MyError;
+use crate::models::user::User;
+use crate::state::AppState;
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{web, FromRequest, HttpRequest};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::future::{ready, Ready};
+use std::sync::LazyLock;
+
+pub const ACCESS_TOKEN_SECS: i64 = 15 * 60;
+pub const REFRESH_TOKEN_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// Hashes `password` with Argon2 and a random salt, in PHC string format.
+/// This is slow on purpose, so call it from `web::block`.
+pub fn hash_password(password: &str) -> Result<String, MyError> {
+  let salt = SaltString::generate(&mut OsRng);
+  Argon2::default()
+    .hash_password(password.as_bytes(), &salt)
+    .map(|hash| hash.to_string())
+    .map_err(|err| MyError::internal(format!("Failed to hash password: {}", err)))
+}
+
+/// A hash of no one's password, with the same parameters as real ones.
+static DUMMY_HASH: LazyLock<String> =
+  LazyLock::new(|| hash_password("no such user").expect("hashing a fixed password"));
+
+/// Checks `password` against the hash of `user`, or against a dummy hash if
+/// there is no such user, so an unknown username takes as long as a wrong
+/// password. Call it from `web::block` too.
+pub fn verify_user_password(password: &str, user: Option<&User>) -> bool {
+  match user {
+    Some(user) => verify_password(password, &user.password_hash),
+    None => {
+      verify_password(password, &DUMMY_HASH);
+      false
+    }
+  }
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+  match PasswordHash::new(hash) {
+    Ok(hash) => Argon2::default()
+      .verify_password(password.as_bytes(), &hash)
+      .is_ok(),
+    Err(_) => false,
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+  Access,
+  Refresh,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+  /// The user id.
+  pub sub: String,
+  pub exp: i64,
+  /// Lets a refresh token be used only once.
+  pub jti: String,
+  pub kind: TokenKind,
+}
+
+impl Claims {
+  pub fn user_id(&self) -> Result<i32, MyError> {
+    self
+      .sub
+      .parse()
+      .map_err(|_| MyError::Unauthorized("Invalid token".into()))
+  }
+
+  pub fn expires_at(&self) -> NaiveDateTime {
+    Utc
+      .timestamp_opt(self.exp, 0)
+      .single()
+      .unwrap_or_else(Utc::now)
+      .naive_utc()
+  }
+}
+
+/// Signs and checks tokens with the `JWT_SECRET`.
+pub struct Keys {
+  encoding: EncodingKey,
+  decoding: DecodingKey,
+}
+
+impl Keys {
+  pub fn new(secret: &[u8]) -> Self {
+    Keys {
+      encoding: EncodingKey::from_secret(secret),
+      decoding: DecodingKey::from_secret(secret),
+    }
+  }
+
+  /// Returns a signed token for `user_id` along with its claims.
+  pub fn issue(&self, user_id: i32, kind: TokenKind) -> Result<(String, Claims), MyError> {
+    let ttl = match kind {
+      TokenKind::Access => ACCESS_TOKEN_SECS,
+      TokenKind::Refresh => REFRESH_TOKEN_SECS,
+    };
+    let claims = Claims {
+      sub: user_id.to_string(),
+      exp: Utc::now().timestamp() + ttl,
+      jti: random_id(),
+      kind,
+    };
+    let token = jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+      .map_err(|err| MyError::internal_with("Failed to sign token", err))?;
+    Ok((token, claims))
+  }
+
+  /// Checks the signature and expiry of `token`, and that it is a `kind`
+  /// token.
+  pub fn verify(&self, token: &str, kind: TokenKind) -> Result<Claims, MyError> {
+    let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::default())
+      .map_err(|err| MyError::Unauthorized(format!("Invalid token: {}", err)))?
+      .claims;
+    if claims.kind != kind {
+      return Err(MyError::Unauthorized("Invalid token: wrong kind".into()));
+    }
+    Ok(claims)
+  }
+}
+
+fn random_id() -> String {
+  let mut bytes = [0u8; 16];
+  OsRng.fill_bytes(&mut bytes);
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The signed-in user, from an `Authorization: Bearer` access token.
+/// Handlers that take one reject anonymous requests with `401`.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUser {
+  pub id: i32,
+}
+
+impl AuthUser {
+  pub async fn user(&self, app_state: &AppState) -> Result<User, MyError> {
+    match app_state.db.get_user(self.id).await {
+      Err(MyError::NotFound(_)) => Err(MyError::Unauthorized("User no longer exists".into())),
+      result => result,
+    }
+  }
+
+  /// The teacher this user owns, `403` if there is none.
+  pub async fn teacher_id(&self, app_state: &AppState) -> Result<i32, MyError> {
+    self
+      .user(app_state)
+      .await?
+      .teacher_id
+      .ok_or_else(|| MyError::Forbidden("Create a teacher profile first".into()))
+  }
+
+  /// The student this user owns, `403` if there is none.
+  pub async fn student_id(&self, app_state: &AppState) -> Result<i32, MyError> {
+    self
+      .user(app_state)
+      .await?
+      .student_id
+      .ok_or_else(|| MyError::Forbidden("Create a student profile first".into()))
+  }
+
+  /// Checks that this user owns `student_id`.
+  pub async fn check_is_student(
+    &self,
+    app_state: &AppState,
+    student_id: i32,
+  ) -> Result<(), MyError> {
+    match self.student_id(app_state).await? == student_id {
+      true => Ok(()),
+      false => Err(MyError::Forbidden(
+        "You can only see your own student profile and enrollments".into(),
+      )),
+    }
+  }
+
+  pub async fn check_moderator(&self, app_state: &AppState) -> Result<(), MyError> {
+    match self.user(app_state).await?.is_moderator {
+      true => Ok(()),
+      false => Err(MyError::Forbidden("Only moderators can do this".into())),
+    }
+  }
+
+  /// Checks that this user owns `teacher_id`.
+  pub async fn check_owns(&self, app_state: &AppState, teacher_id: i32) -> Result<(), MyError> {
+    match self.teacher_id(app_state).await? == teacher_id {
+      true => Ok(()),
+      false => Err(MyError::Forbidden(
+        "You can only change your own teacher profile and courses".into(),
+      )),
+    }
+  }
+}
+
+impl FromRequest for AuthUser {
+  type Error = MyError;
+  type Future = Ready<Result<Self, Self::Error>>;
+
+  fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+    ready(authenticate(req))
+  }
+}
+
+fn authenticate(req: &HttpRequest) -> Result<AuthUser, MyError> {
+  let app_state = req
+    .app_data::<web::Data<AppState>>()
+    .ok_or_else(|| MyError::internal("AppState is not configured"))?;
+  let token = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "))
+    .ok_or_else(|| MyError::Unauthorized("Sign in to do this".into()))?;
+  let claims = app_state.keys.verify(token.trim(), TokenKind::Access)?;
+  Ok(AuthUser {
+    id: claims.user_id()?,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_password_hash() {
+    let hash = hash_password("correct horse").unwrap();
+    assert!(hash.starts_with("$argon2"));
+    assert!(verify_password("correct horse", &hash));
+    assert!(!verify_password("battery staple", &hash));
+    assert!(!verify_password("correct horse", "not a hash"));
+    assert!(!verify_user_password("no such user", None));
+    assert!(DUMMY_HASH.starts_with("$argon2"));
+  }
+
+  #[test]
+  fn test_tokens() {
+    let keys = Keys::new(b"secret");
+    let (token, claims) = keys.issue(7, TokenKind::Access).unwrap();
+
+    let verified = keys.verify(&token, TokenKind::Access).unwrap();
+    assert_eq!(verified.user_id().unwrap(), 7);
+    assert_eq!(verified.jti, claims.jti);
+    assert!(keys.verify(&token, TokenKind::Refresh).is_err());
+    assert!(Keys::new(b"other")
+      .verify(&token, TokenKind::Access)
+      .is_err());
+  }
+}
@@ -0,0 +1,108 @@
+use chrono::{NaiveDateTime, Utc};
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+/// An iCalendar `VEVENT`, with times in UTC.
+pub struct Event<'a> {
+  /// Must stay the same across exports, so calendar apps update the event
+  /// instead of adding it again.
+  pub uid: String,
+  pub starts_at: NaiveDateTime,
+  pub ends_at: NaiveDateTime,
+  pub summary: &'a str,
+  pub description: Option<&'a str>,
+}
+
+/// Writes an iCalendar (RFC 5545) document named `name` with `events`.
+pub fn calendar(name: &str, events: &[Event]) -> String {
+  let stamp = format_time(Utc::now().naive_utc());
+  let mut lines = vec![
+    "BEGIN:VCALENDAR".to_string(),
+    "VERSION:2.0".to_string(),
+    "PRODID:-//rust-web-full-stack//tutor service//ZH".to_string(),
+    "CALSCALE:GREGORIAN".to_string(),
+    format!("X-WR-CALNAME:{}", escape(name)),
+  ];
+  for event in events {
+    lines.push("BEGIN:VEVENT".into());
+    lines.push(format!("UID:{}", event.uid));
+    lines.push(format!("DTSTAMP:{}", stamp));
+    lines.push(format!("DTSTART:{}", format_time(event.starts_at)));
+    lines.push(format!("DTEND:{}", format_time(event.ends_at)));
+    lines.push(format!("SUMMARY:{}", escape(event.summary)));
+    if let Some(description) = event.description {
+      lines.push(format!("DESCRIPTION:{}", escape(description)));
+    }
+    lines.push("END:VEVENT".into());
+  }
+  lines.push("END:VCALENDAR".into());
+
+  lines.iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+fn format_time(time: NaiveDateTime) -> String {
+  time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '\\' | ';' | ',' => {
+        escaped.push('\\');
+        escaped.push(c);
+      }
+      '\n' => escaped.push_str("\\n"),
+      '\r' => {}
+      _ => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// Splits `line` into lines of at most 75 bytes, continued with a leading
+/// space, without splitting a character.
+fn fold(line: &str) -> String {
+  let mut folded = String::with_capacity(line.len());
+  let mut width = 0;
+  for c in line.chars() {
+    if width + c.len_utf8() > 75 {
+      folded.push_str("\r\n ");
+      width = 1;
+    }
+    folded.push(c);
+    width += c.len_utf8();
+  }
+  folded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_calendar() {
+    let at = |text| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+    let events = [Event {
+      uid: "session-1@tutor-service".into(),
+      starts_at: at("2026-10-26 11:00"),
+      ends_at: at("2026-10-26 12:30"),
+      summary: "Rust 入门; 第一课, 所有权",
+      description: None,
+    }];
+    let ics = calendar("Li Ming", &events);
+
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    assert!(ics.contains("\r\nDTSTART:20261026T110000Z\r\n"));
+    assert!(ics.contains("\r\nSUMMARY:Rust 入门\\; 第一课\\, 所有权\r\n"));
+  }
+
+  #[test]
+  fn test_fold() {
+    let line = format!("DESCRIPTION:{}", "课".repeat(30));
+    let folded = fold(&line);
+    assert!(folded.split("\r\n").all(|part| part.len() <= 75));
+    assert_eq!(folded.replace("\r\n ", ""), line);
+  }
+}
@@ -1,32 +1,65 @@
+use super::enrollment::db_fill_places;
+use super::CourseRepository;
 use crate::error::MyError;
-use crate::models::course::*;
+use crate::models::{course::*, Page, Paged, Sort};
+use async_trait::async_trait;
 use sqlx::postgres::PgPool;
 
-pub async fn db_get_courses(pool: &PgPool) -> Result<Vec<Course>, MyError> {
-  let record = sqlx::query_as!(Course, "select * from course")
+/// Conditions that are `null` match every row. The search uses the same
+/// expression as `course_search_idx`, so it can use the index.
+const COURSE_FILTER: &str = r#"
+  ($1::int is null or teacher_id = $1)
+  and ($2::varchar is null or language = $2)
+  and ($3::varchar is null or level = $3)
+  and ($4::varchar is null or format = $4)
+  and ($5::int is null or price >= $5)
+  and ($6::int is null or price <= $6)
+  and ($7::text is null or
+    to_tsvector('simple', name || ' ' || coalesce(description, ''))
+    @@ plainto_tsquery('simple', $7))"#;
+
+pub async fn db_get_courses(
+  pool: &PgPool,
+  filter: CourseFilter,
+  sort: Sort,
+  page: Page,
+) -> Result<Paged<Course>, MyError> {
+  let sql = format!(
+    "select * from course where {} {} limit $8 offset $9",
+    COURSE_FILTER,
+    sort.order_by()
+  );
+  let items = sqlx::query_as::<_, Course>(&sql)
+    .bind(filter.teacher_id)
+    .bind(&filter.language)
+    .bind(&filter.level)
+    .bind(&filter.format)
+    .bind(filter.min_price)
+    .bind(filter.max_price)
+    .bind(&filter.search)
+    .bind(page.size)
+    .bind(page.offset())
     .fetch_all(pool)
     .await?;
 
-  Ok(record)
-}
-
-pub async fn db_get_courses_of_teacher(
-  pool: &PgPool,
-  teacher_id: i32,
-) -> Result<Vec<Course>, MyError> {
-  let record = sqlx::query_as!(
-    Course,
-    "select * from course where teacher_id = $1",
-    teacher_id
-  )
-  .fetch_all(pool)
-  .await?;
+  let sql = format!("select count(*) from course where {}", COURSE_FILTER);
+  let (total,) = sqlx::query_as::<_, (i64,)>(&sql)
+    .bind(filter.teacher_id)
+    .bind(filter.language)
+    .bind(filter.level)
+    .bind(filter.format)
+    .bind(filter.min_price)
+    .bind(filter.max_price)
+    .bind(filter.search)
+    .fetch_one(pool)
+    .await?;
 
-  Ok(record)
+  Ok(Paged { items, total })
 }
 
 pub async fn db_get_course(pool: &PgPool, id: i32) -> Result<Course, MyError> {
-  let record = sqlx::query_as!(Course, "select * from course where id = $1", id,)
+  let record = sqlx::query_as::<_, Course>("select * from course where id = $1")
+    .bind(id)
     .fetch_optional(pool)
     .await?;
 
@@ -40,22 +73,22 @@ pub async fn db_create_course(
   pool: &PgPool,
   new_course: CreateCourseDTO,
 ) -> Result<Course, MyError> {
-  let record = sqlx::query_as!(
-    Course,
-    r#"insert into course 
-     (teacher_id, name, description, format, structure, duration, price, language, level)
-     values ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+  let record = sqlx::query_as::<_, Course>(
+    r#"insert into course
+     (teacher_id, name, description, format, structure, duration, price, language, level, capacity)
+     values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
      returning *"#,
-    new_course.teacher_id,
-    new_course.name,
-    new_course.description,
-    new_course.format,
-    new_course.structure,
-    new_course.duration,
-    new_course.price,
-    new_course.language,
-    new_course.level
   )
+  .bind(new_course.teacher_id)
+  .bind(new_course.name)
+  .bind(new_course.description)
+  .bind(new_course.format)
+  .bind(new_course.structure)
+  .bind(new_course.duration)
+  .bind(new_course.price)
+  .bind(new_course.language)
+  .bind(new_course.level)
+  .bind(new_course.capacity)
   .fetch_one(pool)
   .await?;
 
@@ -63,42 +96,93 @@ pub async fn db_create_course(
 }
 
 pub async fn db_remove_course(pool: &PgPool, id: i32) -> Result<String, MyError> {
-  let record = sqlx::query!("delete from course where id = $1", id)
+  let record = sqlx::query("delete from course where id = $1")
+    .bind(id)
     .execute(pool)
     .await?;
 
-  Ok(format!("Delete {:?} courese", record))
+  match record.rows_affected() {
+    0 => Err(MyError::NotFound("Course not found".into())),
+    rows => Ok(format!("Deleted {} course", rows)),
+  }
 }
 
+/// Updates only the columns present in `dto`: every column is paired with a
+/// flag saying whether to overwrite it. Places freed by a larger capacity go
+/// to the waitlist.
 pub async fn db_update_course(
   pool: &PgPool,
-
   id: i32,
   dto: UpdateCourseDTO,
 ) -> Result<Course, MyError> {
-  sqlx::query_as!(Course, "select * from course where id = $1", id)
-    .fetch_one(pool)
-    .await
-    .map_err(|_| MyError::NotFound("Course not found".into()))?;
-
-  let record = sqlx::query_as!(
-    Course,
-    r#"update course set name = $1, description = $2, format = $3,
-     structure = $4, duration = $5, price = $6, language = $7, level = $8
-     where id = $9
+  let mut tx = pool.begin().await?;
+  let record = sqlx::query_as::<_, Course>(
+    r#"update course set
+     name = case when $1 then $2 else name end,
+     description = case when $3 then $4 else description end,
+     format = case when $5 then $6 else format end,
+     structure = case when $7 then $8 else structure end,
+     duration = case when $9 then $10 else duration end,
+     price = case when $11 then $12 else price end,
+     language = case when $13 then $14 else language end,
+     level = case when $15 then $16 else level end,
+     capacity = case when $17 then $18 else capacity end
+     where id = $19
      returning *"#,
-    dto.name,
-    dto.description,
-    dto.format,
-    dto.structure,
-    dto.duration,
-    dto.price,
-    dto.language,
-    dto.level,
-    id
   )
-  .fetch_one(pool)
+  .bind(dto.name.is_some())
+  .bind(dto.name)
+  .bind(dto.description.is_some())
+  .bind(dto.description.flatten())
+  .bind(dto.format.is_some())
+  .bind(dto.format.flatten())
+  .bind(dto.structure.is_some())
+  .bind(dto.structure.flatten())
+  .bind(dto.duration.is_some())
+  .bind(dto.duration.flatten())
+  .bind(dto.price.is_some())
+  .bind(dto.price.flatten())
+  .bind(dto.language.is_some())
+  .bind(dto.language.flatten())
+  .bind(dto.level.is_some())
+  .bind(dto.level.flatten())
+  .bind(dto.capacity.is_some())
+  .bind(dto.capacity.flatten())
+  .bind(id)
+  .fetch_optional(&mut tx)
   .await?;
 
+  let record = record.ok_or_else(|| MyError::NotFound("Course not found".into()))?;
+  db_fill_places(&mut tx, id).await?;
+  tx.commit().await?;
+
   Ok(record)
 }
+
+#[async_trait]
+impl CourseRepository for PgPool {
+  async fn create_course(&self, dto: CreateCourseDTO) -> Result<Course, MyError> {
+    db_create_course(self, dto).await
+  }
+
+  async fn get_courses(
+    &self,
+    filter: CourseFilter,
+    sort: Sort,
+    page: Page,
+  ) -> Result<Paged<Course>, MyError> {
+    db_get_courses(self, filter, sort, page).await
+  }
+
+  async fn get_course(&self, id: i32) -> Result<Course, MyError> {
+    db_get_course(self, id).await
+  }
+
+  async fn remove_course(&self, id: i32) -> Result<String, MyError> {
+    db_remove_course(self, id).await
+  }
+
+  async fn update_course(&self, id: i32, dto: UpdateCourseDTO) -> Result<Course, MyError> {
+    db_update_course(self, id, dto).await
+  }
+}
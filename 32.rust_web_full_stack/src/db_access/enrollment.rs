@@ -0,0 +1,193 @@
+use super::EnrollmentRepository;
+use crate::error::MyError;
+use crate::models::student::*;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+use sqlx::{Postgres, Transaction};
+
+/// Locks the course row for the rest of `tx` and returns its capacity.
+/// Enrollments in the same course take turns this way, so two of them can't
+/// both take the last place.
+async fn lock_course(
+  tx: &mut Transaction<'_, Postgres>,
+  course_id: i32,
+) -> Result<Option<i32>, MyError> {
+  let record =
+    sqlx::query_as::<_, (Option<i32>,)>("select capacity from course where id = $1 for update")
+      .bind(course_id)
+      .fetch_optional(&mut *tx)
+      .await?;
+
+  match record {
+    Some((capacity,)) => Ok(capacity),
+    None => Err(MyError::NotFound("Course not found".into())),
+  }
+}
+
+/// Moves students from the waitlist into free places, longest waiting
+/// first. Call it with the course locked.
+pub async fn db_fill_places(
+  tx: &mut Transaction<'_, Postgres>,
+  course_id: i32,
+) -> Result<(), MyError> {
+  sqlx::query(
+    r#"update enrollment set status = 'enrolled', updated_at = now()
+     where id in (
+       select id from enrollment
+       where course_id = $1 and status = 'waitlisted'
+       order by updated_at, id
+       limit greatest(
+         coalesce((select capacity from course where id = $1), 2147483647)
+         - (select count(*) from enrollment where course_id = $1 and status = 'enrolled'),
+         0
+       )
+     )"#,
+  )
+  .bind(course_id)
+  .execute(&mut *tx)
+  .await?;
+
+  Ok(())
+}
+
+pub async fn db_enroll(
+  pool: &PgPool,
+  course_id: i32,
+  student_id: i32,
+) -> Result<Enrollment, MyError> {
+  let mut tx = pool.begin().await?;
+  let capacity = lock_course(&mut tx, course_id).await?;
+
+  let existing = sqlx::query_as::<_, (String,)>(
+    "select status from enrollment where course_id = $1 and student_id = $2",
+  )
+  .bind(course_id)
+  .bind(student_id)
+  .fetch_optional(&mut tx)
+  .await?;
+  if let Some((status,)) = existing {
+    if status != DROPPED {
+      return Err(MyError::Conflict(format!(
+        "Already {} in this course",
+        status
+      )));
+    }
+  }
+
+  let (enrolled,) = sqlx::query_as::<_, (i64,)>(
+    "select count(*) from enrollment where course_id = $1 and status = 'enrolled'",
+  )
+  .bind(course_id)
+  .fetch_one(&mut tx)
+  .await?;
+  let status = match capacity {
+    Some(capacity) if enrolled >= i64::from(capacity) => WAITLISTED,
+    _ => ENROLLED,
+  };
+
+  let record = sqlx::query_as::<_, Enrollment>(
+    r#"insert into enrollment (course_id, student_id, status)
+     values ($1, $2, $3)
+     on conflict (course_id, student_id)
+     do update set status = excluded.status, updated_at = now()
+     returning *"#,
+  )
+  .bind(course_id)
+  .bind(student_id)
+  .bind(status)
+  .fetch_one(&mut tx)
+  .await?;
+  tx.commit().await?;
+
+  Ok(record)
+}
+
+/// Drops the student from the course or its waitlist. A freed place goes to
+/// the waitlist.
+pub async fn db_drop_enrollment(
+  pool: &PgPool,
+  course_id: i32,
+  student_id: i32,
+) -> Result<Enrollment, MyError> {
+  let mut tx = pool.begin().await?;
+  lock_course(&mut tx, course_id).await?;
+
+  let record = sqlx::query_as::<_, Enrollment>(
+    r#"update enrollment set status = 'dropped', updated_at = now()
+     where course_id = $1 and student_id = $2 and status <> 'dropped'
+     returning *"#,
+  )
+  .bind(course_id)
+  .bind(student_id)
+  .fetch_optional(&mut tx)
+  .await?;
+  let record = record.ok_or_else(|| MyError::NotFound("Enrollment not found".into()))?;
+
+  db_fill_places(&mut tx, course_id).await?;
+  tx.commit().await?;
+
+  Ok(record)
+}
+
+pub async fn db_get_enrollments_of_course(
+  pool: &PgPool,
+  course_id: i32,
+  status: Option<String>,
+) -> Result<Vec<Enrollment>, MyError> {
+  let record = sqlx::query_as::<_, Enrollment>(
+    r#"select * from enrollment
+     where course_id = $1 and ($2::varchar is null or status = $2)
+     order by updated_at, id"#,
+  )
+  .bind(course_id)
+  .bind(status)
+  .fetch_all(pool)
+  .await?;
+
+  Ok(record)
+}
+
+pub async fn db_get_enrollments_of_student(
+  pool: &PgPool,
+  student_id: i32,
+  status: Option<String>,
+) -> Result<Vec<Enrollment>, MyError> {
+  let record = sqlx::query_as::<_, Enrollment>(
+    r#"select * from enrollment
+     where student_id = $1 and ($2::varchar is null or status = $2)
+     order by updated_at, id"#,
+  )
+  .bind(student_id)
+  .bind(status)
+  .fetch_all(pool)
+  .await?;
+
+  Ok(record)
+}
+
+#[async_trait]
+impl EnrollmentRepository for PgPool {
+  async fn enroll(&self, course_id: i32, student_id: i32) -> Result<Enrollment, MyError> {
+    db_enroll(self, course_id, student_id).await
+  }
+
+  async fn drop_enrollment(&self, course_id: i32, student_id: i32) -> Result<Enrollment, MyError> {
+    db_drop_enrollment(self, course_id, student_id).await
+  }
+
+  async fn get_enrollments_of_course(
+    &self,
+    course_id: i32,
+    status: Option<String>,
+  ) -> Result<Vec<Enrollment>, MyError> {
+    db_get_enrollments_of_course(self, course_id, status).await
+  }
+
+  async fn get_enrollments_of_student(
+    &self,
+    student_id: i32,
+    status: Option<String>,
+  ) -> Result<Vec<Enrollment>, MyError> {
+    db_get_enrollments_of_student(self, student_id, status).await
+  }
+}
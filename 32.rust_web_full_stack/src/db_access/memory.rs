@@ -0,0 +1,911 @@
+use super::{
+  CourseRepository, EnrollmentRepository, ReviewRepository, ScheduleRepository, StudentRepository,
+  TeacherRepository, UserRepository,
+};
+use crate::error::{constraint_message, MyError};
+use crate::models::{
+  course::*, review::*, schedule::*, student::*, teacher::*, user::*, Page, Paged, Sort,
+};
+use crate::validation::FieldError;
+use async_trait::async_trait;
+use chrono::{Local, NaiveDateTime, Utc};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+/// A backend that keeps the tables in memory, for tests and for trying the
+/// service without Postgres. It follows the Postgres schema's behaviour,
+/// including its foreign key constraint.
+#[derive(Default)]
+pub struct MemoryRepository {
+  tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+  teachers: Vec<Teacher>,
+  courses: Vec<Course>,
+  users: Vec<User>,
+  /// Refresh token ids with their user and expiry.
+  refresh_tokens: HashMap<String, (i32, NaiveDateTime)>,
+  students: Vec<Student>,
+  enrollments: Vec<Enrollment>,
+  reviews: Vec<Review>,
+  schedules: Vec<Schedule>,
+  sessions: Vec<Session>,
+  availability: Vec<Availability>,
+  last_teacher_id: i32,
+  last_course_id: i32,
+  last_user_id: i32,
+  last_student_id: i32,
+  last_enrollment_id: i32,
+  last_review_id: i32,
+  last_schedule_id: i32,
+  last_session_id: i32,
+  last_availability_id: i32,
+}
+
+impl Tables {
+  /// Like [`db_fill_places`](super::enrollment::db_fill_places).
+  fn fill_places(&mut self, course_id: i32) {
+    let capacity = match self.courses.iter().find(|c| c.id == course_id) {
+      Some(course) => course.capacity,
+      None => return,
+    };
+    let enrolled = self
+      .enrollments
+      .iter()
+      .filter(|e| e.course_id == course_id && e.status == ENROLLED)
+      .count();
+    let free = match capacity {
+      Some(capacity) => (capacity as usize).saturating_sub(enrolled),
+      None => usize::MAX,
+    };
+    let mut waiting: Vec<&mut Enrollment> = self
+      .enrollments
+      .iter_mut()
+      .filter(|e| e.course_id == course_id && e.status == WAITLISTED)
+      .collect();
+    waiting.sort_by_key(|e| (e.updated_at, e.id));
+    let now = Local::now().naive_local();
+    for enrollment in waiting.into_iter().take(free) {
+      enrollment.status = ENROLLED.into();
+      enrollment.updated_at = now;
+    }
+  }
+
+  /// `on delete cascade` from `course` to the rows of its students and
+  /// schedules, and from `teacher` to `teacher_availability`.
+  fn remove_orphans(&mut self) {
+    let courses: Vec<i32> = self.courses.iter().map(|c| c.id).collect();
+    self.enrollments.retain(|e| courses.contains(&e.course_id));
+    self.reviews.retain(|r| courses.contains(&r.course_id));
+    self.schedules.retain(|s| courses.contains(&s.course_id));
+    self.sessions.retain(|s| courses.contains(&s.course_id));
+    let teachers: Vec<i32> = self.teachers.iter().map(|t| t.id).collect();
+    self
+      .availability
+      .retain(|a| teachers.contains(&a.teacher_id));
+  }
+
+  fn sessions_of_teacher(&self, teacher_id: i32) -> Vec<Session> {
+    let courses: Vec<i32> = self
+      .courses
+      .iter()
+      .filter(|c| c.teacher_id == teacher_id)
+      .map(|c| c.id)
+      .collect();
+    let mut sessions: Vec<Session> = self
+      .sessions
+      .iter()
+      .filter(|s| courses.contains(&s.course_id))
+      .cloned()
+      .collect();
+    sessions.sort_by_key(|s| (s.starts_at, s.id));
+    sessions
+  }
+
+  fn availability_of(&self, teacher_id: i32) -> Vec<Availability> {
+    let mut windows: Vec<Availability> = self
+      .availability
+      .iter()
+      .filter(|a| a.teacher_id == teacher_id)
+      .cloned()
+      .collect();
+    windows.sort_by_key(|a| (a.weekday, a.start_time, a.id));
+    windows
+  }
+
+  /// Like `count_rating` in [`super::review`], with `average_rating`
+  /// computed as by its generated column.
+  fn count_rating(&mut self, course_id: i32, sign: i32, rating: i32) {
+    if let Some(course) = self.courses.iter_mut().find(|c| c.id == course_id) {
+      course.review_count += sign;
+      course.rating_total += sign * rating;
+      course.average_rating = match course.review_count {
+        0 => None,
+        count => Some(course.rating_total as f64 / count as f64),
+      };
+    }
+  }
+}
+
+impl MemoryRepository {
+  /// Teacher 1 with course 1, owned by user 1 `liming`, and the moderator
+  /// user 2 `admin`, both with password `password`, which the handler tests
+  /// rely on.
+  #[cfg(test)]
+  pub fn fixtures() -> Self {
+    let teacher = Teacher {
+      id: 1,
+      name: "Li Ming".into(),
+      profile: Some("清华大学计算机系讲师".into()),
+    };
+    let course = Course {
+      teacher_id: 1,
+      id: 1,
+      name: "Rust 入门".into(),
+      time: Some(Local::now().naive_local()),
+      description: None,
+      format: None,
+      structure: None,
+      duration: None,
+      price: Some(100),
+      language: Some("中文".into()),
+      level: Some("初级".into()),
+      capacity: None,
+      review_count: 0,
+      average_rating: None,
+      rating_total: 0,
+    };
+    let password_hash = crate::auth::hash_password("password").unwrap();
+    let user = User {
+      id: 1,
+      username: "liming".into(),
+      password_hash: password_hash.clone(),
+      teacher_id: Some(1),
+      student_id: None,
+      is_moderator: false,
+    };
+    let moderator = User {
+      id: 2,
+      username: "admin".into(),
+      password_hash,
+      teacher_id: None,
+      student_id: None,
+      is_moderator: true,
+    };
+    let tables = Tables {
+      teachers: vec![teacher],
+      courses: vec![course],
+      users: vec![user, moderator],
+      last_teacher_id: 1,
+      last_course_id: 1,
+      last_user_id: 2,
+      ..Tables::default()
+    };
+    MemoryRepository {
+      tables: Mutex::new(tables),
+    }
+  }
+
+  fn tables(&self) -> MutexGuard<'_, Tables> {
+    self.tables.lock().unwrap_or_else(|e| e.into_inner())
+  }
+}
+
+/// Like `to_tsvector('simple', text) @@ plainto_tsquery('simple', search)`:
+/// every word of `search` is one of the words of `text`.
+fn matches(text: &str, search: &str) -> bool {
+  let words: Vec<String> = words(text).collect();
+  words(search).all(|word| words.contains(&word))
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+  text
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(str::to_lowercase)
+}
+
+/// Orders like [`Sort::order_by`], with `None` last in either direction.
+fn compare<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+  match (a, b) {
+    (Some(a), Some(b)) if descending => b.cmp(&a),
+    (Some(a), Some(b)) => a.cmp(&b),
+    (a, b) => a.is_none().cmp(&b.is_none()),
+  }
+}
+
+fn paginate<T>(rows: Vec<T>, page: Page) -> Paged<T> {
+  let total = rows.len() as i64;
+  let items = rows
+    .into_iter()
+    .skip(page.offset() as usize)
+    .take(page.size as usize)
+    .collect();
+  Paged { items, total }
+}
+
+/// The error Postgres would give for a violated foreign key.
+fn constraint_error(constraint: &str) -> MyError {
+  MyError::Unprocessable(
+    constraint_message(Some(constraint))
+      .unwrap_or_default()
+      .into(),
+  )
+}
+
+#[async_trait]
+impl TeacherRepository for MemoryRepository {
+  async fn create_teacher(&self, user_id: i32, dto: CreateTeacherDTO) -> Result<Teacher, MyError> {
+    let mut tables = self.tables();
+    let teacher_id = tables.last_teacher_id + 1;
+    match tables.users.iter_mut().find(|u| u.id == user_id) {
+      Some(user) if user.teacher_id.is_none() => user.teacher_id = Some(teacher_id),
+      _ => {
+        return Err(MyError::Forbidden(
+          "You already have a teacher profile".into(),
+        ))
+      }
+    }
+    tables.last_teacher_id = teacher_id;
+    let teacher = Teacher {
+      id: tables.last_teacher_id,
+      name: dto.name,
+      profile: dto.profile,
+    };
+    tables.teachers.push(teacher.clone());
+    Ok(teacher)
+  }
+
+  async fn get_teachers(
+    &self,
+    search: Option<String>,
+    sort: Sort,
+    page: Page,
+  ) -> Result<Paged<Teacher>, MyError> {
+    let mut teachers: Vec<Teacher> = self
+      .tables()
+      .teachers
+      .iter()
+      .filter(|t| match &search {
+        Some(search) => {
+          let text = format!("{} {}", t.name, t.profile.as_deref().unwrap_or_default());
+          matches(&text, search)
+        }
+        None => true,
+      })
+      .cloned()
+      .collect();
+    teachers.sort_by(|a, b| {
+      let order = match sort.column {
+        "name" => compare(Some(&a.name), Some(&b.name), sort.descending),
+        _ => compare(Some(a.id), Some(b.id), sort.descending),
+      };
+      order.then(a.id.cmp(&b.id))
+    });
+    Ok(paginate(teachers, page))
+  }
+
+  async fn get_teacher(&self, id: i32) -> Result<Teacher, MyError> {
+    self
+      .tables()
+      .teachers
+      .iter()
+      .find(|t| t.id == id)
+      .cloned()
+      .ok_or_else(|| MyError::NotFound("Teacher not found".into()))
+  }
+
+  async fn remove_teacher(&self, id: i32) -> Result<String, MyError> {
+    let mut tables = self.tables();
+    let before = tables.teachers.len();
+    tables.teachers.retain(|t| t.id != id);
+    match before - tables.teachers.len() {
+      0 => Err(MyError::NotFound("Teacher not found".into())),
+      rows => {
+        // `on delete cascade`
+        tables.courses.retain(|c| c.teacher_id != id);
+        tables.remove_orphans();
+        // `on delete set null`
+        for user in tables.users.iter_mut().filter(|u| u.teacher_id == Some(id)) {
+          user.teacher_id = None;
+        }
+        Ok(format!("Deleted {} teacher", rows))
+      }
+    }
+  }
+
+  async fn update_teacher(&self, id: i32, dto: UpdateTeacherDTO) -> Result<Teacher, MyError> {
+    let mut tables = self.tables();
+    let teacher = tables
+      .teachers
+      .iter_mut()
+      .find(|t| t.id == id)
+      .ok_or_else(|| MyError::NotFound("Teacher not found".into()))?;
+    if let Some(name) = dto.name {
+      teacher.name = name;
+    }
+    if let Some(profile) = dto.profile {
+      teacher.profile = profile;
+    }
+    Ok(teacher.clone())
+  }
+}
+
+#[async_trait]
+impl CourseRepository for MemoryRepository {
+  async fn create_course(&self, dto: CreateCourseDTO) -> Result<Course, MyError> {
+    let mut tables = self.tables();
+    if !tables.teachers.iter().any(|t| t.id == dto.teacher_id) {
+      return Err(constraint_error("course_teacher_id_fkey"));
+    }
+    tables.last_course_id += 1;
+    let course = Course {
+      teacher_id: dto.teacher_id,
+      id: tables.last_course_id,
+      name: dto.name,
+      time: Some(Local::now().naive_local()),
+      description: dto.description,
+      format: dto.format,
+      structure: dto.structure,
+      duration: dto.duration,
+      price: dto.price,
+      language: dto.language,
+      level: dto.level,
+      capacity: dto.capacity,
+      review_count: 0,
+      average_rating: None,
+      rating_total: 0,
+    };
+    tables.courses.push(course.clone());
+    Ok(course)
+  }
+
+  async fn get_courses(
+    &self,
+    filter: CourseFilter,
+    sort: Sort,
+    page: Page,
+  ) -> Result<Paged<Course>, MyError> {
+    let same = |value: &Option<String>, wanted: &Option<String>| match wanted {
+      Some(wanted) => value.as_ref() == Some(wanted),
+      None => true,
+    };
+    let mut courses: Vec<Course> = self
+      .tables()
+      .courses
+      .iter()
+      .filter(|c| filter.teacher_id.is_none_or(|id| c.teacher_id == id))
+      .filter(|c| same(&c.language, &filter.language))
+      .filter(|c| same(&c.level, &filter.level))
+      .filter(|c| same(&c.format, &filter.format))
+      .filter(|c| filter.min_price.is_none_or(|min| c.price >= Some(min)))
+      .filter(|c| {
+        filter
+          .max_price
+          .is_none_or(|max| c.price.is_some_and(|price| price <= max))
+      })
+      .filter(|c| match &filter.search {
+        Some(search) => {
+          let text = format!(
+            "{} {}",
+            c.name,
+            c.description.as_deref().unwrap_or_default()
+          );
+          matches(&text, search)
+        }
+        None => true,
+      })
+      .cloned()
+      .collect();
+    courses.sort_by(|a, b| {
+      let order = match sort.column {
+        "name" => compare(Some(&a.name), Some(&b.name), sort.descending),
+        "price" => compare(a.price, b.price, sort.descending),
+        "time" => compare(a.time, b.time, sort.descending),
+        _ => compare(Some(a.id), Some(b.id), sort.descending),
+      };
+      order.then(a.id.cmp(&b.id))
+    });
+    Ok(paginate(courses, page))
+  }
+
+  async fn get_course(&self, id: i32) -> Result<Course, MyError> {
+    self
+      .tables()
+      .courses
+      .iter()
+      .find(|c| c.id == id)
+      .cloned()
+      .ok_or_else(|| MyError::NotFound("Course not found".into()))
+  }
+
+  async fn remove_course(&self, id: i32) -> Result<String, MyError> {
+    let mut tables = self.tables();
+    let before = tables.courses.len();
+    tables.courses.retain(|c| c.id != id);
+    match before - tables.courses.len() {
+      0 => Err(MyError::NotFound("Course not found".into())),
+      rows => {
+        tables.remove_orphans();
+        Ok(format!("Deleted {} course", rows))
+      }
+    }
+  }
+
+  async fn update_course(&self, id: i32, dto: UpdateCourseDTO) -> Result<Course, MyError> {
+    let mut tables = self.tables();
+    let course = tables
+      .courses
+      .iter_mut()
+      .find(|c| c.id == id)
+      .ok_or_else(|| MyError::NotFound("Course not found".into()))?;
+    if let Some(name) = dto.name {
+      course.name = name;
+    }
+    if let Some(description) = dto.description {
+      course.description = description;
+    }
+    if let Some(format) = dto.format {
+      course.format = format;
+    }
+    if let Some(structure) = dto.structure {
+      course.structure = structure;
+    }
+    if let Some(duration) = dto.duration {
+      course.duration = duration;
+    }
+    if let Some(price) = dto.price {
+      course.price = price;
+    }
+    if let Some(language) = dto.language {
+      course.language = language;
+    }
+    if let Some(level) = dto.level {
+      course.level = level;
+    }
+    if let Some(capacity) = dto.capacity {
+      course.capacity = capacity;
+    }
+    let course = course.clone();
+    tables.fill_places(id);
+    Ok(course)
+  }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+  async fn create_user(&self, username: String, password_hash: String) -> Result<User, MyError> {
+    let mut tables = self.tables();
+    if tables.users.iter().any(|u| u.username == username) {
+      return Err(MyError::ValidationError(vec![FieldError::new(
+        "username", "is taken",
+      )]));
+    }
+    tables.last_user_id += 1;
+    let user = User {
+      id: tables.last_user_id,
+      username,
+      password_hash,
+      teacher_id: None,
+      student_id: None,
+      is_moderator: false,
+    };
+    tables.users.push(user.clone());
+    Ok(user)
+  }
+
+  async fn get_user(&self, id: i32) -> Result<User, MyError> {
+    self
+      .tables()
+      .users
+      .iter()
+      .find(|u| u.id == id)
+      .cloned()
+      .ok_or_else(|| MyError::NotFound("User not found".into()))
+  }
+
+  async fn get_user_by_name(&self, username: &str) -> Result<Option<User>, MyError> {
+    let tables = self.tables();
+    Ok(
+      tables
+        .users
+        .iter()
+        .find(|u| u.username == username)
+        .cloned(),
+    )
+  }
+
+  async fn add_refresh_token(
+    &self,
+    id: String,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+  ) -> Result<(), MyError> {
+    let now = Utc::now().naive_utc();
+    let mut tables = self.tables();
+    tables
+      .refresh_tokens
+      .retain(|_, (_, expires_at)| *expires_at >= now);
+    tables.refresh_tokens.insert(id, (user_id, expires_at));
+    Ok(())
+  }
+
+  async fn take_refresh_token(&self, id: &str) -> Result<Option<i32>, MyError> {
+    let now = Utc::now().naive_utc();
+    let mut tables = self.tables();
+    match tables.refresh_tokens.get(id) {
+      Some((user_id, expires_at)) if *expires_at >= now => {
+        let user_id = *user_id;
+        tables.refresh_tokens.remove(id);
+        Ok(Some(user_id))
+      }
+      _ => Ok(None),
+    }
+  }
+}
+
+#[async_trait]
+impl StudentRepository for MemoryRepository {
+  async fn create_student(&self, user_id: i32, dto: CreateStudentDTO) -> Result<Student, MyError> {
+    let mut tables = self.tables();
+    let student_id = tables.last_student_id + 1;
+    match tables.users.iter_mut().find(|u| u.id == user_id) {
+      Some(user) if user.student_id.is_none() => user.student_id = Some(student_id),
+      _ => {
+        return Err(MyError::Forbidden(
+          "You already have a student profile".into(),
+        ))
+      }
+    }
+    tables.last_student_id = student_id;
+    let student = Student {
+      id: student_id,
+      name: dto.name,
+    };
+    tables.students.push(student.clone());
+    Ok(student)
+  }
+
+  async fn get_student(&self, id: i32) -> Result<Student, MyError> {
+    self
+      .tables()
+      .students
+      .iter()
+      .find(|s| s.id == id)
+      .cloned()
+      .ok_or_else(|| MyError::NotFound("Student not found".into()))
+  }
+}
+
+#[async_trait]
+impl EnrollmentRepository for MemoryRepository {
+  async fn enroll(&self, course_id: i32, student_id: i32) -> Result<Enrollment, MyError> {
+    let mut tables = self.tables();
+    let capacity = match tables.courses.iter().find(|c| c.id == course_id) {
+      Some(course) => course.capacity,
+      None => return Err(MyError::NotFound("Course not found".into())),
+    };
+    if !tables.students.iter().any(|s| s.id == student_id) {
+      return Err(constraint_error("enrollment_student_id_fkey"));
+    }
+    let enrolled = tables
+      .enrollments
+      .iter()
+      .filter(|e| e.course_id == course_id && e.status == ENROLLED)
+      .count();
+    let status = match capacity {
+      Some(capacity) if enrolled >= capacity as usize => WAITLISTED,
+      _ => ENROLLED,
+    };
+    let now = Local::now().naive_local();
+
+    let existing = tables
+      .enrollments
+      .iter_mut()
+      .find(|e| e.course_id == course_id && e.student_id == student_id);
+    if let Some(enrollment) = existing {
+      if enrollment.status != DROPPED {
+        return Err(MyError::Conflict(format!(
+          "Already {} in this course",
+          enrollment.status
+        )));
+      }
+      enrollment.status = status.into();
+      enrollment.updated_at = now;
+      return Ok(enrollment.clone());
+    }
+
+    tables.last_enrollment_id += 1;
+    let enrollment = Enrollment {
+      id: tables.last_enrollment_id,
+      course_id,
+      student_id,
+      status: status.into(),
+      created_at: now,
+      updated_at: now,
+    };
+    tables.enrollments.push(enrollment.clone());
+    Ok(enrollment)
+  }
+
+  async fn drop_enrollment(&self, course_id: i32, student_id: i32) -> Result<Enrollment, MyError> {
+    let mut tables = self.tables();
+    if !tables.courses.iter().any(|c| c.id == course_id) {
+      return Err(MyError::NotFound("Course not found".into()));
+    }
+    let enrollment = tables
+      .enrollments
+      .iter_mut()
+      .find(|e| e.course_id == course_id && e.student_id == student_id && e.status != DROPPED)
+      .ok_or_else(|| MyError::NotFound("Enrollment not found".into()))?;
+    enrollment.status = DROPPED.into();
+    enrollment.updated_at = Local::now().naive_local();
+    let enrollment = enrollment.clone();
+    tables.fill_places(course_id);
+    Ok(enrollment)
+  }
+
+  async fn get_enrollments_of_course(
+    &self,
+    course_id: i32,
+    status: Option<String>,
+  ) -> Result<Vec<Enrollment>, MyError> {
+    let tables = self.tables();
+    let mut enrollments: Vec<Enrollment> = tables
+      .enrollments
+      .iter()
+      .filter(|e| e.course_id == course_id)
+      .filter(|e| status.as_ref().is_none_or(|status| &e.status == status))
+      .cloned()
+      .collect();
+    enrollments.sort_by_key(|e| (e.updated_at, e.id));
+    Ok(enrollments)
+  }
+
+  async fn get_enrollments_of_student(
+    &self,
+    student_id: i32,
+    status: Option<String>,
+  ) -> Result<Vec<Enrollment>, MyError> {
+    let tables = self.tables();
+    let mut enrollments: Vec<Enrollment> = tables
+      .enrollments
+      .iter()
+      .filter(|e| e.student_id == student_id)
+      .filter(|e| status.as_ref().is_none_or(|status| &e.status == status))
+      .cloned()
+      .collect();
+    enrollments.sort_by_key(|e| (e.updated_at, e.id));
+    Ok(enrollments)
+  }
+}
+
+#[async_trait]
+impl ReviewRepository for MemoryRepository {
+  async fn create_review(
+    &self,
+    course_id: i32,
+    student_id: i32,
+    dto: CreateReviewDTO,
+  ) -> Result<Review, MyError> {
+    let mut tables = self.tables();
+    if !tables.courses.iter().any(|c| c.id == course_id) {
+      return Err(constraint_error("review_course_id_fkey"));
+    }
+    if tables
+      .reviews
+      .iter()
+      .any(|r| r.course_id == course_id && r.student_id == student_id)
+    {
+      return Err(MyError::Conflict(
+        "You have already reviewed this course".into(),
+      ));
+    }
+    tables.last_review_id += 1;
+    let review = Review {
+      id: tables.last_review_id,
+      course_id,
+      student_id,
+      rating: dto.rating,
+      body: dto.body,
+      reply: None,
+      replied_at: None,
+      hidden_at: None,
+      hidden_reason: None,
+      created_at: Local::now().naive_local(),
+    };
+    tables.reviews.push(review.clone());
+    tables.count_rating(course_id, 1, review.rating);
+    Ok(review)
+  }
+
+  async fn get_review(&self, id: i32) -> Result<Review, MyError> {
+    self
+      .tables()
+      .reviews
+      .iter()
+      .find(|r| r.id == id)
+      .cloned()
+      .ok_or_else(|| MyError::NotFound("Review not found".into()))
+  }
+
+  async fn get_reviews_of_course(
+    &self,
+    course_id: i32,
+    page: Page,
+  ) -> Result<Paged<Review>, MyError> {
+    let mut reviews: Vec<Review> = self
+      .tables()
+      .reviews
+      .iter()
+      .filter(|r| r.course_id == course_id && r.hidden_at.is_none())
+      .cloned()
+      .collect();
+    reviews.sort_by_key(|r| std::cmp::Reverse((r.created_at, r.id)));
+    Ok(paginate(reviews, page))
+  }
+
+  async fn get_hidden_reviews(&self, page: Page) -> Result<Paged<Review>, MyError> {
+    let mut reviews: Vec<Review> = self
+      .tables()
+      .reviews
+      .iter()
+      .filter(|r| r.hidden_at.is_some())
+      .cloned()
+      .collect();
+    reviews.sort_by_key(|r| std::cmp::Reverse((r.hidden_at, r.id)));
+    Ok(paginate(reviews, page))
+  }
+
+  async fn reply_to_review(&self, id: i32, reply: String) -> Result<Review, MyError> {
+    let mut tables = self.tables();
+    let review = tables
+      .reviews
+      .iter_mut()
+      .find(|r| r.id == id)
+      .ok_or_else(|| MyError::NotFound("Review not found".into()))?;
+    review.reply = Some(reply);
+    review.replied_at = Some(Local::now().naive_local());
+    Ok(review.clone())
+  }
+
+  async fn set_review_hidden(&self, id: i32, reason: Option<String>) -> Result<Review, MyError> {
+    let mut tables = self.tables();
+    let review = tables
+      .reviews
+      .iter_mut()
+      .find(|r| r.id == id)
+      .ok_or_else(|| MyError::NotFound("Review not found".into()))?;
+    let was_hidden = review.hidden_at.is_some();
+    review.hidden_at = match reason {
+      Some(_) => review
+        .hidden_at
+        .or_else(|| Some(Local::now().naive_local())),
+      None => None,
+    };
+    review.hidden_reason = reason;
+    let review = review.clone();
+    match (was_hidden, review.hidden_at.is_some()) {
+      (false, true) => tables.count_rating(review.course_id, -1, review.rating),
+      (true, false) => tables.count_rating(review.course_id, 1, review.rating),
+      _ => {}
+    }
+    Ok(review)
+  }
+}
+
+#[async_trait]
+impl ScheduleRepository for MemoryRepository {
+  async fn create_schedule(
+    &self,
+    course_id: i32,
+    dto: CreateScheduleDTO,
+    slots: Vec<Slot>,
+  ) -> Result<ScheduleWithSessions, MyError> {
+    let mut tables = self.tables();
+    let teacher_id = match tables.courses.iter().find(|c| c.id == course_id) {
+      Some(course) => course.teacher_id,
+      None => return Err(MyError::NotFound("Course not found".into())),
+    };
+    check_availability(&slots, &tables.availability_of(teacher_id))?;
+    let busy = tables.sessions_of_teacher(teacher_id);
+    if let Some(session) = busy
+      .iter()
+      .find(|session| slots.iter().any(|slot| slot.overlaps(session)))
+    {
+      return Err(MyError::Conflict(format!(
+        "The teacher already has a session from {} to {} UTC",
+        session.starts_at, session.ends_at
+      )));
+    }
+
+    tables.last_schedule_id += 1;
+    let schedule = Schedule {
+      id: tables.last_schedule_id,
+      course_id,
+      repeat: dto.repeat().into(),
+      occurrences: dto.occurrences(),
+      time_zone: dto.time_zone,
+      starts_at: dto.starts_at,
+      duration_minutes: dto.duration_minutes,
+    };
+    tables.schedules.push(schedule.clone());
+    let mut sessions = Vec::with_capacity(slots.len());
+    for slot in slots {
+      tables.last_session_id += 1;
+      let session = Session {
+        id: tables.last_session_id,
+        schedule_id: schedule.id,
+        course_id,
+        starts_at: slot.starts_at,
+        ends_at: slot.ends_at,
+      };
+      tables.sessions.push(session.clone());
+      sessions.push(session);
+    }
+    Ok(ScheduleWithSessions { schedule, sessions })
+  }
+
+  async fn remove_schedule(&self, course_id: i32, id: i32) -> Result<String, MyError> {
+    let mut tables = self.tables();
+    let before = tables.schedules.len();
+    tables
+      .schedules
+      .retain(|s| !(s.id == id && s.course_id == course_id));
+    match before - tables.schedules.len() {
+      0 => Err(MyError::NotFound("Schedule not found".into())),
+      rows => {
+        tables.sessions.retain(|s| s.schedule_id != id);
+        Ok(format!("Deleted {} schedule", rows))
+      }
+    }
+  }
+
+  async fn get_sessions_of_course(&self, course_id: i32) -> Result<Vec<Session>, MyError> {
+    let mut sessions: Vec<Session> = self
+      .tables()
+      .sessions
+      .iter()
+      .filter(|s| s.course_id == course_id)
+      .cloned()
+      .collect();
+    sessions.sort_by_key(|s| (s.starts_at, s.id));
+    Ok(sessions)
+  }
+
+  async fn get_sessions_of_teacher(&self, teacher_id: i32) -> Result<Vec<Session>, MyError> {
+    Ok(self.tables().sessions_of_teacher(teacher_id))
+  }
+
+  async fn get_availability(&self, teacher_id: i32) -> Result<Vec<Availability>, MyError> {
+    Ok(self.tables().availability_of(teacher_id))
+  }
+
+  async fn set_availability(
+    &self,
+    teacher_id: i32,
+    dto: SetAvailabilityDTO,
+  ) -> Result<Vec<Availability>, MyError> {
+    let mut tables = self.tables();
+    if !tables.teachers.iter().any(|t| t.id == teacher_id) {
+      return Err(MyError::NotFound("Teacher not found".into()));
+    }
+    tables.availability.retain(|a| a.teacher_id != teacher_id);
+    for window in dto.windows {
+      tables.last_availability_id += 1;
+      let availability = Availability {
+        id: tables.last_availability_id,
+        teacher_id,
+        time_zone: dto.time_zone.clone(),
+        weekday: window.weekday,
+        start_time: window.start_time,
+        end_time: window.end_time,
+      };
+      tables.availability.push(availability);
+    }
+    Ok(tables.availability_of(teacher_id))
+  }
+}
@@ -0,0 +1,210 @@
+use crate::error::MyError;
+use sqlx::postgres::PgPool;
+use sqlx::{Executor, Postgres, Row, Transaction};
+
+pub struct Migration {
+  pub version: i64,
+  pub description: &'static str,
+  up: &'static str,
+  down: &'static str,
+}
+
+/// Every migration, oldest first. New ones are only ever appended, with the
+/// next version number; applied migrations must not be edited, which
+/// [`migrate_up`] checks against the checksum stored when they were applied.
+pub const MIGRATIONS: &[Migration] = &[
+  Migration {
+    version: 1,
+    description: "initial schema",
+    up: include_str!("../../migrations/0001_initial.up.sql"),
+    down: include_str!("../../migrations/0001_initial.down.sql"),
+  },
+  Migration {
+    version: 2,
+    description: "search indexes",
+    up: include_str!("../../migrations/0002_search.up.sql"),
+    down: include_str!("../../migrations/0002_search.down.sql"),
+  },
+  Migration {
+    version: 3,
+    description: "users and refresh tokens",
+    up: include_str!("../../migrations/0003_users.up.sql"),
+    down: include_str!("../../migrations/0003_users.down.sql"),
+  },
+  Migration {
+    version: 4,
+    description: "students and enrollments",
+    up: include_str!("../../migrations/0004_enrollment.up.sql"),
+    down: include_str!("../../migrations/0004_enrollment.down.sql"),
+  },
+  Migration {
+    version: 5,
+    description: "reviews and moderators",
+    up: include_str!("../../migrations/0005_reviews.up.sql"),
+    down: include_str!("../../migrations/0005_reviews.down.sql"),
+  },
+  Migration {
+    version: 6,
+    description: "schedules and availability",
+    up: include_str!("../../migrations/0006_schedule.up.sql"),
+    down: include_str!("../../migrations/0006_schedule.down.sql"),
+  },
+];
+
+impl Migration {
+  /// A 64-bit FNV-1a hash of the `up` script, as hex. It only has to notice
+  /// edits, not resist tampering, and unlike `DefaultHasher` it is stable
+  /// across Rust releases.
+  pub fn checksum(&self) -> String {
+    let hash = self.up.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+      (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+  }
+}
+
+/// Creates the table that tracks applied migrations, then locks it for the
+/// rest of `tx`, so two instances booting at once don't migrate twice.
+async fn lock(tx: &mut Transaction<'_, Postgres>) -> Result<(), MyError> {
+  tx.execute(
+    r#"create table if not exists schema_migrations (
+      version bigint primary key,
+      description varchar(200) not null,
+      applied_at timestamp not null default now()
+    );
+    alter table schema_migrations add column if not exists checksum varchar(16);
+    lock table schema_migrations in exclusive mode"#,
+  )
+  .await?;
+  Ok(())
+}
+
+async fn applied_versions(tx: &mut Transaction<'_, Postgres>) -> Result<Vec<i64>, MyError> {
+  let rows = sqlx::query("select version from schema_migrations order by version")
+    .fetch_all(tx)
+    .await?;
+  Ok(
+    rows
+      .iter()
+      .map(|row| row.get::<i64, _>("version"))
+      .collect(),
+  )
+}
+
+/// Fails if a migration was edited after it was applied. Rows from before
+/// checksums were stored get the current one instead.
+async fn verify(tx: &mut Transaction<'_, Postgres>) -> Result<(), MyError> {
+  let rows = sqlx::query("select version, checksum from schema_migrations")
+    .fetch_all(&mut *tx)
+    .await?;
+  for row in rows {
+    let version: i64 = row.get("version");
+    let migration = match MIGRATIONS.iter().find(|m| m.version == version) {
+      Some(migration) => migration,
+      None => continue,
+    };
+    match row.get::<Option<String>, _>("checksum") {
+      Some(checksum) if checksum != migration.checksum() => {
+        return Err(MyError::internal(format!(
+          "Migration {} was edited after it was applied",
+          version
+        )));
+      }
+      Some(_) => {}
+      None => {
+        sqlx::query("update schema_migrations set checksum = $2 where version = $1")
+          .bind(version)
+          .bind(migration.checksum())
+          .execute(&mut *tx)
+          .await?;
+      }
+    }
+  }
+  Ok(())
+}
+
+/// The versions applied to the database, oldest first. They may include
+/// versions this build doesn't know about.
+pub async fn applied(pool: &PgPool) -> Result<Vec<i64>, MyError> {
+  let mut tx = pool.begin().await?;
+  lock(&mut tx).await?;
+  let versions = applied_versions(&mut tx).await?;
+  tx.commit().await?;
+  Ok(versions)
+}
+
+/// Applies every pending migration in order, each in its own transaction,
+/// and returns the ones it applied. Nothing is applied if an already
+/// applied migration no longer matches its checksum.
+pub async fn migrate_up(pool: &PgPool) -> Result<Vec<&'static Migration>, MyError> {
+  let mut tx = pool.begin().await?;
+  lock(&mut tx).await?;
+  verify(&mut tx).await?;
+  tx.commit().await?;
+
+  let mut done = Vec::new();
+  for migration in MIGRATIONS {
+    let mut tx = pool.begin().await?;
+    lock(&mut tx).await?;
+    if applied_versions(&mut tx)
+      .await?
+      .contains(&migration.version)
+    {
+      continue;
+    }
+    tx.execute(migration.up).await?;
+    sqlx::query(
+      "insert into schema_migrations (version, description, checksum) values ($1, $2, $3)",
+    )
+    .bind(migration.version)
+    .bind(migration.description)
+    .bind(migration.checksum())
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    done.push(migration);
+  }
+  Ok(done)
+}
+
+/// Rolls back the `steps` most recently applied migrations, newest first,
+/// and returns the ones it rolled back.
+pub async fn migrate_down(pool: &PgPool, steps: usize) -> Result<Vec<&'static Migration>, MyError> {
+  let mut undone = Vec::new();
+  for _ in 0..steps {
+    let mut tx = pool.begin().await?;
+    lock(&mut tx).await?;
+    let version = match applied_versions(&mut tx).await?.last() {
+      Some(version) => *version,
+      None => break,
+    };
+    let migration = MIGRATIONS
+      .iter()
+      .find(|m| m.version == version)
+      .ok_or_else(|| {
+        MyError::internal(format!("Migration {} is unknown to this build", version))
+      })?;
+    tx.execute(migration.down).await?;
+    sqlx::query("delete from schema_migrations where version = $1")
+      .bind(version)
+      .execute(&mut tx)
+      .await?;
+    tx.commit().await?;
+    undone.push(migration);
+  }
+  Ok(undone)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_checksum() {
+    let checksums: Vec<String> = MIGRATIONS.iter().map(Migration::checksum).collect();
+    assert!(checksums.iter().all(|c| c.len() == 16));
+    for (i, checksum) in checksums.iter().enumerate() {
+      assert!(!checksums[i + 1..].contains(checksum));
+    }
+  }
+}
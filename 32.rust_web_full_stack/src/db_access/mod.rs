@@ -1,2 +1,188 @@
 pub mod course;
+pub mod enrollment;
+pub mod memory;
+pub mod migrations;
+pub mod review;
+pub mod schedule;
+pub mod student;
 pub mod teacher;
+pub mod user;
+
+use crate::error::MyError;
+use crate::models::{
+  course::*, review::*, schedule::*, student::*, teacher::*, user::*, Page, Paged, Sort,
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use memory::MemoryRepository;
+use sqlx::postgres::PgPoolOptions;
+
+#[async_trait]
+pub trait TeacherRepository: Send + Sync {
+  /// Creates a teacher owned by `user_id`. A user owns at most one teacher,
+  /// so this is `Forbidden` if they already have one.
+  async fn create_teacher(&self, user_id: i32, dto: CreateTeacherDTO) -> Result<Teacher, MyError>;
+  async fn get_teachers(
+    &self,
+    search: Option<String>,
+    sort: Sort,
+    page: Page,
+  ) -> Result<Paged<Teacher>, MyError>;
+  async fn get_teacher(&self, id: i32) -> Result<Teacher, MyError>;
+  async fn remove_teacher(&self, id: i32) -> Result<String, MyError>;
+  async fn update_teacher(&self, id: i32, dto: UpdateTeacherDTO) -> Result<Teacher, MyError>;
+}
+
+#[async_trait]
+pub trait CourseRepository: Send + Sync {
+  async fn create_course(&self, dto: CreateCourseDTO) -> Result<Course, MyError>;
+  async fn get_courses(
+    &self,
+    filter: CourseFilter,
+    sort: Sort,
+    page: Page,
+  ) -> Result<Paged<Course>, MyError>;
+  async fn get_course(&self, id: i32) -> Result<Course, MyError>;
+  async fn remove_course(&self, id: i32) -> Result<String, MyError>;
+  async fn update_course(&self, id: i32, dto: UpdateCourseDTO) -> Result<Course, MyError>;
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+  /// A `ValidationError` if the username is taken.
+  async fn create_user(&self, username: String, password_hash: String) -> Result<User, MyError>;
+  async fn get_user(&self, id: i32) -> Result<User, MyError>;
+  async fn get_user_by_name(&self, username: &str) -> Result<Option<User>, MyError>;
+  async fn add_refresh_token(
+    &self,
+    id: String,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+  ) -> Result<(), MyError>;
+  /// Removes a refresh token and returns its user, if it hadn't expired.
+  async fn take_refresh_token(&self, id: &str) -> Result<Option<i32>, MyError>;
+}
+
+#[async_trait]
+pub trait StudentRepository: Send + Sync {
+  /// Creates a student owned by `user_id`. A user owns at most one student,
+  /// so this is `Forbidden` if they already have one.
+  async fn create_student(&self, user_id: i32, dto: CreateStudentDTO) -> Result<Student, MyError>;
+  async fn get_student(&self, id: i32) -> Result<Student, MyError>;
+}
+
+#[async_trait]
+pub trait EnrollmentRepository: Send + Sync {
+  /// Enrolls the student, or puts them on the waitlist if the course is
+  /// full. `Conflict` if they are already on either.
+  async fn enroll(&self, course_id: i32, student_id: i32) -> Result<Enrollment, MyError>;
+  /// Drops the student from the course or its waitlist, giving a freed place
+  /// to the student who has waited longest.
+  async fn drop_enrollment(&self, course_id: i32, student_id: i32) -> Result<Enrollment, MyError>;
+  async fn get_enrollments_of_course(
+    &self,
+    course_id: i32,
+    status: Option<String>,
+  ) -> Result<Vec<Enrollment>, MyError>;
+  async fn get_enrollments_of_student(
+    &self,
+    student_id: i32,
+    status: Option<String>,
+  ) -> Result<Vec<Enrollment>, MyError>;
+}
+
+/// Reviews change their course's `review_count` and `rating_total` in the
+/// same transaction, so course listings don't have to aggregate reviews.
+#[async_trait]
+pub trait ReviewRepository: Send + Sync {
+  /// `Conflict` if the student has already reviewed the course.
+  async fn create_review(
+    &self,
+    course_id: i32,
+    student_id: i32,
+    dto: CreateReviewDTO,
+  ) -> Result<Review, MyError>;
+  async fn get_review(&self, id: i32) -> Result<Review, MyError>;
+  /// The course's visible reviews, newest first.
+  async fn get_reviews_of_course(
+    &self,
+    course_id: i32,
+    page: Page,
+  ) -> Result<Paged<Review>, MyError>;
+  /// Every hidden review, most recently hidden first.
+  async fn get_hidden_reviews(&self, page: Page) -> Result<Paged<Review>, MyError>;
+  /// Sets or replaces the teacher's reply.
+  async fn reply_to_review(&self, id: i32, reply: String) -> Result<Review, MyError>;
+  /// Hides the review for `reason`, or shows it again if `None`.
+  async fn set_review_hidden(&self, id: i32, reason: Option<String>) -> Result<Review, MyError>;
+}
+
+/// Sessions belong to courses, but a teacher can't be in two sessions at once,
+/// so scheduling checks every course of the teacher.
+#[async_trait]
+pub trait ScheduleRepository: Send + Sync {
+  /// Adds the schedule with `slots` as its sessions. `Conflict` if a slot
+  /// overlaps another session of the course's teacher, and `Unprocessable` if
+  /// it is outside their availability.
+  async fn create_schedule(
+    &self,
+    course_id: i32,
+    dto: CreateScheduleDTO,
+    slots: Vec<Slot>,
+  ) -> Result<ScheduleWithSessions, MyError>;
+  /// Removes the schedule and its sessions.
+  async fn remove_schedule(&self, course_id: i32, id: i32) -> Result<String, MyError>;
+  /// The course's sessions, earliest first.
+  async fn get_sessions_of_course(&self, course_id: i32) -> Result<Vec<Session>, MyError>;
+  /// The sessions of all the teacher's courses, earliest first.
+  async fn get_sessions_of_teacher(&self, teacher_id: i32) -> Result<Vec<Session>, MyError>;
+  async fn get_availability(&self, teacher_id: i32) -> Result<Vec<Availability>, MyError>;
+  /// Replaces the teacher's windows. Sessions already scheduled are kept.
+  async fn set_availability(
+    &self,
+    teacher_id: i32,
+    dto: SetAvailabilityDTO,
+  ) -> Result<Vec<Availability>, MyError>;
+}
+
+/// Everything the handlers need from storage, implemented by `PgPool` and
+/// [`MemoryRepository`].
+pub trait Repository:
+  TeacherRepository
+  + CourseRepository
+  + UserRepository
+  + StudentRepository
+  + EnrollmentRepository
+  + ReviewRepository
+  + ScheduleRepository
+{
+}
+
+impl<T> Repository for T where
+  T: TeacherRepository
+    + CourseRepository
+    + UserRepository
+    + StudentRepository
+    + EnrollmentRepository
+    + ReviewRepository
+    + ScheduleRepository
+{
+}
+
+/// Opens the backend named by `database_url`. `memory:` keeps everything in
+/// memory until the process exits; any other URL is handed to Postgres, whose
+/// pending migrations are applied first.
+pub async fn connect(database_url: &str) -> Result<Box<dyn Repository>, MyError> {
+  if database_url == "memory:" {
+    return Ok(Box::new(MemoryRepository::default()));
+  }
+  let pool = PgPoolOptions::new().connect(database_url).await?;
+  for migration in migrations::migrate_up(&pool).await? {
+    tracing::info!(
+      version = migration.version,
+      description = migration.description,
+      "Applied migration"
+    );
+  }
+  Ok(Box::new(pool))
+}
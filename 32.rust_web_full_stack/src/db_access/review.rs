@@ -0,0 +1,206 @@
+use super::ReviewRepository;
+use crate::error::MyError;
+use crate::models::{review::*, Page, Paged};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sqlx::postgres::PgPool;
+use sqlx::{Postgres, Transaction};
+
+/// Adds a review with `rating` to its course's totals if `sign` is `1`, or
+/// takes it away if `sign` is `-1`.
+async fn count_rating(
+  tx: &mut Transaction<'_, Postgres>,
+  course_id: i32,
+  sign: i32,
+  rating: i32,
+) -> Result<(), MyError> {
+  sqlx::query(
+    r#"update course set
+     review_count = review_count + $2,
+     rating_total = rating_total + $2 * $3
+     where id = $1"#,
+  )
+  .bind(course_id)
+  .bind(sign)
+  .bind(rating)
+  .execute(&mut *tx)
+  .await?;
+
+  Ok(())
+}
+
+pub async fn db_create_review(
+  pool: &PgPool,
+  course_id: i32,
+  student_id: i32,
+  dto: CreateReviewDTO,
+) -> Result<Review, MyError> {
+  let mut tx = pool.begin().await?;
+  let record = sqlx::query_as::<_, Review>(
+    r#"insert into review (course_id, student_id, rating, body)
+     values ($1, $2, $3, $4)
+     on conflict (course_id, student_id) do nothing
+     returning *"#,
+  )
+  .bind(course_id)
+  .bind(student_id)
+  .bind(dto.rating)
+  .bind(dto.body)
+  .fetch_optional(&mut tx)
+  .await?;
+
+  let review =
+    record.ok_or_else(|| MyError::Conflict("You have already reviewed this course".into()))?;
+  count_rating(&mut tx, course_id, 1, review.rating).await?;
+  tx.commit().await?;
+
+  Ok(review)
+}
+
+pub async fn db_get_review(pool: &PgPool, id: i32) -> Result<Review, MyError> {
+  let record = sqlx::query_as::<_, Review>("select * from review where id = $1")
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+  match record {
+    Some(review) => Ok(review),
+    None => Err(MyError::NotFound("Review not found".into())),
+  }
+}
+
+pub async fn db_get_reviews_of_course(
+  pool: &PgPool,
+  course_id: i32,
+  page: Page,
+) -> Result<Paged<Review>, MyError> {
+  let items = sqlx::query_as::<_, Review>(
+    r#"select * from review
+     where course_id = $1 and hidden_at is null
+     order by created_at desc, id desc
+     limit $2 offset $3"#,
+  )
+  .bind(course_id)
+  .bind(page.size)
+  .bind(page.offset())
+  .fetch_all(pool)
+  .await?;
+
+  let (total,) = sqlx::query_as::<_, (i64,)>(
+    "select count(*) from review where course_id = $1 and hidden_at is null",
+  )
+  .bind(course_id)
+  .fetch_one(pool)
+  .await?;
+
+  Ok(Paged { items, total })
+}
+
+pub async fn db_get_hidden_reviews(pool: &PgPool, page: Page) -> Result<Paged<Review>, MyError> {
+  let items = sqlx::query_as::<_, Review>(
+    r#"select * from review
+     where hidden_at is not null
+     order by hidden_at desc, id desc
+     limit $1 offset $2"#,
+  )
+  .bind(page.size)
+  .bind(page.offset())
+  .fetch_all(pool)
+  .await?;
+
+  let (total,) =
+    sqlx::query_as::<_, (i64,)>("select count(*) from review where hidden_at is not null")
+      .fetch_one(pool)
+      .await?;
+
+  Ok(Paged { items, total })
+}
+
+pub async fn db_reply_to_review(pool: &PgPool, id: i32, reply: String) -> Result<Review, MyError> {
+  let record = sqlx::query_as::<_, Review>(
+    "update review set reply = $2, replied_at = now() where id = $1 returning *",
+  )
+  .bind(id)
+  .bind(reply)
+  .fetch_optional(pool)
+  .await?;
+
+  match record {
+    Some(review) => Ok(review),
+    None => Err(MyError::NotFound("Review not found".into())),
+  }
+}
+
+/// Hiding a hidden review only changes its reason, and showing a visible one
+/// does nothing; the course's totals change only when visibility does.
+pub async fn db_set_review_hidden(
+  pool: &PgPool,
+  id: i32,
+  reason: Option<String>,
+) -> Result<Review, MyError> {
+  let mut tx = pool.begin().await?;
+  let before = sqlx::query_as::<_, (Option<NaiveDateTime>,)>(
+    "select hidden_at from review where id = $1 for update",
+  )
+  .bind(id)
+  .fetch_optional(&mut tx)
+  .await?;
+  let (hidden_at,) = before.ok_or_else(|| MyError::NotFound("Review not found".into()))?;
+
+  let review = sqlx::query_as::<_, Review>(
+    r#"update review set
+     hidden_at = case when $2::varchar is null then null else coalesce(hidden_at, now()) end,
+     hidden_reason = $2
+     where id = $1
+     returning *"#,
+  )
+  .bind(id)
+  .bind(reason)
+  .fetch_one(&mut tx)
+  .await?;
+
+  match (hidden_at.is_some(), review.hidden_at.is_some()) {
+    (false, true) => count_rating(&mut tx, review.course_id, -1, review.rating).await?,
+    (true, false) => count_rating(&mut tx, review.course_id, 1, review.rating).await?,
+    _ => {}
+  }
+  tx.commit().await?;
+
+  Ok(review)
+}
+
+#[async_trait]
+impl ReviewRepository for PgPool {
+  async fn create_review(
+    &self,
+    course_id: i32,
+    student_id: i32,
+    dto: CreateReviewDTO,
+  ) -> Result<Review, MyError> {
+    db_create_review(self, course_id, student_id, dto).await
+  }
+
+  async fn get_review(&self, id: i32) -> Result<Review, MyError> {
+    db_get_review(self, id).await
+  }
+
+  async fn get_reviews_of_course(
+    &self,
+    course_id: i32,
+    page: Page,
+  ) -> Result<Paged<Review>, MyError> {
+    db_get_reviews_of_course(self, course_id, page).await
+  }
+
+  async fn get_hidden_reviews(&self, page: Page) -> Result<Paged<Review>, MyError> {
+    db_get_hidden_reviews(self, page).await
+  }
+
+  async fn reply_to_review(&self, id: i32, reply: String) -> Result<Review, MyError> {
+    db_reply_to_review(self, id, reply).await
+  }
+
+  async fn set_review_hidden(&self, id: i32, reason: Option<String>) -> Result<Review, MyError> {
+    db_set_review_hidden(self, id, reason).await
+  }
+}
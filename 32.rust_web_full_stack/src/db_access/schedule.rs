@@ -0,0 +1,231 @@
+use super::ScheduleRepository;
+use crate::error::MyError;
+use crate::models::schedule::*;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+use sqlx::{Postgres, Transaction};
+
+/// Locks the teacher row for the rest of `tx`. Scheduling for the same
+/// teacher takes turns this way, so two schedules can't both take a slot.
+async fn lock_teacher(tx: &mut Transaction<'_, Postgres>, teacher_id: i32) -> Result<(), MyError> {
+  let record = sqlx::query("select id from teacher where id = $1 for update")
+    .bind(teacher_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+  match record {
+    Some(_) => Ok(()),
+    None => Err(MyError::NotFound("Teacher not found".into())),
+  }
+}
+
+const AVAILABILITY: &str =
+  "select * from teacher_availability where teacher_id = $1 order by weekday, start_time, id";
+
+async fn availability(
+  tx: &mut Transaction<'_, Postgres>,
+  teacher_id: i32,
+) -> Result<Vec<Availability>, MyError> {
+  let windows = sqlx::query_as::<_, Availability>(AVAILABILITY)
+    .bind(teacher_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+  Ok(windows)
+}
+
+pub async fn db_create_schedule(
+  pool: &PgPool,
+  course_id: i32,
+  dto: CreateScheduleDTO,
+  slots: Vec<Slot>,
+) -> Result<ScheduleWithSessions, MyError> {
+  let mut tx = pool.begin().await?;
+  let course = sqlx::query_as::<_, (i32,)>("select teacher_id from course where id = $1")
+    .bind(course_id)
+    .fetch_optional(&mut tx)
+    .await?;
+  let (teacher_id,) = course.ok_or_else(|| MyError::NotFound("Course not found".into()))?;
+  lock_teacher(&mut tx, teacher_id).await?;
+  check_availability(&slots, &availability(&mut tx, teacher_id).await?)?;
+
+  for slot in &slots {
+    let taken = sqlx::query_as::<_, Session>(
+      r#"select s.* from course_session s
+       join course c on c.id = s.course_id
+       where c.teacher_id = $1 and s.starts_at < $3 and s.ends_at > $2
+       order by s.starts_at
+       limit 1"#,
+    )
+    .bind(teacher_id)
+    .bind(slot.starts_at)
+    .bind(slot.ends_at)
+    .fetch_optional(&mut tx)
+    .await?;
+    if let Some(session) = taken {
+      return Err(MyError::Conflict(format!(
+        "The teacher already has a session from {} to {} UTC",
+        session.starts_at, session.ends_at
+      )));
+    }
+  }
+
+  let repeat = dto.repeat().to_string();
+  let occurrences = dto.occurrences();
+  let schedule = sqlx::query_as::<_, Schedule>(
+    r#"insert into course_schedule
+     (course_id, time_zone, starts_at, duration_minutes, repeat, occurrences)
+     values ($1, $2, $3, $4, $5, $6)
+     returning *"#,
+  )
+  .bind(course_id)
+  .bind(dto.time_zone)
+  .bind(dto.starts_at)
+  .bind(dto.duration_minutes)
+  .bind(repeat)
+  .bind(occurrences)
+  .fetch_one(&mut tx)
+  .await?;
+
+  let mut sessions = Vec::with_capacity(slots.len());
+  for slot in slots {
+    let session = sqlx::query_as::<_, Session>(
+      r#"insert into course_session (schedule_id, course_id, starts_at, ends_at)
+       values ($1, $2, $3, $4)
+       returning *"#,
+    )
+    .bind(schedule.id)
+    .bind(course_id)
+    .bind(slot.starts_at)
+    .bind(slot.ends_at)
+    .fetch_one(&mut tx)
+    .await?;
+    sessions.push(session);
+  }
+  tx.commit().await?;
+
+  Ok(ScheduleWithSessions { schedule, sessions })
+}
+
+pub async fn db_remove_schedule(pool: &PgPool, course_id: i32, id: i32) -> Result<String, MyError> {
+  let record = sqlx::query("delete from course_schedule where id = $1 and course_id = $2")
+    .bind(id)
+    .bind(course_id)
+    .execute(pool)
+    .await?;
+
+  match record.rows_affected() {
+    0 => Err(MyError::NotFound("Schedule not found".into())),
+    rows => Ok(format!("Deleted {} schedule", rows)),
+  }
+}
+
+pub async fn db_get_sessions_of_course(
+  pool: &PgPool,
+  course_id: i32,
+) -> Result<Vec<Session>, MyError> {
+  let sessions = sqlx::query_as::<_, Session>(
+    "select * from course_session where course_id = $1 order by starts_at, id",
+  )
+  .bind(course_id)
+  .fetch_all(pool)
+  .await?;
+
+  Ok(sessions)
+}
+
+pub async fn db_get_sessions_of_teacher(
+  pool: &PgPool,
+  teacher_id: i32,
+) -> Result<Vec<Session>, MyError> {
+  let sessions = sqlx::query_as::<_, Session>(
+    r#"select s.* from course_session s
+     join course c on c.id = s.course_id
+     where c.teacher_id = $1
+     order by s.starts_at, s.id"#,
+  )
+  .bind(teacher_id)
+  .fetch_all(pool)
+  .await?;
+
+  Ok(sessions)
+}
+
+pub async fn db_get_availability(
+  pool: &PgPool,
+  teacher_id: i32,
+) -> Result<Vec<Availability>, MyError> {
+  let windows = sqlx::query_as::<_, Availability>(AVAILABILITY)
+    .bind(teacher_id)
+    .fetch_all(pool)
+    .await?;
+
+  Ok(windows)
+}
+
+pub async fn db_set_availability(
+  pool: &PgPool,
+  teacher_id: i32,
+  dto: SetAvailabilityDTO,
+) -> Result<Vec<Availability>, MyError> {
+  let mut tx = pool.begin().await?;
+  lock_teacher(&mut tx, teacher_id).await?;
+  sqlx::query("delete from teacher_availability where teacher_id = $1")
+    .bind(teacher_id)
+    .execute(&mut tx)
+    .await?;
+  for window in dto.windows {
+    sqlx::query(
+      r#"insert into teacher_availability
+       (teacher_id, time_zone, weekday, start_time, end_time)
+       values ($1, $2, $3, $4, $5)"#,
+    )
+    .bind(teacher_id)
+    .bind(&dto.time_zone)
+    .bind(window.weekday)
+    .bind(window.start_time)
+    .bind(window.end_time)
+    .execute(&mut tx)
+    .await?;
+  }
+  let windows = availability(&mut tx, teacher_id).await?;
+  tx.commit().await?;
+
+  Ok(windows)
+}
+
+#[async_trait]
+impl ScheduleRepository for PgPool {
+  async fn create_schedule(
+    &self,
+    course_id: i32,
+    dto: CreateScheduleDTO,
+    slots: Vec<Slot>,
+  ) -> Result<ScheduleWithSessions, MyError> {
+    db_create_schedule(self, course_id, dto, slots).await
+  }
+
+  async fn remove_schedule(&self, course_id: i32, id: i32) -> Result<String, MyError> {
+    db_remove_schedule(self, course_id, id).await
+  }
+
+  async fn get_sessions_of_course(&self, course_id: i32) -> Result<Vec<Session>, MyError> {
+    db_get_sessions_of_course(self, course_id).await
+  }
+
+  async fn get_sessions_of_teacher(&self, teacher_id: i32) -> Result<Vec<Session>, MyError> {
+    db_get_sessions_of_teacher(self, teacher_id).await
+  }
+
+  async fn get_availability(&self, teacher_id: i32) -> Result<Vec<Availability>, MyError> {
+    db_get_availability(self, teacher_id).await
+  }
+
+  async fn set_availability(
+    &self,
+    teacher_id: i32,
+    dto: SetAvailabilityDTO,
+  ) -> Result<Vec<Availability>, MyError> {
+    db_set_availability(self, teacher_id, dto).await
+  }
+}
@@ -0,0 +1,53 @@
+use super::StudentRepository;
+use crate::error::MyError;
+use crate::models::student::*;
+use async_trait::async_trait;
+use sqlx::postgres::PgPool;
+
+pub async fn db_create_student(
+  pool: &PgPool,
+  user_id: i32,
+  dto: CreateStudentDTO,
+) -> Result<Student, MyError> {
+  let mut tx = pool.begin().await?;
+  let record = sqlx::query_as::<_, Student>("insert into student (name) values ($1) returning *")
+    .bind(dto.name)
+    .fetch_one(&mut tx)
+    .await?;
+
+  let owner =
+    sqlx::query("update app_user set student_id = $1 where id = $2 and student_id is null")
+      .bind(record.id)
+      .bind(user_id)
+      .execute(&mut tx)
+      .await?;
+  if owner.rows_affected() == 0 {
+    // Dropping `tx` rolls back the insert.
+    return Err(MyError::Forbidden(
+      "You already have a student profile".into(),
+    ));
+  }
+  tx.commit().await?;
+
+  Ok(record)
+}
+
+pub async fn db_get_student(pool: &PgPool, id: i32) -> Result<Student, MyError> {
+  let record = sqlx::query_as::<_, Student>("select * from student where id = $1")
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+  record.ok_or_else(|| MyError::NotFound("Student not found".into()))
+}
+
+#[async_trait]
+impl StudentRepository for PgPool {
+  async fn create_student(&self, user_id: i32, dto: CreateStudentDTO) -> Result<Student, MyError> {
+    db_create_student(self, user_id, dto).await
+  }
+
+  async fn get_student(&self, id: i32) -> Result<Student, MyError> {
+    db_get_student(self, id).await
+  }
+}
@@ -1,36 +1,78 @@
+use super::TeacherRepository;
 use crate::error::MyError;
-use crate::models::teacher::*;
+use crate::models::{teacher::*, Page, Paged, Sort};
+use async_trait::async_trait;
 use sqlx::postgres::PgPool;
 
 pub async fn db_create_teacher(
   pool: &PgPool,
+  user_id: i32,
   create_teacher_dto: CreateTeacherDTO,
 ) -> Result<Teacher, MyError> {
-  let record = sqlx::query_as!(
-    Teacher,
+  let mut tx = pool.begin().await?;
+  let record = sqlx::query_as::<_, Teacher>(
     r#"
   insert into teacher (name, profile)
   values ($1, $2)
   returning *"#,
-    create_teacher_dto.name,
-    create_teacher_dto.profile
   )
-  .fetch_one(pool)
+  .bind(create_teacher_dto.name)
+  .bind(create_teacher_dto.profile)
+  .fetch_one(&mut tx)
   .await?;
 
+  let owner =
+    sqlx::query("update app_user set teacher_id = $1 where id = $2 and teacher_id is null")
+      .bind(record.id)
+      .bind(user_id)
+      .execute(&mut tx)
+      .await?;
+  if owner.rows_affected() == 0 {
+    // Dropping `tx` rolls back the insert.
+    return Err(MyError::Forbidden(
+      "You already have a teacher profile".into(),
+    ));
+  }
+  tx.commit().await?;
+
   Ok(record)
 }
 
-pub async fn db_get_teachers(pool: &PgPool) -> Result<Vec<Teacher>, MyError> {
-  let record = sqlx::query_as!(Teacher, "select * from teacher",)
+const TEACHER_FILTER: &str = r#"
+  $1::text is null or
+  to_tsvector('simple', name || ' ' || coalesce(profile, ''))
+  @@ plainto_tsquery('simple', $1)"#;
+
+pub async fn db_get_teachers(
+  pool: &PgPool,
+  search: Option<String>,
+  sort: Sort,
+  page: Page,
+) -> Result<Paged<Teacher>, MyError> {
+  let sql = format!(
+    "select * from teacher where {} {} limit $2 offset $3",
+    TEACHER_FILTER,
+    sort.order_by()
+  );
+  let items = sqlx::query_as::<_, Teacher>(&sql)
+    .bind(&search)
+    .bind(page.size)
+    .bind(page.offset())
     .fetch_all(pool)
     .await?;
 
-  Ok(record)
+  let sql = format!("select count(*) from teacher where {}", TEACHER_FILTER);
+  let (total,) = sqlx::query_as::<_, (i64,)>(&sql)
+    .bind(search)
+    .fetch_one(pool)
+    .await?;
+
+  Ok(Paged { items, total })
 }
 
 pub async fn db_get_teacher(pool: &PgPool, id: i32) -> Result<Teacher, MyError> {
-  let record = sqlx::query_as!(Teacher, "select * from teacher where id = $1", id)
+  let record = sqlx::query_as::<_, Teacher>("select * from teacher where id = $1")
+    .bind(id)
     .fetch_optional(pool)
     .await?;
 
@@ -41,34 +83,66 @@ pub async fn db_get_teacher(pool: &PgPool, id: i32) -> Result<Teacher, MyError>
 }
 
 pub async fn db_remove_teacher(pool: &PgPool, id: i32) -> Result<String, MyError> {
-  let record = sqlx::query!("delete from teacher where id = $1", id)
+  let record = sqlx::query("delete from teacher where id = $1")
+    .bind(id)
     .execute(pool)
     .await?;
 
-  Ok(format!("Delete {:?} teacher", record))
+  match record.rows_affected() {
+    0 => Err(MyError::NotFound("Teacher not found".into())),
+    rows => Ok(format!("Deleted {} teacher", rows)),
+  }
 }
 
+/// Updates only the columns present in `dto`, see
+/// [`db_update_course`](super::course::db_update_course).
 pub async fn db_update_teacher(
   pool: &PgPool,
   id: i32,
   dto: UpdateTeacherDTO,
 ) -> Result<Teacher, MyError> {
-  sqlx::query_as!(Teacher, "select * from teacher where id = $1", id)
-    .fetch_one(pool)
-    .await
-    .map_err(|_| MyError::NotFound("Teacher not found".into()))?;
-
-  let record = sqlx::query_as!(
-    Teacher,
-    r#"update teacher set name = $1, profile = $2
-     where id = $3
+  let record = sqlx::query_as::<_, Teacher>(
+    r#"update teacher set
+     name = case when $1 then $2 else name end,
+     profile = case when $3 then $4 else profile end
+     where id = $5
      returning *"#,
-    dto.name,
-    dto.profile,
-    id
   )
-  .fetch_one(pool)
+  .bind(dto.name.is_some())
+  .bind(dto.name)
+  .bind(dto.profile.is_some())
+  .bind(dto.profile.flatten())
+  .bind(id)
+  .fetch_optional(pool)
   .await?;
 
-  Ok(record)
+  record.ok_or_else(|| MyError::NotFound("Teacher not found".into()))
+}
+
+#[async_trait]
+impl TeacherRepository for PgPool {
+  async fn create_teacher(&self, user_id: i32, dto: CreateTeacherDTO) -> Result<Teacher, MyError> {
+    db_create_teacher(self, user_id, dto).await
+  }
+
+  async fn get_teachers(
+    &self,
+    search: Option<String>,
+    sort: Sort,
+    page: Page,
+  ) -> Result<Paged<Teacher>, MyError> {
+    db_get_teachers(self, search, sort, page).await
+  }
+
+  async fn get_teacher(&self, id: i32) -> Result<Teacher, MyError> {
+    db_get_teacher(self, id).await
+  }
+
+  async fn remove_teacher(&self, id: i32) -> Result<String, MyError> {
+    db_remove_teacher(self, id).await
+  }
+
+  async fn update_teacher(&self, id: i32, dto: UpdateTeacherDTO) -> Result<Teacher, MyError> {
+    db_update_teacher(self, id, dto).await
+  }
 }
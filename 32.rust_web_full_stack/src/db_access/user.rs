@@ -0,0 +1,106 @@
+use super::UserRepository;
+use crate::error::MyError;
+use crate::models::user::*;
+use crate::validation::FieldError;
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use sqlx::postgres::PgPool;
+
+pub async fn db_create_user(
+  pool: &PgPool,
+  username: String,
+  password_hash: String,
+) -> Result<User, MyError> {
+  let record = sqlx::query_as::<_, User>(
+    r#"insert into app_user (username, password_hash)
+     values ($1, $2)
+     on conflict (username) do nothing
+     returning *"#,
+  )
+  .bind(username)
+  .bind(password_hash)
+  .fetch_optional(pool)
+  .await?;
+
+  record.ok_or_else(|| MyError::ValidationError(vec![FieldError::new("username", "is taken")]))
+}
+
+pub async fn db_get_user(pool: &PgPool, id: i32) -> Result<User, MyError> {
+  let record = sqlx::query_as::<_, User>("select * from app_user where id = $1")
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+  record.ok_or_else(|| MyError::NotFound("User not found".into()))
+}
+
+pub async fn db_get_user_by_name(pool: &PgPool, username: &str) -> Result<Option<User>, MyError> {
+  let record = sqlx::query_as::<_, User>("select * from app_user where username = $1")
+    .bind(username)
+    .fetch_optional(pool)
+    .await?;
+
+  Ok(record)
+}
+
+pub async fn db_add_refresh_token(
+  pool: &PgPool,
+  id: String,
+  user_id: i32,
+  expires_at: NaiveDateTime,
+) -> Result<(), MyError> {
+  // Expired tokens can't be used any more, so this is a good time to drop
+  // them.
+  sqlx::query("delete from refresh_token where expires_at < $1")
+    .bind(Utc::now().naive_utc())
+    .execute(pool)
+    .await?;
+  sqlx::query("insert into refresh_token (id, user_id, expires_at) values ($1, $2, $3)")
+    .bind(id)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
+
+pub async fn db_take_refresh_token(pool: &PgPool, id: &str) -> Result<Option<i32>, MyError> {
+  let record = sqlx::query_as::<_, (i32,)>(
+    "delete from refresh_token where id = $1 and expires_at >= $2 returning user_id",
+  )
+  .bind(id)
+  .bind(Utc::now().naive_utc())
+  .fetch_optional(pool)
+  .await?;
+
+  Ok(record.map(|(user_id,)| user_id))
+}
+
+#[async_trait]
+impl UserRepository for PgPool {
+  async fn create_user(&self, username: String, password_hash: String) -> Result<User, MyError> {
+    db_create_user(self, username, password_hash).await
+  }
+
+  async fn get_user(&self, id: i32) -> Result<User, MyError> {
+    db_get_user(self, id).await
+  }
+
+  async fn get_user_by_name(&self, username: &str) -> Result<Option<User>, MyError> {
+    db_get_user_by_name(self, username).await
+  }
+
+  async fn add_refresh_token(
+    &self,
+    id: String,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+  ) -> Result<(), MyError> {
+    db_add_refresh_token(self, id, user_id, expires_at).await
+  }
+
+  async fn take_refresh_token(&self, id: &str) -> Result<Option<i32>, MyError> {
+    db_take_refresh_token(self, id).await
+  }
+}
@@ -1,35 +1,144 @@
-use actix_web::{error, http::StatusCode, HttpResponse};
+use crate::validation::FieldError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{error, HttpResponse};
 use serde::Serialize;
 use sqlx::error::Error as SqlxError;
+use std::error::Error as StdError;
 use std::fmt;
 
+pub type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// Postgres `SQLSTATE`s that are the client's fault.
+const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+/// What clients are told when one of these constraints fails. The database's
+/// own message names tables and columns, so it is only logged.
+const CONSTRAINT_MESSAGES: &[(&str, &str)] = &[
+  ("app_user_username_key", "Username is taken"),
+  (
+    "app_user_teacher_id_key",
+    "You already have a teacher profile",
+  ),
+  (
+    "app_user_student_id_key",
+    "You already have a student profile",
+  ),
+  (
+    "enrollment_course_id_student_id_key",
+    "Already enrolled in this course",
+  ),
+  (
+    "review_course_id_student_id_key",
+    "You have already reviewed this course",
+  ),
+  ("course_teacher_id_fkey", "Teacher not found"),
+  ("teacher_availability_teacher_id_fkey", "Teacher not found"),
+  ("enrollment_course_id_fkey", "Course not found"),
+  ("review_course_id_fkey", "Course not found"),
+  ("course_schedule_course_id_fkey", "Course not found"),
+  ("course_session_course_id_fkey", "Course not found"),
+  ("enrollment_student_id_fkey", "Student not found"),
+  ("review_student_id_fkey", "Student not found"),
+];
+
 #[derive(Debug, Serialize)]
 pub struct MyErrorResponse {
+  /// One of [`MyError::code`], for clients to match on.
+  code: &'static str,
   error_message: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  errors: Vec<FieldError>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub enum MyError {
-  DBError(String),
-  ActixError(String),
+  /// A database failure that isn't the client's fault.
+  DBError(SqlxError),
+  /// Any other failure on our side. `message` says what we were doing.
+  Internal {
+    message: String,
+    source: Option<BoxError>,
+  },
   NotFound(String),
+  InvalidInput(String),
+  ValidationError(Vec<FieldError>),
+  Unauthorized(String),
+  Forbidden(String),
+  /// The change would duplicate a unique value.
+  Conflict(String),
+  /// The change refers to a row that doesn't exist.
+  Unprocessable(String),
 }
 
 impl MyError {
-  fn error_response(&self) -> String {
+  pub fn internal(message: impl Into<String>) -> Self {
+    MyError::Internal {
+      message: message.into(),
+      source: None,
+    }
+  }
+
+  pub fn internal_with(message: impl Into<String>, source: impl Into<BoxError>) -> Self {
+    MyError::Internal {
+      message: message.into(),
+      source: Some(source.into()),
+    }
+  }
+
+  /// A stable name for the kind of error, sent to clients as `code`.
+  pub fn code(&self) -> &'static str {
     match self {
-      MyError::DBError(msg) => {
-        println!("Database error occured: {}", msg);
-        "Database Error".into()
-      }
-      MyError::ActixError(msg) => {
-        println!("Server error occured: {}", msg);
-        "Internal Server Error".into()
-      }
-      MyError::NotFound(msg) => {
-        println!("Not found error occured: {}", msg);
-        msg.into()
-      }
+      MyError::DBError(_) => "database_error",
+      MyError::Internal { .. } => "internal_error",
+      MyError::NotFound(_) => "not_found",
+      MyError::InvalidInput(_) => "invalid_input",
+      MyError::ValidationError(_) => "validation_failed",
+      MyError::Unauthorized(_) => "unauthorized",
+      MyError::Forbidden(_) => "forbidden",
+      MyError::Conflict(_) => "conflict",
+      MyError::Unprocessable(_) => "unprocessable",
+    }
+  }
+
+  /// What the client is told. Server errors don't reveal their details.
+  fn client_message(&self) -> String {
+    match self {
+      MyError::DBError(_) => "Database Error".into(),
+      MyError::Internal { .. } => "Internal Server Error".into(),
+      MyError::ValidationError(_) => "Invalid input".into(),
+      MyError::NotFound(msg)
+      | MyError::InvalidInput(msg)
+      | MyError::Unauthorized(msg)
+      | MyError::Forbidden(msg)
+      | MyError::Conflict(msg)
+      | MyError::Unprocessable(msg) => msg.clone(),
+    }
+  }
+
+  /// The messages of every error that led to this one, outermost first.
+  pub fn sources(&self) -> Vec<String> {
+    let mut sources = Vec::new();
+    let mut source = self.source();
+    while let Some(err) = source {
+      sources.push(err.to_string());
+      source = err.source();
+    }
+    sources
+  }
+
+  fn log(&self) {
+    let status = error::ResponseError::status_code(self);
+    if status.is_server_error() {
+      tracing::error!(
+        code = self.code(),
+        status = status.as_u16(),
+        sources = ?self.sources(),
+        "{}",
+        self
+      );
+    } else {
+      tracing::debug!(code = self.code(), status = status.as_u16(), "{}", self);
     }
   }
 }
@@ -37,31 +146,158 @@ impl MyError {
 impl error::ResponseError for MyError {
   fn status_code(&self) -> StatusCode {
     match self {
-      MyError::DBError(_) | MyError::ActixError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      MyError::DBError(_) | MyError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
       MyError::NotFound(_) => StatusCode::NOT_FOUND,
+      MyError::InvalidInput(_) | MyError::ValidationError(_) => StatusCode::BAD_REQUEST,
+      MyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+      MyError::Forbidden(_) => StatusCode::FORBIDDEN,
+      MyError::Conflict(_) => StatusCode::CONFLICT,
+      MyError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
     }
   }
+
   fn error_response(&self) -> HttpResponse {
-    HttpResponse::build(self.status_code()).json(MyErrorResponse {
-      error_message: self.error_response(),
+    self.log();
+    let mut res = HttpResponse::build(self.status_code());
+    if let MyError::Unauthorized(_) = self {
+      res.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+    }
+    let errors = match self {
+      MyError::ValidationError(errors) => errors.clone(),
+      _ => Vec::new(),
+    };
+    res.json(MyErrorResponse {
+      code: self.code(),
+      error_message: self.client_message(),
+      errors,
     })
   }
 }
 
 impl fmt::Display for MyError {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{}", self)
+    match self {
+      MyError::DBError(_) => write!(f, "Database error"),
+      MyError::Internal { message, .. } => write!(f, "{}", message),
+      MyError::ValidationError(errors) => {
+        write!(f, "Invalid input:")?;
+        for error in errors {
+          write!(f, " {} {};", error.field, error.reason)?;
+        }
+        Ok(())
+      }
+      MyError::NotFound(msg)
+      | MyError::InvalidInput(msg)
+      | MyError::Unauthorized(msg)
+      | MyError::Forbidden(msg)
+      | MyError::Conflict(msg)
+      | MyError::Unprocessable(msg) => write!(f, "{}", msg),
+    }
+  }
+}
+
+impl StdError for MyError {
+  fn source(&self) -> Option<&(dyn StdError + 'static)> {
+    match self {
+      MyError::DBError(err) => Some(err),
+      MyError::Internal {
+        source: Some(source),
+        ..
+      } => Some(source.as_ref()),
+      _ => None,
+    }
   }
 }
 
 impl From<error::Error> for MyError {
   fn from(err: error::Error) -> Self {
-    MyError::ActixError(err.to_string())
+    MyError::internal(err.to_string())
+  }
+}
+
+impl From<error::BlockingError> for MyError {
+  fn from(err: error::BlockingError) -> Self {
+    MyError::internal_with("Blocking task failed", err)
   }
 }
 
 impl From<SqlxError> for MyError {
   fn from(err: SqlxError) -> Self {
-    MyError::DBError(err.to_string())
+    match constraint_violation(&err) {
+      Some(violation) => violation,
+      None => MyError::DBError(err),
+    }
+  }
+}
+
+/// A failed unique or foreign key constraint, with a fixed message for the
+/// client. The database's own message is logged instead.
+fn constraint_violation(err: &SqlxError) -> Option<MyError> {
+  let db = err.as_database_error()?;
+  let code = db.code()?;
+  let fallback = match code.as_ref() {
+    UNIQUE_VIOLATION => "The record already exists",
+    FOREIGN_KEY_VIOLATION => "A record it refers to does not exist",
+    _ => return None,
+  };
+  tracing::debug!(constraint = ?db.constraint(), "{}", db.message());
+  let message = constraint_message(db.constraint())
+    .unwrap_or(fallback)
+    .to_string();
+  Some(match code.as_ref() {
+    UNIQUE_VIOLATION => MyError::Conflict(message),
+    _ => MyError::Unprocessable(message),
+  })
+}
+
+/// The fixed client message for a violated constraint, if it is one we know.
+pub fn constraint_message(constraint: Option<&str>) -> Option<&'static str> {
+  let constraint = constraint?;
+  CONSTRAINT_MESSAGES
+    .iter()
+    .find(|(name, _)| *name == constraint)
+    .map(|(_, message)| *message)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::body::to_bytes;
+  use actix_web::ResponseError;
+  use std::io;
+
+  #[test]
+  fn test_display_and_sources() {
+    let err = MyError::NotFound("Course not found".into());
+    assert_eq!(err.to_string(), "Course not found");
+
+    let io = io::Error::other("disk on fire");
+    let err = MyError::internal_with("Failed to save", io);
+    assert_eq!(err.to_string(), "Failed to save");
+    assert_eq!(err.sources(), ["disk on fire"]);
+  }
+
+  #[test]
+  fn test_constraint_message() {
+    assert_eq!(
+      constraint_message(Some("course_teacher_id_fkey")),
+      Some("Teacher not found")
+    );
+    assert_eq!(constraint_message(Some("unknown_key")), None);
+    assert_eq!(constraint_message(None), None);
+  }
+
+  #[actix_rt::test]
+  async fn test_error_response() {
+    let err = MyError::Unprocessable("no such teacher".into());
+    assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let err = MyError::internal_with("Failed to save", io::Error::other("secret detail"));
+    let res = err.error_response();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = to_bytes(res.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "internal_error");
+    assert_eq!(body["error_message"], "Internal Server Error");
   }
 }
@@ -0,0 +1,193 @@
+use crate::auth::{hash_password, verify_user_password, TokenKind, ACCESS_TOKEN_SECS};
+use crate::db_access::UserRepository;
+use crate::error::MyError;
+use crate::models::user::*;
+use crate::state::AppState;
+use crate::validation::Validate;
+use actix_web::{web, HttpResponse};
+
+/// A new access token, and a refresh token that can be exchanged for new
+/// tokens once.
+async fn issue_tokens(app_state: &AppState, user_id: i32) -> Result<TokensResponse, MyError> {
+  let (access_token, _) = app_state.keys.issue(user_id, TokenKind::Access)?;
+  let (refresh_token, claims) = app_state.keys.issue(user_id, TokenKind::Refresh)?;
+  let expires_at = claims.expires_at();
+  app_state
+    .db
+    .add_refresh_token(claims.jti, user_id, expires_at)
+    .await?;
+
+  Ok(TokensResponse {
+    access_token,
+    refresh_token,
+    token_type: "Bearer",
+    expires_in: ACCESS_TOKEN_SECS,
+  })
+}
+
+pub async fn register(
+  app_state: web::Data<AppState>,
+  dto: web::Json<CredentialsDTO>,
+) -> Result<HttpResponse, MyError> {
+  dto.check()?;
+  let CredentialsDTO { username, password } = dto.into_inner();
+  let password_hash = web::block(move || hash_password(&password)).await??;
+
+  app_state
+    .db
+    .create_user(username, password_hash)
+    .await
+    .map(|user| HttpResponse::Ok().json(user))
+}
+
+pub async fn login(
+  app_state: web::Data<AppState>,
+  dto: web::Json<CredentialsDTO>,
+) -> Result<HttpResponse, MyError> {
+  let CredentialsDTO { username, password } = dto.into_inner();
+  let user = app_state.db.get_user_by_name(&username).await?;
+  let (user, verified) = web::block(move || {
+    let verified = verify_user_password(&password, user.as_ref());
+    (user, verified)
+  })
+  .await?;
+  // Same answer and timing either way, so logins can't be used to find
+  // usernames.
+  let user = user
+    .filter(|_| verified)
+    .ok_or_else(|| MyError::Unauthorized("Invalid username or password".into()))?;
+
+  let tokens = issue_tokens(&app_state, user.id).await?;
+  Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Exchanges a refresh token for new tokens. Each refresh token works once.
+pub async fn refresh(
+  app_state: web::Data<AppState>,
+  dto: web::Json<RefreshDTO>,
+) -> Result<HttpResponse, MyError> {
+  let claims = app_state
+    .keys
+    .verify(&dto.refresh_token, TokenKind::Refresh)?;
+  let user_id = app_state
+    .db
+    .take_refresh_token(&claims.jti)
+    .await?
+    .ok_or_else(|| MyError::Unauthorized("Refresh token has been used or revoked".into()))?;
+
+  let tokens = issue_tokens(&app_state, user_id).await?;
+  Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Revokes a refresh token. Access tokens stay valid until they expire.
+pub async fn logout(
+  app_state: web::Data<AppState>,
+  dto: web::Json<RefreshDTO>,
+) -> Result<HttpResponse, MyError> {
+  let claims = app_state
+    .keys
+    .verify(&dto.refresh_token, TokenKind::Refresh)?;
+  app_state.db.take_refresh_token(&claims.jti).await?;
+
+  Ok(HttpResponse::Ok().json("Logged out"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::auth::verify_password;
+  use actix_web::body::to_bytes;
+  use actix_web::http::StatusCode;
+  use actix_web::ResponseError;
+
+  async fn login_as(
+    app_state: &web::Data<AppState>,
+    password: &str,
+  ) -> Result<HttpResponse, MyError> {
+    let dto = web::Json(CredentialsDTO {
+      username: "liming".into(),
+      password: password.into(),
+    });
+    login(app_state.clone(), dto).await
+  }
+
+  /// The refresh token from a successful login or refresh.
+  async fn refresh_token(res: HttpResponse) -> String {
+    let body = to_bytes(res.into_body()).await.unwrap();
+    let tokens: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    tokens["refresh_token"].as_str().unwrap().to_string()
+  }
+
+  #[actix_rt::test]
+  async fn test_register() {
+    let app_state = AppState::for_test();
+
+    let dto = web::Json(CredentialsDTO {
+      username: "wanglei".into(),
+      password: "hunter22".into(),
+    });
+    let res = register(app_state.clone(), dto).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let user = app_state
+      .db
+      .get_user_by_name("wanglei")
+      .await
+      .unwrap()
+      .unwrap();
+    assert!(verify_password("hunter22", &user.password_hash));
+
+    let dto = web::Json(CredentialsDTO {
+      username: "liming".into(),
+      password: "hunter22".into(),
+    });
+    let res = register(app_state, dto).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::BAD_REQUEST);
+  }
+
+  #[actix_rt::test]
+  async fn test_login() {
+    let app_state = AppState::for_test();
+
+    let res = login_as(&app_state, "password").await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = login_as(&app_state, "wrong").await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::UNAUTHORIZED);
+
+    let dto = web::Json(CredentialsDTO {
+      username: "nobody".into(),
+      password: "password".into(),
+    });
+    let res = login(app_state, dto).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::UNAUTHORIZED);
+  }
+
+  #[actix_rt::test]
+  async fn test_refresh_once() {
+    let app_state = AppState::for_test();
+    let token = refresh_token(login_as(&app_state, "password").await.unwrap()).await;
+
+    let dto = || {
+      web::Json(RefreshDTO {
+        refresh_token: token.clone(),
+      })
+    };
+    let res = refresh(app_state.clone(), dto()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let next = refresh_token(res).await;
+
+    let res = refresh(app_state.clone(), dto()).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::UNAUTHORIZED);
+
+    let dto = web::Json(RefreshDTO {
+      refresh_token: next.clone(),
+    });
+    logout(app_state.clone(), dto).await.unwrap();
+    let dto = web::Json(RefreshDTO {
+      refresh_token: next,
+    });
+    let res = refresh(app_state, dto).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::UNAUTHORIZED);
+  }
+}
@@ -1,28 +1,46 @@
-use crate::db_access::course::*;
+use super::paged_response;
+use crate::auth::AuthUser;
+use crate::db_access::{CourseRepository, TeacherRepository};
 use crate::error::MyError;
-use crate::models::course::*;
+use crate::models::{course::*, Page, Sort};
 use crate::state::AppState;
-use actix_web::{web, HttpResponse};
+use crate::validation::FieldError;
+use actix_web::{web, HttpRequest, HttpResponse};
 
 pub async fn create_course(
   app_state: web::Data<AppState>,
+  user: AuthUser,
   dto: web::Json<CreateCourseDTO>,
 ) -> Result<HttpResponse, MyError> {
-  db_create_course(&app_state.db, dto.try_into()?)
+  let dto: CreateCourseDTO = dto.try_into()?;
+  match app_state.db.get_teacher(dto.teacher_id).await {
+    Err(MyError::NotFound(_)) => {
+      let error = FieldError::new("teacher_id", "no such teacher");
+      return Err(MyError::ValidationError(vec![error]));
+    }
+    result => result?,
+  };
+  user.check_owns(&app_state, dto.teacher_id).await?;
+  app_state
+    .db
+    .create_course(dto)
     .await
     .map(|course| HttpResponse::Ok().json(course))
 }
 
 pub async fn get_courses(
+  req: HttpRequest,
   app_state: web::Data<AppState>,
   query: web::Query<GetCoursesQuery>,
 ) -> Result<HttpResponse, MyError> {
-  let result = match query.teacher_id {
-    Some(teahcer_id) => db_get_courses_of_teacher(&app_state.db, teahcer_id).await,
-    None => db_get_courses(&app_state.db).await,
-  };
-
-  result.map(|courses| HttpResponse::Ok().json(courses))
+  let page = Page::new(query.page, query.per_page)?;
+  let sort = Sort::parse(query.sort.as_deref(), COURSE_SORT_COLUMNS)?;
+  let courses = app_state
+    .db
+    .get_courses(query.filter()?, sort, page)
+    .await?;
+
+  Ok(paged_response(&req, page, courses))
 }
 
 pub async fn get_course(
@@ -30,28 +48,42 @@ pub async fn get_course(
   path: web::Path<i32>,
 ) -> Result<HttpResponse, MyError> {
   let id = path.into_inner();
-  db_get_course(&app_state.db, id)
+  app_state
+    .db
+    .get_course(id)
     .await
     .map(|course| HttpResponse::Ok().json(course))
 }
 
 pub async fn remove_course(
   app_state: web::Data<AppState>,
+  user: AuthUser,
   path: web::Path<i32>,
 ) -> Result<HttpResponse, MyError> {
   let id = path.into_inner();
-  db_remove_course(&app_state.db, id)
+  let course = app_state.db.get_course(id).await?;
+  user.check_owns(&app_state, course.teacher_id).await?;
+  app_state
+    .db
+    .remove_course(id)
     .await
     .map(|msg| HttpResponse::Ok().json(msg))
 }
 
+/// Handles `PATCH` and, for older clients, `PUT`. Only the fields in the body
+/// are changed.
 pub async fn update_course(
   app_state: web::Data<AppState>,
+  user: AuthUser,
   path: web::Path<i32>,
   update_course: web::Json<UpdateCourseDTO>,
 ) -> Result<HttpResponse, MyError> {
   let id = path.into_inner();
-  db_update_course(&app_state.db, id, update_course.try_into()?)
+  let course = app_state.db.get_course(id).await?;
+  user.check_owns(&app_state, course.teacher_id).await?;
+  app_state
+    .db
+    .update_course(id, update_course.try_into()?)
     .await
     .map(|course| HttpResponse::Ok().json(course))
 }
@@ -59,26 +91,32 @@ pub async fn update_course(
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::db_access::UserRepository;
   use actix_web::http::StatusCode;
+  use actix_web::test::TestRequest;
   use actix_web::ResponseError;
-  use dotenvy::dotenv;
-  use sqlx::postgres::PgPoolOptions;
-  use std::env;
-  use std::sync::Mutex;
 
-  #[ignore]
-  #[actix_rt::test]
-  async fn test_create_course() {
-    dotenv().ok();
+  /// Owns teacher 1 in the fixtures.
+  const OWNER: AuthUser = AuthUser { id: 1 };
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+  fn create_dto(name: &str, price: i32, language: &str) -> CreateCourseDTO {
+    CreateCourseDTO {
+      teacher_id: 1,
+      name: name.into(),
+      description: None,
+      format: None,
+      structure: None,
+      duration: None,
+      price: Some(price),
+      language: Some(language.into()),
+      level: None,
+      capacity: None,
+    }
+  }
 
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+  #[actix_rt::test]
+  async fn test_create_course() {
+    let app_state = AppState::for_test();
     let course = web::Json(CreateCourseDTO {
       teacher_id: 1,
       name: "JavaScript ES6".to_string(),
@@ -87,50 +125,133 @@ mod tests {
       structure: None,
       duration: None,
       price: None,
-      language: Some("JavaScript".into()),
+      language: Some("English".into()),
       level: Some("初级".into()),
+      capacity: None,
     });
 
-    let res = create_course(app_state, course).await.unwrap();
+    let res = create_course(app_state, OWNER, course).await.unwrap();
     assert_eq!(res.status(), StatusCode::OK);
   }
 
   #[actix_rt::test]
-  async fn test_get_courses_of_teacher() {
-    dotenv().ok();
+  async fn test_create_course_invalid() {
+    let app_state = AppState::for_test();
+    let mut dto = create_dto(&"很长的课程名".repeat(30), -1, "Klingon");
+    dto.teacher_id = 9999;
+
+    let res = create_course(app_state.clone(), OWNER, web::Json(dto)).await;
+    match res.unwrap_err() {
+      MyError::ValidationError(errors) => {
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["name", "price", "language"]);
+      }
+      err => panic!("unexpected {:?}", err),
+    }
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+    let mut dto = create_dto("Rust 宏", 150, "中文");
+    dto.teacher_id = 9999;
+    let res = create_course(app_state, OWNER, web::Json(dto)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::BAD_REQUEST);
+  }
 
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+  #[actix_rt::test]
+  async fn test_change_others_course() {
+    let app_state = AppState::for_test();
+    let other = app_state
+      .db
+      .create_user("wanglei".into(), "".into())
+      .await
+      .unwrap();
+    let user = AuthUser { id: other.id };
+
+    let dto = web::Json(create_dto("Rust 宏", 150, "中文"));
+    let res = create_course(app_state.clone(), user, dto).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+
+    let dto = web::Json(UpdateCourseDTO::default());
+    let res = update_course(app_state.clone(), user, web::Path::from(1), dto).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+
+    let res = remove_course(app_state.clone(), user, web::Path::from(1)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+    assert!(app_state.db.get_course(1).await.is_ok());
+  }
+
+  #[actix_rt::test]
+  async fn test_get_courses_of_teacher() {
+    let app_state = AppState::for_test();
 
     let res = get_courses(
+      TestRequest::default().to_http_request(),
       app_state,
       web::Query(GetCoursesQuery {
         teacher_id: Some(1),
+        ..GetCoursesQuery::default()
       }),
     )
     .await
     .unwrap();
     assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "1");
   }
 
   #[actix_rt::test]
-  async fn test_get_course_success() {
-    dotenv().ok();
+  async fn test_get_courses_filtered_and_paged() {
+    let app_state = AppState::for_test();
+    for (name, price, language) in [
+      ("Rust 进阶", 300, "中文"),
+      ("Async Rust", 200, "English"),
+      ("Rust 宏", 150, "中文"),
+    ] {
+      let dto = create_dto(name, price, language);
+      app_state.db.create_course(dto).await.unwrap();
+    }
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+    let query = "language=%E4%B8%AD%E6%96%87&q=rust&sort=-price&page=2&per_page=1";
+    let req = TestRequest::get()
+      .uri(&format!("/courses/?{}", query))
+      .to_http_request();
+    let query = web::Query::<GetCoursesQuery>::from_query(query).unwrap();
+    let res = get_courses(req, app_state.clone(), query).await.unwrap();
+
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "3");
+    let link = res.headers().get("Link").unwrap().to_str().unwrap();
+    assert!(link.contains("sort=-price&page=3&per_page=1>; rel=\"next\""));
+    assert!(link.contains("page=1&per_page=1>; rel=\"prev\""));
+
+    let filter = CourseFilter {
+      min_price: Some(120),
+      max_price: Some(250),
+      ..CourseFilter::default()
+    };
+    let sort = Sort::parse(Some("-price"), COURSE_SORT_COLUMNS).unwrap();
+    let courses = app_state
+      .db
+      .get_courses(filter, sort, Page::default())
+      .await
+      .unwrap();
+    let names: Vec<_> = courses.items.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["Async Rust", "Rust 宏"]);
+  }
+
+  #[actix_rt::test]
+  async fn test_get_courses_invalid_query() {
+    let app_state = AppState::for_test();
 
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
+    let query = web::Query(GetCoursesQuery {
+      sort: Some("teacher_id".into()),
+      ..GetCoursesQuery::default()
     });
+    let req = TestRequest::default().to_http_request();
+    let res = get_courses(req, app_state, query).await;
+
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::BAD_REQUEST);
+  }
+
+  #[actix_rt::test]
+  async fn test_get_course_success() {
+    let app_state = AppState::for_test();
 
     let res = get_course(app_state, web::Path::from(1)).await.unwrap();
     assert_eq!(res.status(), StatusCode::OK);
@@ -138,37 +259,16 @@ mod tests {
 
   #[actix_rt::test]
   async fn test_get_course_failure() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    let app_state = AppState::for_test();
 
     let res = get_course(app_state, web::Path::from(9999)).await;
 
-    match res {
-      Ok(_) => println!("Something went wrong"),
-      Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND),
-    };
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::NOT_FOUND);
   }
 
   #[actix_rt::test]
   async fn test_update_course_success() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    let app_state = AppState::for_test();
 
     let update_course_dto = web::Json(UpdateCourseDTO {
       name: Some("Course changed".into()),
@@ -179,52 +279,66 @@ mod tests {
       price: None,
       language: None,
       level: None,
+      capacity: None,
     });
 
-    let res = update_course(app_state, web::Path::from(1), update_course_dto)
+    let res = update_course(app_state, OWNER, web::Path::from(1), update_course_dto)
       .await
       .unwrap();
 
     assert_eq!(res.status(), StatusCode::OK)
   }
 
-  #[ignore]
   #[actix_rt::test]
-  async fn test_remove_course_success() {
-    dotenv().ok();
+  async fn test_update_course_partial() {
+    let app_state = AppState::for_test();
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
+    let dto = web::Json(UpdateCourseDTO {
+      description: Some(Some("Ownership and borrowing".into())),
+      language: Some(None),
+      ..UpdateCourseDTO::default()
     });
+    update_course(app_state.clone(), OWNER, web::Path::from(1), dto)
+      .await
+      .unwrap();
 
-    let res = remove_course(app_state, web::Path::from(1)).await.unwrap();
+    let course = app_state.db.get_course(1).await.unwrap();
+    assert_eq!(course.name, "Rust 入门");
+    assert_eq!(
+      course.description.as_deref(),
+      Some("Ownership and borrowing")
+    );
+    assert_eq!(course.price, Some(100));
+    assert_eq!(course.language, None);
+  }
 
-    assert_eq!(res.status(), StatusCode::OK)
+  #[actix_rt::test]
+  async fn test_update_course_failure() {
+    let app_state = AppState::for_test();
+
+    let dto = web::Json(UpdateCourseDTO::default());
+    let res = update_course(app_state, OWNER, web::Path::from(9999), dto).await;
+
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::NOT_FOUND);
   }
 
   #[actix_rt::test]
-  async fn test_remove_course_failure() {
-    dotenv().ok();
+  async fn test_remove_course_success() {
+    let app_state = AppState::for_test();
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+    let res = remove_course(app_state, OWNER, web::Path::from(1))
+      .await
+      .unwrap();
 
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    assert_eq!(res.status(), StatusCode::OK)
+  }
 
-    let res = remove_course(app_state, web::Path::from(9999)).await;
+  #[actix_rt::test]
+  async fn test_remove_course_failure() {
+    let app_state = AppState::for_test();
 
-    match res {
-      Ok(_) => println!("Something went wrong"),
-      Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND),
-    }
+    let res = remove_course(app_state, OWNER, web::Path::from(9999)).await;
+
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::NOT_FOUND);
   }
 }
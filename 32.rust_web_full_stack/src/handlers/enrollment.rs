@@ -0,0 +1,134 @@
+use crate::auth::AuthUser;
+use crate::db_access::{CourseRepository, EnrollmentRepository};
+use crate::error::MyError;
+use crate::models::student::*;
+use crate::state::AppState;
+use crate::validation::Validate;
+use actix_web::{web, HttpResponse};
+
+/// Enrolls the signed-in user's student in the course, or puts them on its
+/// waitlist if it is full.
+pub async fn enroll(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  let student_id = user.student_id(&app_state).await?;
+  app_state
+    .db
+    .enroll(course_id, student_id)
+    .await
+    .map(|enrollment| HttpResponse::Ok().json(enrollment))
+}
+
+/// Drops a student from the course. Students can drop themselves, and
+/// teachers can drop anyone from their own courses.
+pub async fn drop_enrollment(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, MyError> {
+  let (course_id, student_id) = path.into_inner();
+  let course = app_state.db.get_course(course_id).await?;
+  let account = user.user(&app_state).await?;
+  if account.student_id != Some(student_id) && account.teacher_id != Some(course.teacher_id) {
+    return Err(MyError::Forbidden(
+      "Only the student or the course's teacher can do this".into(),
+    ));
+  }
+  app_state
+    .db
+    .drop_enrollment(course_id, student_id)
+    .await
+    .map(|enrollment| HttpResponse::Ok().json(enrollment))
+}
+
+/// The course's enrollments, including its waitlist in order. Only its
+/// teacher can see them.
+pub async fn get_enrollments_of_course(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  query: web::Query<GetEnrollmentsQuery>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  query.check()?;
+  let course = app_state.db.get_course(course_id).await?;
+  user.check_owns(&app_state, course.teacher_id).await?;
+  app_state
+    .db
+    .get_enrollments_of_course(course_id, query.into_inner().status)
+    .await
+    .map(|enrollments| HttpResponse::Ok().json(enrollments))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::models::course::UpdateCourseDTO;
+  use actix_web::http::StatusCode;
+  use actix_web::ResponseError;
+
+  async fn status_of(app_state: &AppState, student_id: i32) -> String {
+    let enrollments = app_state
+      .db
+      .get_enrollments_of_student(student_id, None)
+      .await
+      .unwrap();
+    enrollments[0].status.clone()
+  }
+
+  #[actix_rt::test]
+  async fn test_waitlist() {
+    let app_state = AppState::for_test();
+    let dto = UpdateCourseDTO {
+      capacity: Some(Some(1)),
+      ..UpdateCourseDTO::default()
+    };
+    app_state.db.update_course(1, dto).await.unwrap();
+    let (alice, alice_id) = app_state.new_student("alice").await;
+    let (bob, bob_id) = app_state.new_student("bob").await;
+
+    enroll(app_state.clone(), alice, web::Path::from(1))
+      .await
+      .unwrap();
+    enroll(app_state.clone(), bob, web::Path::from(1))
+      .await
+      .unwrap();
+    assert_eq!(status_of(&app_state, alice_id).await, ENROLLED);
+    assert_eq!(status_of(&app_state, bob_id).await, WAITLISTED);
+
+    let res = enroll(app_state.clone(), bob, web::Path::from(1)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+    let res = drop_enrollment(app_state.clone(), bob, web::Path::from((1, alice_id))).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+
+    drop_enrollment(app_state.clone(), alice, web::Path::from((1, alice_id)))
+      .await
+      .unwrap();
+    assert_eq!(status_of(&app_state, alice_id).await, DROPPED);
+    assert_eq!(status_of(&app_state, bob_id).await, ENROLLED);
+  }
+
+  #[actix_rt::test]
+  async fn test_enrollments_of_course_for_teacher_only() {
+    let app_state = AppState::for_test();
+    let (alice, _) = app_state.new_student("alice").await;
+    enroll(app_state.clone(), alice, web::Path::from(1))
+      .await
+      .unwrap();
+
+    let query = || web::Query(GetEnrollmentsQuery { status: None });
+    let res =
+      get_enrollments_of_course(app_state.clone(), alice, web::Path::from(1), query()).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+
+    let owner = AuthUser { id: 1 };
+    let res = get_enrollments_of_course(app_state, owner, web::Path::from(1), query())
+      .await
+      .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+  }
+}
@@ -1,3 +1,55 @@
+pub mod auth;
 pub mod course;
+pub mod enrollment;
 pub mod general;
+pub mod review;
+pub mod schedule;
+pub mod student;
 pub mod teacher;
+
+use crate::models::{Page, Paged};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+
+/// Responds with one page of a list: the rows as a JSON array, how many there
+/// are on all pages in `X-Total-Count`, and the other pages in `Link`.
+pub fn paged_response<T: Serialize>(
+  req: &HttpRequest,
+  page: Page,
+  paged: Paged<T>,
+) -> HttpResponse {
+  let last = page.count(paged.total);
+  let params: Vec<&str> = req
+    .query_string()
+    .split('&')
+    .filter(|p| !p.is_empty() && !p.starts_with("page=") && !p.starts_with("per_page="))
+    .collect();
+  let link = |number: i64, rel: &str| {
+    let mut query = params.join("&");
+    if !query.is_empty() {
+      query.push('&');
+    }
+    format!(
+      "<{}?{}page={}&per_page={}>; rel=\"{}\"",
+      req.path(),
+      query,
+      number,
+      page.size,
+      rel
+    )
+  };
+
+  let mut links = vec![link(1, "first")];
+  if page.number > 1 {
+    links.push(link((page.number - 1).min(last), "prev"));
+  }
+  if page.number < last {
+    links.push(link(page.number + 1, "next"));
+  }
+  links.push(link(last, "last"));
+
+  HttpResponse::Ok()
+    .insert_header(("X-Total-Count", paged.total.to_string()))
+    .insert_header(("Link", links.join(", ")))
+    .json(paged.items)
+}
@@ -0,0 +1,223 @@
+use super::paged_response;
+use crate::auth::AuthUser;
+use crate::db_access::{CourseRepository, EnrollmentRepository, ReviewRepository};
+use crate::error::MyError;
+use crate::models::{review::*, student::ENROLLED, Page};
+use crate::state::AppState;
+use crate::validation::Validate;
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Posts the signed-in user's review of a course they are enrolled in.
+pub async fn create_review(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  dto: web::Json<CreateReviewDTO>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  let dto: CreateReviewDTO = dto.try_into()?;
+  app_state.db.get_course(course_id).await?;
+  let student_id = user.student_id(&app_state).await?;
+  let enrolled = app_state
+    .db
+    .get_enrollments_of_student(student_id, Some(ENROLLED.into()))
+    .await?
+    .iter()
+    .any(|e| e.course_id == course_id);
+  if !enrolled {
+    return Err(MyError::Forbidden(
+      "Only students enrolled in the course can review it".into(),
+    ));
+  }
+  app_state
+    .db
+    .create_review(course_id, student_id, dto)
+    .await
+    .map(|review| HttpResponse::Ok().json(review))
+}
+
+pub async fn get_reviews_of_course(
+  req: HttpRequest,
+  app_state: web::Data<AppState>,
+  path: web::Path<i32>,
+  query: web::Query<GetReviewsQuery>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  let page = Page::new(query.page, query.per_page)?;
+  app_state.db.get_course(course_id).await?;
+  let reviews = app_state.db.get_reviews_of_course(course_id, page).await?;
+
+  Ok(paged_response(&req, page, reviews))
+}
+
+/// Lets the course's teacher reply to a review, replacing any earlier reply.
+pub async fn reply_to_review(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  dto: web::Json<ReplyDTO>,
+) -> Result<HttpResponse, MyError> {
+  let id = path.into_inner();
+  dto.check()?;
+  let review = app_state.db.get_review(id).await?;
+  let course = app_state.db.get_course(review.course_id).await?;
+  user.check_owns(&app_state, course.teacher_id).await?;
+  app_state
+    .db
+    .reply_to_review(id, dto.into_inner().body)
+    .await
+    .map(|review| HttpResponse::Ok().json(review))
+}
+
+/// Reviews moderators have hidden, most recently hidden first, so they can
+/// be looked over again and shown if hidden by mistake.
+pub async fn get_hidden_reviews(
+  req: HttpRequest,
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  query: web::Query<GetReviewsQuery>,
+) -> Result<HttpResponse, MyError> {
+  let page = Page::new(query.page, query.per_page)?;
+  user.check_moderator(&app_state).await?;
+  let reviews = app_state.db.get_hidden_reviews(page).await?;
+
+  Ok(paged_response(&req, page, reviews))
+}
+
+pub async fn hide_review(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  dto: web::Json<HideReviewDTO>,
+) -> Result<HttpResponse, MyError> {
+  dto.check()?;
+  user.check_moderator(&app_state).await?;
+  app_state
+    .db
+    .set_review_hidden(path.into_inner(), Some(dto.into_inner().reason))
+    .await
+    .map(|review| HttpResponse::Ok().json(review))
+}
+
+pub async fn unhide_review(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  user.check_moderator(&app_state).await?;
+  app_state
+    .db
+    .set_review_hidden(path.into_inner(), None)
+    .await
+    .map(|review| HttpResponse::Ok().json(review))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use actix_web::http::StatusCode;
+  use actix_web::test::TestRequest;
+  use actix_web::ResponseError;
+
+  /// Teaches course 1 in the fixtures.
+  const TEACHER: AuthUser = AuthUser { id: 1 };
+  const MODERATOR: AuthUser = AuthUser { id: 2 };
+
+  /// Signs up a student enrolled in course 1.
+  async fn enrolled_student(app_state: &AppState, name: &str) -> AuthUser {
+    let (user, student_id) = app_state.new_student(name).await;
+    app_state.db.enroll(1, student_id).await.unwrap();
+    user
+  }
+
+  fn review_dto(rating: i32) -> web::Json<CreateReviewDTO> {
+    web::Json(CreateReviewDTO {
+      rating,
+      body: "讲得很清楚".into(),
+    })
+  }
+
+  #[actix_rt::test]
+  async fn test_create_review() {
+    let app_state = AppState::for_test();
+    let alice = enrolled_student(&app_state, "alice").await;
+    let bob = enrolled_student(&app_state, "bob").await;
+    let (carol, _) = app_state.new_student("carol").await;
+
+    let res = create_review(app_state.clone(), carol, web::Path::from(1), review_dto(5)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+    let res = create_review(app_state.clone(), alice, web::Path::from(1), review_dto(6)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::BAD_REQUEST);
+
+    create_review(app_state.clone(), alice, web::Path::from(1), review_dto(5))
+      .await
+      .unwrap();
+    create_review(app_state.clone(), bob, web::Path::from(1), review_dto(2))
+      .await
+      .unwrap();
+    let res = create_review(app_state.clone(), alice, web::Path::from(1), review_dto(1)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+    let course = app_state.db.get_course(1).await.unwrap();
+    assert_eq!(course.review_count, 2);
+    assert_eq!(course.average_rating, Some(3.5));
+  }
+
+  #[actix_rt::test]
+  async fn test_hide_review() {
+    let app_state = AppState::for_test();
+    let alice = enrolled_student(&app_state, "alice").await;
+    create_review(app_state.clone(), alice, web::Path::from(1), review_dto(1))
+      .await
+      .unwrap();
+    let hide = || {
+      web::Json(HideReviewDTO {
+        reason: "Abusive".into(),
+      })
+    };
+
+    let res = hide_review(app_state.clone(), TEACHER, web::Path::from(1), hide()).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+
+    hide_review(app_state.clone(), MODERATOR, web::Path::from(1), hide())
+      .await
+      .unwrap();
+    let course = app_state.db.get_course(1).await.unwrap();
+    assert_eq!((course.review_count, course.average_rating), (0, None));
+    let req = TestRequest::default().to_http_request();
+    let query = web::Query(GetReviewsQuery::default());
+    let res = get_reviews_of_course(req, app_state.clone(), web::Path::from(1), query)
+      .await
+      .unwrap();
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "0");
+
+    unhide_review(app_state.clone(), MODERATOR, web::Path::from(1))
+      .await
+      .unwrap();
+    let course = app_state.db.get_course(1).await.unwrap();
+    assert_eq!((course.review_count, course.average_rating), (1, Some(1.0)));
+  }
+
+  #[actix_rt::test]
+  async fn test_reply_to_review() {
+    let app_state = AppState::for_test();
+    let alice = enrolled_student(&app_state, "alice").await;
+    create_review(app_state.clone(), alice, web::Path::from(1), review_dto(4))
+      .await
+      .unwrap();
+    let reply = || {
+      web::Json(ReplyDTO {
+        body: "谢谢".into(),
+      })
+    };
+
+    let res = reply_to_review(app_state.clone(), alice, web::Path::from(1), reply()).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+
+    reply_to_review(app_state.clone(), TEACHER, web::Path::from(1), reply())
+      .await
+      .unwrap();
+    let review = app_state.db.get_review(1).await.unwrap();
+    assert_eq!(review.reply.as_deref(), Some("谢谢"));
+  }
+}
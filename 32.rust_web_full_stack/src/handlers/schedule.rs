@@ -0,0 +1,289 @@
+use crate::auth::AuthUser;
+use crate::calendar::{self, Event};
+use crate::db_access::{CourseRepository, ScheduleRepository, TeacherRepository};
+use crate::error::MyError;
+use crate::models::schedule::*;
+use crate::state::AppState;
+use actix_web::http::header;
+use actix_web::{web, HttpResponse};
+use std::collections::HashMap;
+
+/// Adds recurring sessions to a course, checked against the teacher's
+/// availability and their other sessions.
+pub async fn create_schedule(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  dto: web::Json<CreateScheduleDTO>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  let course = app_state.db.get_course(course_id).await?;
+  user.check_owns(&app_state, course.teacher_id).await?;
+  let dto: CreateScheduleDTO = dto.try_into()?;
+  let slots = dto.slots()?;
+  app_state
+    .db
+    .create_schedule(course_id, dto, slots)
+    .await
+    .map(|schedule| HttpResponse::Ok().json(schedule))
+}
+
+pub async fn remove_schedule(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<(i32, i32)>,
+) -> Result<HttpResponse, MyError> {
+  let (course_id, id) = path.into_inner();
+  let course = app_state.db.get_course(course_id).await?;
+  user.check_owns(&app_state, course.teacher_id).await?;
+  app_state
+    .db
+    .remove_schedule(course_id, id)
+    .await
+    .map(|msg| HttpResponse::Ok().json(msg))
+}
+
+pub async fn get_sessions_of_course(
+  app_state: web::Data<AppState>,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  app_state.db.get_course(course_id).await?;
+  app_state
+    .db
+    .get_sessions_of_course(course_id)
+    .await
+    .map(|sessions| HttpResponse::Ok().json(sessions))
+}
+
+pub async fn get_availability(
+  app_state: web::Data<AppState>,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  let teacher_id = path.into_inner();
+  app_state.db.get_teacher(teacher_id).await?;
+  app_state
+    .db
+    .get_availability(teacher_id)
+    .await
+    .map(|windows| HttpResponse::Ok().json(windows))
+}
+
+/// Replaces the teacher's weekly availability. An empty list means they can
+/// be booked at any time.
+pub async fn set_availability(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  dto: web::Json<SetAvailabilityDTO>,
+) -> Result<HttpResponse, MyError> {
+  let teacher_id = path.into_inner();
+  app_state.db.get_teacher(teacher_id).await?;
+  user.check_owns(&app_state, teacher_id).await?;
+  app_state
+    .db
+    .set_availability(teacher_id, dto.try_into()?)
+    .await
+    .map(|windows| HttpResponse::Ok().json(windows))
+}
+
+fn event<'a>(session: &Session, summary: &'a str, description: Option<&'a str>) -> Event<'a> {
+  Event {
+    uid: format!("session-{}@tutor-service", session.id),
+    starts_at: session.starts_at,
+    ends_at: session.ends_at,
+    summary,
+    description,
+  }
+}
+
+fn calendar_response(filename: String, ics: String) -> HttpResponse {
+  HttpResponse::Ok()
+    .content_type(calendar::CONTENT_TYPE)
+    .insert_header((
+      header::CONTENT_DISPOSITION,
+      format!("attachment; filename=\"{}\"", filename),
+    ))
+    .body(ics)
+}
+
+/// The course's sessions as an iCalendar file.
+pub async fn get_course_calendar(
+  app_state: web::Data<AppState>,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  let course_id = path.into_inner();
+  let course = app_state.db.get_course(course_id).await?;
+  let sessions = app_state.db.get_sessions_of_course(course_id).await?;
+  let events: Vec<Event> = sessions
+    .iter()
+    .map(|session| event(session, &course.name, course.description.as_deref()))
+    .collect();
+
+  let ics = calendar::calendar(&course.name, &events);
+  Ok(calendar_response(format!("course-{}.ics", course_id), ics))
+}
+
+/// The sessions of all the teacher's courses as an iCalendar file.
+pub async fn get_teacher_calendar(
+  app_state: web::Data<AppState>,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  let teacher_id = path.into_inner();
+  let teacher = app_state.db.get_teacher(teacher_id).await?;
+  let sessions = app_state.db.get_sessions_of_teacher(teacher_id).await?;
+  let mut courses = HashMap::new();
+  for session in &sessions {
+    if !courses.contains_key(&session.course_id) {
+      let course = app_state.db.get_course(session.course_id).await?;
+      courses.insert(session.course_id, course);
+    }
+  }
+  let events: Vec<Event> = sessions
+    .iter()
+    .map(|session| {
+      let course = &courses[&session.course_id];
+      event(session, &course.name, course.description.as_deref())
+    })
+    .collect();
+
+  let ics = calendar::calendar(&teacher.name, &events);
+  Ok(calendar_response(
+    format!("teacher-{}.ics", teacher_id),
+    ics,
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db_access::UserRepository;
+  use crate::models::course::CreateCourseDTO;
+  use actix_web::body::to_bytes;
+  use actix_web::http::StatusCode;
+  use actix_web::ResponseError;
+  use chrono::{NaiveDateTime, NaiveTime};
+
+  /// Owns teacher 1 and course 1 in the fixtures.
+  const OWNER: AuthUser = AuthUser { id: 1 };
+
+  /// Mondays at 19:00 in Shanghai, from 2026-10-26.
+  fn mondays(occurrences: i32) -> web::Json<CreateScheduleDTO> {
+    web::Json(CreateScheduleDTO {
+      time_zone: "Asia/Shanghai".into(),
+      starts_at: "2026-10-26T19:00:00".parse().unwrap(),
+      duration_minutes: 90,
+      repeat: Some(REPEAT_WEEKLY.into()),
+      occurrences: Some(occurrences),
+    })
+  }
+
+  async fn second_course(app_state: &AppState) -> i32 {
+    let dto = CreateCourseDTO {
+      teacher_id: 1,
+      name: "Rust 进阶".into(),
+      description: None,
+      format: None,
+      structure: None,
+      duration: None,
+      price: None,
+      language: None,
+      level: None,
+      capacity: None,
+    };
+    app_state.db.create_course(dto).await.unwrap().id
+  }
+
+  #[actix_rt::test]
+  async fn test_create_schedule() {
+    let app_state = AppState::for_test();
+    let res = create_schedule(app_state.clone(), OWNER, web::Path::from(1), mondays(4))
+      .await
+      .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let sessions = app_state.db.get_sessions_of_course(1).await.unwrap();
+    assert_eq!(sessions.len(), 4);
+    assert_eq!(
+      sessions[0].starts_at,
+      "2026-10-26T11:00:00".parse::<NaiveDateTime>().unwrap()
+    );
+
+    let other = app_state
+      .db
+      .create_user("wanglei".into(), "hash".into())
+      .await
+      .unwrap();
+    let user = AuthUser { id: other.id };
+    let res = create_schedule(app_state, user, web::Path::from(1), mondays(1)).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
+  }
+
+  #[actix_rt::test]
+  async fn test_teacher_double_booked() {
+    let app_state = AppState::for_test();
+    create_schedule(app_state.clone(), OWNER, web::Path::from(1), mondays(4))
+      .await
+      .unwrap();
+    let course_id = second_course(&app_state).await;
+
+    // The fourth Monday of the first course.
+    let mut dto = mondays(1);
+    dto.starts_at = "2026-11-16T20:00:00".parse().unwrap();
+    let res = create_schedule(app_state.clone(), OWNER, web::Path::from(course_id), dto).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::CONFLICT);
+
+    let mut dto = mondays(1);
+    dto.starts_at = "2026-11-16T20:30:00".parse().unwrap();
+    create_schedule(app_state, OWNER, web::Path::from(course_id), dto)
+      .await
+      .unwrap();
+  }
+
+  #[actix_rt::test]
+  async fn test_outside_availability() {
+    let app_state = AppState::for_test();
+    let dto = web::Json(SetAvailabilityDTO {
+      time_zone: "Asia/Shanghai".into(),
+      windows: vec![WindowDTO {
+        weekday: 1,
+        start_time: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+        end_time: NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+      }],
+    });
+    set_availability(app_state.clone(), OWNER, web::Path::from(1), dto)
+      .await
+      .unwrap();
+
+    create_schedule(app_state.clone(), OWNER, web::Path::from(1), mondays(2))
+      .await
+      .unwrap();
+    let mut dto = mondays(1);
+    dto.starts_at = "2026-10-27T19:00:00".parse().unwrap();
+    let res = create_schedule(app_state, OWNER, web::Path::from(1), dto).await;
+    assert_eq!(
+      res.unwrap_err().status_code(),
+      StatusCode::UNPROCESSABLE_ENTITY
+    );
+  }
+
+  #[actix_rt::test]
+  async fn test_teacher_calendar() {
+    let app_state = AppState::for_test();
+    create_schedule(app_state.clone(), OWNER, web::Path::from(1), mondays(2))
+      .await
+      .unwrap();
+
+    let res = get_teacher_calendar(app_state, web::Path::from(1))
+      .await
+      .unwrap();
+    assert_eq!(
+      res.headers().get(header::CONTENT_TYPE).unwrap(),
+      calendar::CONTENT_TYPE
+    );
+    let body = to_bytes(res.into_body()).await.unwrap();
+    let ics = std::str::from_utf8(&body).unwrap();
+    assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+    assert!(ics.contains("SUMMARY:Rust 入门\r\n"));
+  }
+}
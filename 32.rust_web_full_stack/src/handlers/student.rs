@@ -0,0 +1,50 @@
+use crate::auth::AuthUser;
+use crate::db_access::{EnrollmentRepository, StudentRepository};
+use crate::error::MyError;
+use crate::models::student::*;
+use crate::state::AppState;
+use crate::validation::Validate;
+use actix_web::{web, HttpResponse};
+
+/// Creates the signed-in user's student profile.
+pub async fn create_student(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  dto: web::Json<CreateStudentDTO>,
+) -> Result<HttpResponse, MyError> {
+  app_state
+    .db
+    .create_student(user.id, dto.try_into()?)
+    .await
+    .map(|student| HttpResponse::Ok().json(student))
+}
+
+pub async fn get_student(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+) -> Result<HttpResponse, MyError> {
+  let id = path.into_inner();
+  user.check_is_student(&app_state, id).await?;
+  app_state
+    .db
+    .get_student(id)
+    .await
+    .map(|student| HttpResponse::Ok().json(student))
+}
+
+pub async fn get_enrollments_of_student(
+  app_state: web::Data<AppState>,
+  user: AuthUser,
+  path: web::Path<i32>,
+  query: web::Query<GetEnrollmentsQuery>,
+) -> Result<HttpResponse, MyError> {
+  let id = path.into_inner();
+  query.check()?;
+  user.check_is_student(&app_state, id).await?;
+  app_state
+    .db
+    .get_enrollments_of_student(id, query.into_inner().status)
+    .await
+    .map(|enrollments| HttpResponse::Ok().json(enrollments))
+}
@@ -1,22 +1,37 @@
-use crate::db_access::teacher::*;
+use super::paged_response;
+use crate::auth::AuthUser;
+use crate::db_access::TeacherRepository;
 use crate::error::MyError;
-use crate::models::teacher::*;
+use crate::models::{teacher::*, Page, Sort};
 use crate::state::AppState;
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 
+/// Creates the signed-in user's teacher profile.
 pub async fn create_teacher(
   app_state: web::Data<AppState>,
+  user: AuthUser,
   dto: web::Json<CreateTeacherDTO>,
 ) -> Result<HttpResponse, MyError> {
-  db_create_teacher(&app_state.db, dto.try_into()?)
+  app_state
+    .db
+    .create_teacher(user.id, dto.try_into()?)
     .await
     .map(|teacher| HttpResponse::Ok().json(teacher))
 }
 
-pub async fn get_teachers(app_state: web::Data<AppState>) -> Result<HttpResponse, MyError> {
-  db_get_teachers(&app_state.db)
-    .await
-    .map(|courses| HttpResponse::Ok().json(courses))
+pub async fn get_teachers(
+  req: HttpRequest,
+  app_state: web::Data<AppState>,
+  query: web::Query<GetTeachersQuery>,
+) -> Result<HttpResponse, MyError> {
+  let page = Page::new(query.page, query.per_page)?;
+  let sort = Sort::parse(query.sort.as_deref(), TEACHER_SORT_COLUMNS)?;
+  let teachers = app_state
+    .db
+    .get_teachers(query.search(), sort, page)
+    .await?;
+
+  Ok(paged_response(&req, page, teachers))
 }
 
 pub async fn get_teacher(
@@ -24,28 +39,42 @@ pub async fn get_teacher(
   path: web::Path<i32>,
 ) -> Result<HttpResponse, MyError> {
   let id = path.into_inner();
-  db_get_teacher(&app_state.db, id)
+  app_state
+    .db
+    .get_teacher(id)
     .await
     .map(|course| HttpResponse::Ok().json(course))
 }
 
 pub async fn remove_teacher(
   app_state: web::Data<AppState>,
+  user: AuthUser,
   path: web::Path<i32>,
 ) -> Result<HttpResponse, MyError> {
   let id = path.into_inner();
-  db_remove_teacher(&app_state.db, id)
+  app_state.db.get_teacher(id).await?;
+  user.check_owns(&app_state, id).await?;
+  app_state
+    .db
+    .remove_teacher(id)
     .await
     .map(|msg| HttpResponse::Ok().json(msg))
 }
 
+/// Handles `PATCH` and, for older clients, `PUT`. Only the fields in the body
+/// are changed.
 pub async fn update_teacher(
   app_state: web::Data<AppState>,
+  user: AuthUser,
   path: web::Path<i32>,
   dto: web::Json<UpdateTeacherDTO>,
 ) -> Result<HttpResponse, MyError> {
   let id = path.into_inner();
-  db_update_teacher(&app_state.db, id, dto.try_into()?)
+  app_state.db.get_teacher(id).await?;
+  user.check_owns(&app_state, id).await?;
+  app_state
+    .db
+    .update_teacher(id, dto.try_into()?)
     .await
     .map(|course| HttpResponse::Ok().json(course))
 }
@@ -53,64 +82,67 @@ pub async fn update_teacher(
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::db_access::UserRepository;
   use actix_web::http::StatusCode;
+  use actix_web::test::TestRequest;
   use actix_web::ResponseError;
-  use dotenvy::dotenv;
-  use sqlx::postgres::PgPoolOptions;
-  use std::env;
-  use std::sync::Mutex;
 
-  #[ignore]
+  /// Owns teacher 1 in the fixtures.
+  const OWNER: AuthUser = AuthUser { id: 1 };
+
   #[actix_rt::test]
   async fn test_create_teacher() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
-    let dto = web::Json(CreateTeacherDTO {
-      name: "Wang Lei".to_string(),
-      profile: Some("北京大学信息科学教授".into()),
-    });
+    let app_state = AppState::for_test();
+    let user = app_state
+      .db
+      .create_user("wanglei".into(), "".into())
+      .await
+      .unwrap();
+    let dto = || {
+      web::Json(CreateTeacherDTO {
+        name: "Wang Lei".to_string(),
+        profile: Some("北京大学信息科学教授".into()),
+      })
+    };
 
-    let res = create_teacher(app_state, dto).await.unwrap();
+    let user = AuthUser { id: user.id };
+    let res = create_teacher(app_state.clone(), user, dto())
+      .await
+      .unwrap();
     assert_eq!(res.status(), StatusCode::OK);
+
+    let res = create_teacher(app_state, user, dto()).await;
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::FORBIDDEN);
   }
 
   #[actix_rt::test]
   async fn test_get_teachers() {
-    dotenv().ok();
+    let app_state = AppState::for_test();
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+    let dto = CreateTeacherDTO {
+      name: "Wang Lei".to_string(),
+      profile: Some("北京大学 信息科学 教授".into()),
+    };
+    let user = app_state
+      .db
+      .create_user("wanglei".into(), "".into())
+      .await
+      .unwrap();
+    app_state.db.create_teacher(user.id, dto).await.unwrap();
 
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
+    let query = web::Query(GetTeachersQuery {
+      q: Some("信息科学".into()),
+      ..GetTeachersQuery::default()
     });
-
-    let res = get_teachers(app_state).await.unwrap();
+    let req = TestRequest::default().to_http_request();
+    let res = get_teachers(req, app_state, query).await.unwrap();
     assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "1");
   }
 
   #[actix_rt::test]
   async fn test_get_teacher_success() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    let app_state = AppState::for_test();
 
     let res = get_teacher(app_state, web::Path::from(1)).await.unwrap();
     assert_eq!(res.status(), StatusCode::OK);
@@ -118,87 +150,46 @@ mod tests {
 
   #[actix_rt::test]
   async fn test_get_teacher_failure() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    let app_state = AppState::for_test();
 
     let res = get_teacher(app_state, web::Path::from(9999)).await;
 
-    match res {
-      Ok(_) => println!("Something went wrong"),
-      Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND),
-    };
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::NOT_FOUND);
   }
 
   #[actix_rt::test]
   async fn test_update_teacher_success() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    let app_state = AppState::for_test();
 
     let dto = web::Json(UpdateTeacherDTO {
       name: Some("Wang TianLei".into()),
       profile: None,
     });
 
-    let res = update_teacher(app_state, web::Path::from(1), dto)
+    let res = update_teacher(app_state, OWNER, web::Path::from(1), dto)
       .await
       .unwrap();
 
     assert_eq!(res.status(), StatusCode::OK)
   }
 
-  #[ignore]
   #[actix_rt::test]
   async fn test_remove_teacher_success() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+    let app_state = AppState::for_test();
 
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
-
-    let res = remove_teacher(app_state, web::Path::from(1)).await.unwrap();
+    let res = remove_teacher(app_state, OWNER, web::Path::from(1))
+      .await
+      .unwrap();
 
     assert_eq!(res.status(), StatusCode::OK)
   }
 
   #[actix_rt::test]
   async fn test_remove_teacher_failure() {
-    dotenv().ok();
-
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-    let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
-
-    let app_state = web::Data::new(AppState {
-      health_check_response: "".to_string(),
-      visit_count: Mutex::new(0),
-      db,
-    });
+    let app_state = AppState::for_test();
 
-    let res = remove_teacher(app_state, web::Path::from(9999)).await;
+    let res = remove_teacher(app_state, OWNER, web::Path::from(9999)).await;
 
-    match res {
-      Ok(_) => println!("Something went wrong"),
-      Err(err) => assert_eq!(err.status_code(), StatusCode::NOT_FOUND),
-    }
+    assert_eq!(res.unwrap_err().status_code(), StatusCode::NOT_FOUND);
   }
 }
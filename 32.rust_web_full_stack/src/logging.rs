@@ -0,0 +1,13 @@
+use tracing_subscriber::EnvFilter;
+
+/// Logs to stdout, filtered by `RUST_LOG` (`info` by default). With
+/// `LOG_FORMAT=json` every event is one JSON object per line, for log
+/// collectors; otherwise it is human-readable text.
+pub fn init() {
+  let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+  let builder = tracing_subscriber::fmt().with_env_filter(filter);
+  match std::env::var("LOG_FORMAT").as_deref() {
+    Ok("json") => builder.json().init(),
+    _ => builder.init(),
+  }
+}
@@ -1,50 +1,153 @@
 use actix_cors::Cors;
 use actix_web::{http, web, App, HttpServer};
+use auth::Keys;
+use db_access::migrations::{self, MIGRATIONS};
 use dotenvy::dotenv;
+use error::MyError;
 use sqlx::postgres::PgPoolOptions;
 use std::env;
 use std::io;
 use std::sync::Mutex;
+use tracing_actix_web::TracingLogger;
 
+mod auth;
+mod calendar;
 mod db_access;
 mod error;
 mod handlers;
+mod logging;
 mod models;
 mod routers;
 mod state;
+mod validation;
 
 use routers::*;
 use state::AppState;
 
+const USAGE: &str = "usage: server [migrate (up | down [STEPS] | status)]";
+
+fn usage() -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, USAGE)
+}
+
+/// `server migrate ...` manages the Postgres schema without starting the
+/// server.
+async fn migrate(database_url: &str, args: &[String]) -> io::Result<()> {
+  let to_io = |err: MyError| {
+    let chain: Vec<String> = std::iter::once(err.to_string())
+      .chain(err.sources())
+      .collect();
+    io::Error::new(io::ErrorKind::Other, chain.join(": "))
+  };
+  if database_url == "memory:" {
+    println!("The in-memory backend has no schema to migrate");
+    return Ok(());
+  }
+  let pool = PgPoolOptions::new()
+    .connect(database_url)
+    .await
+    .map_err(|err| to_io(err.into()))?;
+
+  match args.first().map(String::as_str) {
+    Some("up") if args.len() == 1 => {
+      for migration in migrations::migrate_up(&pool).await.map_err(to_io)? {
+        println!("Applied {} {}", migration.version, migration.description);
+      }
+    }
+    Some("down") if args.len() <= 2 => {
+      let steps = match args.get(1) {
+        Some(steps) => steps.parse().map_err(|_| usage())?,
+        None => 1,
+      };
+      for migration in migrations::migrate_down(&pool, steps)
+        .await
+        .map_err(to_io)?
+      {
+        println!(
+          "Rolled back {} {}",
+          migration.version, migration.description
+        );
+      }
+    }
+    Some("status") if args.len() == 1 => {
+      let applied = migrations::applied(&pool).await.map_err(to_io)?;
+      for migration in MIGRATIONS {
+        let state = match applied.contains(&migration.version) {
+          true => "applied",
+          false => "pending",
+        };
+        println!(
+          "{:>4} {:<8} {}",
+          migration.version, state, migration.description
+        );
+      }
+      for version in applied
+        .iter()
+        .filter(|v| !MIGRATIONS.iter().any(|m| m.version == **v))
+      {
+        println!("{:>4} {:<8} (unknown to this build)", version, "applied");
+      }
+    }
+    _ => return Err(usage()),
+  }
+  Ok(())
+}
+
 #[actix_rt::main]
 async fn main() -> io::Result<()> {
   dotenv().ok();
+  logging::init();
 
   let database_url = env::var("DATABASE_URL").expect("DATABASE_URL is not set");
-  let db = PgPoolOptions::new().connect(&database_url).await.unwrap();
+  let args: Vec<String> = env::args().skip(1).collect();
+  match args.first().map(String::as_str) {
+    None => {}
+    Some("migrate") => return migrate(&database_url, &args[1..]).await,
+    Some(_) => return Err(usage()),
+  }
+  let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET is not set");
+  let db = match db_access::connect(&database_url).await {
+    Ok(db) => db,
+    Err(err) => {
+      tracing::error!(sources = ?err.sources(), "Failed to open the database: {}", err);
+      return Err(io::Error::new(io::ErrorKind::Other, err));
+    }
+  };
 
   let shared_data = web::Data::new(AppState {
     health_check_response: "I'm OK".to_string(),
     visit_count: Mutex::new(0),
     db,
+    keys: Keys::new(jwt_secret.as_bytes()),
   });
   let app = move || {
     let cors = Cors::default()
       .allowed_origin_fn(|origin, _| origin.as_bytes().starts_with(b"http://localhost"))
-      .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+      .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
       .allowed_headers(vec![
         http::header::AUTHORIZATION,
         http::header::ACCEPT,
         http::header::CONTENT_TYPE,
       ])
+      .expose_headers(vec!["X-Total-Count", "Link"])
       .max_age(360);
 
     App::new()
       .app_data(shared_data.clone())
+      .app_data(web::JsonConfig::default().error_handler(|err, _| {
+        MyError::InvalidInput(format!("Invalid JSON body: {}", err)).into()
+      }))
+      .app_data(web::QueryConfig::default().error_handler(|err, _| {
+        MyError::InvalidInput(format!("Invalid query string: {}", err)).into()
+      }))
       .configure(general_routes)
+      .configure(auth_routes)
       .configure(course_routes)
       .configure(teacher_routes)
+      .configure(student_routes)
+      .configure(review_routes)
       .wrap(cors)
+      .wrap(TracingLogger::default())
   };
 
   HttpServer::new(app).bind("127.0.0.1:3000")?.run().await
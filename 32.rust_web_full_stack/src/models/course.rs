@@ -1,4 +1,6 @@
+use super::{non_null, nullable};
 use crate::error::MyError;
+use crate::validation::{set, FieldError, Validate, Validator};
 use actix_web::web;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
@@ -18,8 +20,19 @@ pub struct Course {
   pub price: Option<i32>,
   pub language: Option<String>,
   pub level: Option<String>,
+  /// How many students can enroll, unlimited if `None`.
+  pub capacity: Option<i32>,
+  /// How many visible reviews there are, and their average rating, `None`
+  /// without reviews.
+  pub review_count: i32,
+  pub average_rating: Option<f64>,
+  #[serde(skip)]
+  pub rating_total: i32,
 }
 
+pub const COURSE_LEVELS: &[&str] = &["初级", "中级", "高级"];
+pub const COURSE_LANGUAGES: &[&str] = &["中文", "English"];
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct CreateCourseDTO {
   pub teacher_id: i32,
@@ -31,12 +44,30 @@ pub struct CreateCourseDTO {
   pub price: Option<i32>,
   pub language: Option<String>,
   pub level: Option<String>,
+  pub capacity: Option<i32>,
+}
+
+/// The limits of the `course` columns. Whether `teacher_id` exists is
+/// checked by the handler.
+impl Validate for CreateCourseDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("name", Some(&self.name), 1, 140)
+      .length("description", self.description.as_deref(), 0, 2000)
+      .length("format", self.format.as_deref(), 0, 30)
+      .length("structure", self.structure.as_deref(), 0, 200)
+      .length("duration", self.duration.as_deref(), 0, 30)
+      .at_least("price", self.price, 0)
+      .one_of("language", self.language.as_deref(), COURSE_LANGUAGES)
+      .one_of("level", self.level.as_deref(), COURSE_LEVELS)
+      .at_least("capacity", self.capacity, 1);
+  }
 }
 
 impl TryFrom<web::Json<CreateCourseDTO>> for CreateCourseDTO {
   type Error = MyError;
 
   fn try_from(course: web::Json<CreateCourseDTO>) -> Result<Self, Self::Error> {
+    course.check()?;
     Ok(CreateCourseDTO {
       teacher_id: course.teacher_id,
       name: course.name.clone(),
@@ -47,26 +78,54 @@ impl TryFrom<web::Json<CreateCourseDTO>> for CreateCourseDTO {
       price: course.price,
       language: course.language.clone(),
       level: course.level.clone(),
+      capacity: course.capacity,
     })
   }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// A partial update: fields that are left out keep their value, `null`
+/// clears them.
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct UpdateCourseDTO {
+  #[serde(default, deserialize_with = "non_null")]
   pub name: Option<String>,
-  pub description: Option<String>,
-  pub format: Option<String>,
-  pub structure: Option<String>,
-  pub duration: Option<String>,
-  pub price: Option<i32>,
-  pub language: Option<String>,
-  pub level: Option<String>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub description: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub format: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub structure: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub duration: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub price: Option<Option<i32>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub language: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub level: Option<Option<String>>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub capacity: Option<Option<i32>>,
+}
+
+impl Validate for UpdateCourseDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("name", self.name.as_deref(), 1, 140)
+      .length("description", set(&self.description), 0, 2000)
+      .length("format", set(&self.format), 0, 30)
+      .length("structure", set(&self.structure), 0, 200)
+      .length("duration", set(&self.duration), 0, 30)
+      .at_least("price", self.price.flatten(), 0)
+      .one_of("language", set(&self.language), COURSE_LANGUAGES)
+      .one_of("level", set(&self.level), COURSE_LEVELS)
+      .at_least("capacity", self.capacity.flatten(), 1);
+  }
 }
 
 impl TryFrom<web::Json<UpdateCourseDTO>> for UpdateCourseDTO {
   type Error = MyError;
 
   fn try_from(course: web::Json<UpdateCourseDTO>) -> Result<Self, Self::Error> {
+    course.check()?;
     Ok(UpdateCourseDTO {
       name: course.name.clone(),
       description: course.description.clone(),
@@ -76,11 +135,59 @@ impl TryFrom<web::Json<UpdateCourseDTO>> for UpdateCourseDTO {
       price: course.price,
       language: course.language.clone(),
       level: course.level.clone(),
+      capacity: course.capacity,
     })
   }
 }
 
-#[derive(Deserialize)]
+/// The columns `GET /courses` can be sorted by.
+pub const COURSE_SORT_COLUMNS: &[&str] = &["id", "name", "price", "time"];
+
+#[derive(Deserialize, Debug, Default)]
 pub struct GetCoursesQuery {
   pub teacher_id: Option<i32>,
+  pub language: Option<String>,
+  pub level: Option<String>,
+  pub format: Option<String>,
+  pub min_price: Option<i32>,
+  pub max_price: Option<i32>,
+  /// Words that must all appear in the name or description.
+  pub q: Option<String>,
+  pub sort: Option<String>,
+  pub page: Option<i64>,
+  pub per_page: Option<i64>,
+}
+
+impl GetCoursesQuery {
+  pub fn filter(&self) -> Result<CourseFilter, MyError> {
+    if let (Some(min), Some(max)) = (self.min_price, self.max_price) {
+      if min > max {
+        return Err(MyError::ValidationError(vec![FieldError::new(
+          "min_price",
+          "must not be greater than max_price",
+        )]));
+      }
+    }
+    Ok(CourseFilter {
+      teacher_id: self.teacher_id,
+      language: self.language.clone(),
+      level: self.level.clone(),
+      format: self.format.clone(),
+      min_price: self.min_price,
+      max_price: self.max_price,
+      search: self.q.clone().filter(|q| !q.trim().is_empty()),
+    })
+  }
+}
+
+/// Which courses to list. Every condition that is set must hold.
+#[derive(Debug, Clone, Default)]
+pub struct CourseFilter {
+  pub teacher_id: Option<i32>,
+  pub language: Option<String>,
+  pub level: Option<String>,
+  pub format: Option<String>,
+  pub min_price: Option<i32>,
+  pub max_price: Option<i32>,
+  pub search: Option<String>,
 }
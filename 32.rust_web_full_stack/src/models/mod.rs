@@ -1,2 +1,169 @@
 pub mod course;
+pub mod review;
+pub mod schedule;
+pub mod student;
 pub mod teacher;
+pub mod user;
+
+use crate::error::MyError;
+use crate::validation::{FieldError, Validator};
+use serde::{Deserialize, Deserializer};
+
+/// For optional fields of update DTOs, used with `#[serde(default)]`: a
+/// missing field stays `None`, while `null` becomes `Some(None)`.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Deserialize<'de>,
+{
+  Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// For `not null` columns in update DTOs, used with `#[serde(default)]`: a
+/// missing field stays `None`, while `null` is rejected.
+pub fn non_null<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Deserialize<'de>,
+{
+  T::deserialize(deserializer).map(Some)
+}
+
+/// One page of a list endpoint, from `?page=&per_page=`. Pages count from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+  pub number: i64,
+  pub size: i64,
+}
+
+impl Page {
+  pub const DEFAULT_SIZE: i64 = 20;
+  pub const MAX_SIZE: i64 = 100;
+  /// Keeps `offset` far from overflowing, whatever the page size.
+  pub const MAX_NUMBER: i64 = 1_000_000;
+
+  pub fn new(page: Option<i64>, per_page: Option<i64>) -> Result<Page, MyError> {
+    let number = page.unwrap_or(1);
+    let size = per_page.unwrap_or(Self::DEFAULT_SIZE);
+    let mut v = Validator::default();
+    if !(1..=Self::MAX_NUMBER).contains(&number) {
+      v.error(
+        "page",
+        format!("must be between 1 and {}", Self::MAX_NUMBER),
+      );
+    }
+    if !(1..=Self::MAX_SIZE).contains(&size) {
+      v.error(
+        "per_page",
+        format!("must be between 1 and {}", Self::MAX_SIZE),
+      );
+    }
+    v.finish()?;
+    Ok(Page { number, size })
+  }
+
+  pub fn offset(&self) -> i64 {
+    (self.number - 1) * self.size
+  }
+
+  /// The number of pages needed for `total` rows, at least 1.
+  pub fn count(&self, total: i64) -> i64 {
+    ((total + self.size - 1) / self.size).max(1)
+  }
+}
+
+impl Default for Page {
+  fn default() -> Self {
+    Page {
+      number: 1,
+      size: Self::DEFAULT_SIZE,
+    }
+  }
+}
+
+/// The rows on one page, and how many there are on all pages.
+#[derive(Debug)]
+pub struct Paged<T> {
+  pub items: Vec<T>,
+  pub total: i64,
+}
+
+/// The order of a list, from `?sort=column` or `?sort=-column` for
+/// descending. Only whitelisted columns are accepted, so `column` can go into
+/// SQL as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sort {
+  pub column: &'static str,
+  pub descending: bool,
+}
+
+impl Sort {
+  /// Parses `sort`, allowing only the `columns` given. Without one, the list
+  /// is in ascending order of the first column.
+  pub fn parse(sort: Option<&str>, columns: &[&'static str]) -> Result<Sort, MyError> {
+    let (name, descending) = match sort {
+      None => (columns[0], false),
+      Some(sort) => match sort.strip_prefix('-') {
+        Some(name) => (name, true),
+        None => (sort, false),
+      },
+    };
+    match columns.iter().copied().find(|c| *c == name) {
+      Some(column) => Ok(Sort { column, descending }),
+      None => Err(MyError::ValidationError(vec![FieldError::new(
+        "sort",
+        format!(
+          "must be one of {}, optionally prefixed with -",
+          columns.join(", ")
+        ),
+      )])),
+    }
+  }
+
+  /// The `order by` clause. Rows that tie are ordered by `id`, so pages
+  /// don't overlap.
+  pub fn order_by(&self) -> String {
+    let direction = if self.descending { "desc" } else { "asc" };
+    format!("order by {} {} nulls last, id", self.column, direction)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::course::UpdateCourseDTO;
+  use super::*;
+
+  #[test]
+  fn test_absent_and_null_fields() {
+    let dto: UpdateCourseDTO =
+      serde_json::from_str(r#"{"description": null, "price": 80}"#).unwrap();
+    assert_eq!(dto.name, None);
+    assert_eq!(dto.description, Some(None));
+    assert_eq!(dto.format, None);
+    assert_eq!(dto.price, Some(Some(80)));
+
+    assert!(serde_json::from_str::<UpdateCourseDTO>(r#"{"name": null}"#).is_err());
+  }
+
+  #[test]
+  fn test_page() {
+    let page = Page::new(Some(3), Some(10)).unwrap();
+    assert_eq!(page.offset(), 20);
+    assert_eq!(page.count(0), 1);
+    assert_eq!(page.count(21), 3);
+    assert_eq!(Page::new(None, None).unwrap(), Page::default());
+    assert!(Page::new(Some(0), None).is_err());
+    assert!(Page::new(Some(i64::MAX), Some(Page::MAX_SIZE)).is_err());
+    assert!(Page::new(None, Some(Page::MAX_SIZE + 1)).is_err());
+  }
+
+  #[test]
+  fn test_sort() {
+    let columns = ["id", "name"];
+    let sort = Sort::parse(None, &columns).unwrap();
+    assert_eq!(sort.order_by(), "order by id asc nulls last, id");
+    let sort = Sort::parse(Some("-name"), &columns).unwrap();
+    assert_eq!((sort.column, sort.descending), ("name", true));
+    assert!(Sort::parse(Some("name; drop table course"), &columns).is_err());
+  }
+}
@@ -0,0 +1,77 @@
+use crate::error::MyError;
+use crate::validation::{Validate, Validator};
+use actix_web::web;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// A student's rating and review of a course, with the teacher's reply.
+/// There is at most one per student and course.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Review {
+  pub id: i32,
+  pub course_id: i32,
+  pub student_id: i32,
+  pub rating: i32,
+  pub body: String,
+  pub reply: Option<String>,
+  pub replied_at: Option<NaiveDateTime>,
+  /// When a moderator hid the review. Hidden reviews are only listed to
+  /// moderators and don't count towards the course's rating.
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub hidden_at: Option<NaiveDateTime>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub hidden_reason: Option<String>,
+  pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateReviewDTO {
+  pub rating: i32,
+  pub body: String,
+}
+
+impl Validate for CreateReviewDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.at_least("rating", Some(self.rating), 1)
+      .at_most("rating", Some(self.rating), 5)
+      .length("body", Some(&self.body), 1, 2000);
+  }
+}
+
+impl TryFrom<web::Json<CreateReviewDTO>> for CreateReviewDTO {
+  type Error = MyError;
+
+  fn try_from(dto: web::Json<CreateReviewDTO>) -> Result<Self, Self::Error> {
+    dto.check()?;
+    Ok(dto.into_inner())
+  }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReplyDTO {
+  pub body: String,
+}
+
+impl Validate for ReplyDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("body", Some(&self.body), 1, 2000);
+  }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HideReviewDTO {
+  pub reason: String,
+}
+
+impl Validate for HideReviewDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("reason", Some(&self.reason), 1, 200);
+  }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GetReviewsQuery {
+  pub page: Option<i64>,
+  pub per_page: Option<i64>,
+}
@@ -0,0 +1,305 @@
+use crate::error::MyError;
+use crate::validation::{FieldError, Validate, Validator};
+use actix_web::web;
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+pub const REPEAT_NONE: &str = "none";
+pub const REPEAT_DAILY: &str = "daily";
+pub const REPEAT_WEEKLY: &str = "weekly";
+pub const REPEATS: &[&str] = &[REPEAT_NONE, REPEAT_DAILY, REPEAT_WEEKLY];
+pub const MAX_OCCURRENCES: i32 = 200;
+pub const MAX_SESSION_MINUTES: i32 = 12 * 60;
+pub const MAX_WINDOWS: usize = 50;
+
+/// An IANA time zone name such as `Asia/Shanghai`.
+pub fn parse_time_zone(name: &str) -> Option<Tz> {
+  name.parse().ok()
+}
+
+/// Sessions of a course that repeat at the same wall-clock time in
+/// `time_zone`, so they stay put when daylight saving time starts or ends.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Schedule {
+  pub id: i32,
+  pub course_id: i32,
+  pub time_zone: String,
+  /// The first session's start, in `time_zone`.
+  pub starts_at: NaiveDateTime,
+  pub duration_minutes: i32,
+  pub repeat: String,
+  pub occurrences: i32,
+}
+
+/// One meeting of a course, in UTC.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Session {
+  pub id: i32,
+  pub schedule_id: i32,
+  pub course_id: i32,
+  pub starts_at: NaiveDateTime,
+  pub ends_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScheduleWithSessions {
+  #[serde(flatten)]
+  pub schedule: Schedule,
+  pub sessions: Vec<Session>,
+}
+
+/// When a session will be, in UTC, before it is saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+  pub starts_at: NaiveDateTime,
+  pub ends_at: NaiveDateTime,
+}
+
+impl Slot {
+  pub fn overlaps(&self, session: &Session) -> bool {
+    self.starts_at < session.ends_at && session.starts_at < self.ends_at
+  }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateScheduleDTO {
+  pub time_zone: String,
+  pub starts_at: NaiveDateTime,
+  pub duration_minutes: i32,
+  /// `none` if absent.
+  pub repeat: Option<String>,
+  /// `1` if absent.
+  pub occurrences: Option<i32>,
+}
+
+impl Validate for CreateScheduleDTO {
+  fn validate(&self, v: &mut Validator) {
+    if parse_time_zone(&self.time_zone).is_none() {
+      v.error("time_zone", "must be a time zone such as Asia/Shanghai");
+    }
+    v.at_least("duration_minutes", Some(self.duration_minutes), 1)
+      .at_most(
+        "duration_minutes",
+        Some(self.duration_minutes),
+        MAX_SESSION_MINUTES,
+      )
+      .one_of("repeat", self.repeat.as_deref(), REPEATS)
+      .at_least("occurrences", self.occurrences, 1)
+      .at_most("occurrences", self.occurrences, MAX_OCCURRENCES);
+    if self.repeat() == REPEAT_NONE && self.occurrences() > 1 {
+      v.error("occurrences", "must be 1 unless the schedule repeats");
+    }
+  }
+}
+
+impl TryFrom<web::Json<CreateScheduleDTO>> for CreateScheduleDTO {
+  type Error = MyError;
+
+  fn try_from(dto: web::Json<CreateScheduleDTO>) -> Result<Self, Self::Error> {
+    dto.check()?;
+    Ok(dto.into_inner())
+  }
+}
+
+impl CreateScheduleDTO {
+  pub fn repeat(&self) -> &str {
+    self.repeat.as_deref().unwrap_or(REPEAT_NONE)
+  }
+
+  pub fn occurrences(&self) -> i32 {
+    self.occurrences.unwrap_or(1)
+  }
+
+  /// Every session of the schedule, in UTC. Local times that happen twice
+  /// when the clocks go back use the first; times skipped when they go
+  /// forward are a field error.
+  pub fn slots(&self) -> Result<Vec<Slot>, MyError> {
+    let tz = parse_time_zone(&self.time_zone).ok_or_else(|| {
+      MyError::ValidationError(vec![FieldError::new("time_zone", "is not a time zone")])
+    })?;
+    let step = match self.repeat() {
+      REPEAT_WEEKLY => Duration::weeks(1),
+      _ => Duration::days(1),
+    };
+    (0..self.occurrences())
+      .map(|i| {
+        let local = self.starts_at + step * i;
+        let starts_at = tz
+          .from_local_datetime(&local)
+          .earliest()
+          .ok_or_else(|| {
+            let reason = format!("{} does not exist in {}", local, self.time_zone);
+            MyError::ValidationError(vec![FieldError::new("starts_at", reason)])
+          })?
+          .naive_utc();
+        Ok(Slot {
+          starts_at,
+          ends_at: starts_at + Duration::minutes(self.duration_minutes.into()),
+        })
+      })
+      .collect()
+  }
+}
+
+/// A weekly window in which a teacher can give sessions.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Availability {
+  pub id: i32,
+  pub teacher_id: i32,
+  pub time_zone: String,
+  /// From `1` for Monday to `7` for Sunday.
+  pub weekday: i32,
+  pub start_time: NaiveTime,
+  pub end_time: NaiveTime,
+}
+
+impl Availability {
+  /// Whether `slot` falls within this window, on one day.
+  pub fn covers(&self, slot: &Slot) -> bool {
+    let Some(tz) = parse_time_zone(&self.time_zone) else {
+      return false;
+    };
+    let starts_at = tz.from_utc_datetime(&slot.starts_at).naive_local();
+    let ends_at = tz.from_utc_datetime(&slot.ends_at).naive_local();
+    starts_at.date() == ends_at.date()
+      && starts_at.weekday().number_from_monday() as i32 == self.weekday
+      && self.start_time <= starts_at.time()
+      && ends_at.time() <= self.end_time
+  }
+}
+
+/// Teachers without windows can be booked at any time; otherwise every slot
+/// must fall within one of them.
+pub fn check_availability(slots: &[Slot], windows: &[Availability]) -> Result<(), MyError> {
+  if windows.is_empty() {
+    return Ok(());
+  }
+  match slots
+    .iter()
+    .find(|slot| !windows.iter().any(|w| w.covers(slot)))
+  {
+    Some(slot) => Err(MyError::Unprocessable(format!(
+      "The session at {} UTC is outside the teacher's availability",
+      slot.starts_at
+    ))),
+    None => Ok(()),
+  }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WindowDTO {
+  pub weekday: i32,
+  pub start_time: NaiveTime,
+  pub end_time: NaiveTime,
+}
+
+/// Replaces all of a teacher's windows, which share one time zone.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SetAvailabilityDTO {
+  pub time_zone: String,
+  pub windows: Vec<WindowDTO>,
+}
+
+impl Validate for SetAvailabilityDTO {
+  fn validate(&self, v: &mut Validator) {
+    if parse_time_zone(&self.time_zone).is_none() {
+      v.error("time_zone", "must be a time zone such as Asia/Shanghai");
+    }
+    if self.windows.len() > MAX_WINDOWS {
+      v.error(
+        "windows",
+        format!("must have at most {} windows", MAX_WINDOWS),
+      );
+    }
+    for window in &self.windows {
+      v.at_least("weekday", Some(window.weekday), 1)
+        .at_most("weekday", Some(window.weekday), 7);
+      if window.start_time >= window.end_time {
+        v.error("end_time", "must be after start_time");
+      }
+    }
+  }
+}
+
+impl TryFrom<web::Json<SetAvailabilityDTO>> for SetAvailabilityDTO {
+  type Error = MyError;
+
+  fn try_from(dto: web::Json<SetAvailabilityDTO>) -> Result<Self, Self::Error> {
+    dto.check()?;
+    Ok(dto.into_inner())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn at(text: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+  }
+
+  fn weekly(time_zone: &str, starts_at: &str, occurrences: i32) -> CreateScheduleDTO {
+    CreateScheduleDTO {
+      time_zone: time_zone.into(),
+      starts_at: at(starts_at),
+      duration_minutes: 90,
+      repeat: Some(REPEAT_WEEKLY.into()),
+      occurrences: Some(occurrences),
+    }
+  }
+
+  #[test]
+  fn test_slots_keep_local_time() {
+    // Daylight saving time in New York ends on 2026-11-01.
+    let slots = weekly("America/New_York", "2026-10-26 19:00", 2)
+      .slots()
+      .unwrap();
+    assert_eq!(slots[0].starts_at, at("2026-10-26 23:00"));
+    assert_eq!(slots[0].ends_at, at("2026-10-27 00:30"));
+    assert_eq!(slots[1].starts_at, at("2026-11-03 00:00"));
+
+    // 02:30 is skipped when it starts on 2027-03-14.
+    let dto = weekly("America/New_York", "2027-03-07 02:30", 2);
+    assert!(dto.slots().is_err());
+  }
+
+  #[test]
+  fn test_validate_schedule() {
+    let mut dto = weekly("Mars/Olympus", "2026-10-26 19:00", MAX_OCCURRENCES + 1);
+    dto.repeat = None;
+    match dto.check() {
+      Err(MyError::ValidationError(errors)) => {
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["time_zone", "occurrences", "occurrences"]);
+      }
+      other => panic!("unexpected {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_availability_covers() {
+    let window = Availability {
+      id: 1,
+      teacher_id: 1,
+      time_zone: "Asia/Shanghai".into(),
+      weekday: 1,
+      start_time: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+      end_time: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+    };
+    // Monday 19:00 to 20:30 in Shanghai.
+    let slot = Slot {
+      starts_at: at("2026-10-26 11:00"),
+      ends_at: at("2026-10-26 12:30"),
+    };
+    assert!(window.covers(&slot));
+    let late = Slot {
+      starts_at: at("2026-10-26 13:00"),
+      ends_at: at("2026-10-26 14:30"),
+    };
+    assert!(!window.covers(&late));
+    assert!(check_availability(&[slot, late], &[window]).is_err());
+    assert!(check_availability(&[late], &[]).is_ok());
+  }
+}
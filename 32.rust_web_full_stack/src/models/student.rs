@@ -0,0 +1,66 @@
+use crate::error::MyError;
+use crate::validation::{Validate, Validator};
+use actix_web::web;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Student {
+  pub id: i32,
+  pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateStudentDTO {
+  pub name: String,
+}
+
+impl Validate for CreateStudentDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("name", Some(&self.name), 1, 30);
+  }
+}
+
+impl TryFrom<web::Json<CreateStudentDTO>> for CreateStudentDTO {
+  type Error = MyError;
+
+  fn try_from(dto: web::Json<CreateStudentDTO>) -> Result<Self, Self::Error> {
+    dto.check()?;
+    Ok(CreateStudentDTO {
+      name: dto.name.clone(),
+    })
+  }
+}
+
+/// Has a place in the course.
+pub const ENROLLED: &str = "enrolled";
+/// Waiting for a place, which is given in the order students joined the
+/// waitlist.
+pub const WAITLISTED: &str = "waitlisted";
+pub const DROPPED: &str = "dropped";
+pub const ENROLLMENT_STATUSES: &[&str] = &[ENROLLED, WAITLISTED, DROPPED];
+
+/// A student's place in a course. There is one per student and course;
+/// enrolling again after dropping reuses it.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct Enrollment {
+  pub id: i32,
+  pub course_id: i32,
+  pub student_id: i32,
+  pub status: String,
+  pub created_at: NaiveDateTime,
+  /// When `status` last changed.
+  pub updated_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GetEnrollmentsQuery {
+  pub status: Option<String>,
+}
+
+impl Validate for GetEnrollmentsQuery {
+  fn validate(&self, v: &mut Validator) {
+    v.one_of("status", self.status.as_deref(), ENROLLMENT_STATUSES);
+  }
+}
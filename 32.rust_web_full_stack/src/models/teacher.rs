@@ -1,9 +1,12 @@
 use actix_web::web;
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 
+use super::{non_null, nullable};
 use crate::error::MyError;
+use crate::validation::{set, Validate, Validator};
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, FromRow)]
 pub struct Teacher {
   pub id: i32,
   pub name: String,
@@ -16,10 +19,18 @@ pub struct CreateTeacherDTO {
   pub profile: Option<String>,
 }
 
+impl Validate for CreateTeacherDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("name", Some(&self.name), 1, 30)
+      .length("profile", self.profile.as_deref(), 0, 140);
+  }
+}
+
 impl TryFrom<web::Json<CreateTeacherDTO>> for CreateTeacherDTO {
   type Error = MyError;
 
   fn try_from(dto: web::Json<CreateTeacherDTO>) -> Result<Self, Self::Error> {
+    dto.check()?;
     Ok(CreateTeacherDTO {
       name: dto.name.clone(),
       profile: dto.profile.clone(),
@@ -27,19 +38,49 @@ impl TryFrom<web::Json<CreateTeacherDTO>> for CreateTeacherDTO {
   }
 }
 
-#[derive(Deserialize, Debug)]
+/// A partial update: fields that are left out keep their value, `null`
+/// clears them.
+#[derive(Deserialize, Debug, Default)]
 pub struct UpdateTeacherDTO {
+  #[serde(default, deserialize_with = "non_null")]
   pub name: Option<String>,
-  pub profile: Option<String>,
+  #[serde(default, deserialize_with = "nullable")]
+  pub profile: Option<Option<String>>,
+}
+
+impl Validate for UpdateTeacherDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("name", self.name.as_deref(), 1, 30)
+      .length("profile", set(&self.profile), 0, 140);
+  }
 }
 
 impl TryFrom<web::Json<UpdateTeacherDTO>> for UpdateTeacherDTO {
   type Error = MyError;
 
   fn try_from(dto: web::Json<UpdateTeacherDTO>) -> Result<Self, Self::Error> {
+    dto.check()?;
     Ok(UpdateTeacherDTO {
       name: dto.name.clone(),
       profile: dto.profile.clone(),
     })
   }
 }
+
+/// The columns `GET /teachers` can be sorted by.
+pub const TEACHER_SORT_COLUMNS: &[&str] = &["id", "name"];
+
+#[derive(Deserialize, Debug, Default)]
+pub struct GetTeachersQuery {
+  /// Words that must all appear in the name or profile.
+  pub q: Option<String>,
+  pub sort: Option<String>,
+  pub page: Option<i64>,
+  pub per_page: Option<i64>,
+}
+
+impl GetTeachersQuery {
+  pub fn search(&self) -> Option<String> {
+    self.q.clone().filter(|q| !q.trim().is_empty())
+  }
+}
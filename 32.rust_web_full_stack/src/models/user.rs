@@ -0,0 +1,51 @@
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// An account that can sign in. A user owns at most one teacher, created with
+/// `POST /teachers/`, and may only change that teacher and its courses.
+/// Likewise, a user enrolls in courses as the student they created with
+/// `POST /students/`.
+#[derive(Serialize, Debug, Clone, FromRow)]
+pub struct User {
+  pub id: i32,
+  pub username: String,
+  #[serde(skip)]
+  pub password_hash: String,
+  pub teacher_id: Option<i32>,
+  pub student_id: Option<i32>,
+  /// May hide and show reviews.
+  pub is_moderator: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CredentialsDTO {
+  pub username: String,
+  pub password: String,
+}
+
+/// Only checked on registration, so logins keep working if the rules change.
+impl Validate for CredentialsDTO {
+  fn validate(&self, v: &mut Validator) {
+    v.length("username", Some(&self.username), 1, 50).length(
+      "password",
+      Some(&self.password),
+      8,
+      128,
+    );
+  }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RefreshDTO {
+  pub refresh_token: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokensResponse {
+  pub access_token: String,
+  pub refresh_token: String,
+  pub token_type: &'static str,
+  /// Seconds until `access_token` expires.
+  pub expires_in: i64,
+}
@@ -1,10 +1,22 @@
-use crate::handlers::{course::*, general::*, teacher::*};
+use crate::handlers::{
+  auth::*, course::*, enrollment::*, general::*, review::*, schedule::*, student::*, teacher::*,
+};
 use actix_web::web;
 
 pub fn general_routes(cfg: &mut web::ServiceConfig) {
   cfg.route("/health", web::get().to(health_check_handler));
 }
 
+pub fn auth_routes(cfg: &mut web::ServiceConfig) {
+  cfg.service(
+    web::scope("/auth")
+      .route("/register", web::post().to(register))
+      .route("/login", web::post().to(login))
+      .route("/refresh", web::post().to(refresh))
+      .route("/logout", web::post().to(logout)),
+  );
+}
+
 pub fn course_routes(cfg: &mut web::ServiceConfig) {
   cfg.service(
     web::scope("/courses")
@@ -12,7 +24,48 @@ pub fn course_routes(cfg: &mut web::ServiceConfig) {
       .route("/", web::post().to(create_course))
       .route("/{id}", web::get().to(get_course))
       .route("/{id}", web::delete().to(remove_course))
-      .route("/{id}", web::put().to(update_course)),
+      .route("/{id}", web::patch().to(update_course))
+      .route("/{id}", web::put().to(update_course))
+      .route(
+        "/{id}/enrollments",
+        web::get().to(get_enrollments_of_course),
+      )
+      .route("/{id}/enrollments", web::post().to(enroll))
+      .route(
+        "/{id}/enrollments/{student_id}",
+        web::delete().to(drop_enrollment),
+      )
+      .route("/{id}/reviews", web::get().to(get_reviews_of_course))
+      .route("/{id}/reviews", web::post().to(create_review))
+      .route("/{id}/schedules", web::post().to(create_schedule))
+      .route(
+        "/{id}/schedules/{schedule_id}",
+        web::delete().to(remove_schedule),
+      )
+      .route("/{id}/sessions", web::get().to(get_sessions_of_course))
+      .route("/{id}/calendar.ics", web::get().to(get_course_calendar)),
+  );
+}
+
+pub fn review_routes(cfg: &mut web::ServiceConfig) {
+  cfg.service(
+    web::scope("/reviews")
+      .route("/hidden", web::get().to(get_hidden_reviews))
+      .route("/{id}/reply", web::put().to(reply_to_review))
+      .route("/{id}/hide", web::post().to(hide_review))
+      .route("/{id}/unhide", web::post().to(unhide_review)),
+  );
+}
+
+pub fn student_routes(cfg: &mut web::ServiceConfig) {
+  cfg.service(
+    web::scope("/students")
+      .route("/", web::post().to(create_student))
+      .route("/{id}", web::get().to(get_student))
+      .route(
+        "/{id}/enrollments",
+        web::get().to(get_enrollments_of_student),
+      ),
   );
 }
 
@@ -23,6 +76,10 @@ pub fn teacher_routes(cfg: &mut web::ServiceConfig) {
       .route("/", web::post().to(create_teacher))
       .route("/{id}", web::get().to(get_teacher))
       .route("/{id}", web::delete().to(remove_teacher))
-      .route("/{id}", web::put().to(update_teacher)),
+      .route("/{id}", web::patch().to(update_teacher))
+      .route("/{id}", web::put().to(update_teacher))
+      .route("/{id}/availability", web::get().to(get_availability))
+      .route("/{id}/availability", web::put().to(set_availability))
+      .route("/{id}/calendar.ics", web::get().to(get_teacher_calendar)),
   );
 }
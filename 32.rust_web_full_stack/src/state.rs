@@ -1,8 +1,39 @@
-use sqlx::postgres::PgPool;
+use crate::auth::Keys;
+use crate::db_access::Repository;
 use std::sync::Mutex;
 
 pub struct AppState {
   pub health_check_response: String,
   pub visit_count: Mutex<u32>,
-  pub db: PgPool,
+  pub db: Box<dyn Repository>,
+  pub keys: Keys,
+}
+
+#[cfg(test)]
+impl AppState {
+  /// State backed by [`MemoryRepository::fixtures`](crate::db_access::memory::MemoryRepository::fixtures),
+  /// so handler tests need no database.
+  pub fn for_test() -> actix_web::web::Data<AppState> {
+    actix_web::web::Data::new(AppState {
+      health_check_response: "".to_string(),
+      visit_count: Mutex::new(0),
+      db: Box::new(crate::db_access::memory::MemoryRepository::fixtures()),
+      keys: Keys::new(b"test secret"),
+    })
+  }
+
+  /// Signs up a user with a student profile, returning the user and student.
+  pub async fn new_student(&self, name: &str) -> (crate::auth::AuthUser, i32) {
+    use crate::db_access::{StudentRepository, UserRepository};
+    use crate::models::student::CreateStudentDTO;
+
+    let user = self
+      .db
+      .create_user(name.into(), "hash".into())
+      .await
+      .unwrap();
+    let dto = CreateStudentDTO { name: name.into() };
+    let student = self.db.create_student(user.id, dto).await.unwrap();
+    (crate::auth::AuthUser { id: user.id }, student.id)
+  }
 }
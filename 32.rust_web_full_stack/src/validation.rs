@@ -0,0 +1,129 @@
+use crate::error::MyError;
+use serde::Serialize;
+
+/// An invalid field and why, listed in `400` responses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+  pub field: &'static str,
+  pub reason: String,
+}
+
+impl FieldError {
+  pub fn new(field: &'static str, reason: impl Into<String>) -> Self {
+    FieldError {
+      field,
+      reason: reason.into(),
+    }
+  }
+}
+
+/// Collects every invalid field of an input, so clients can fix them all at
+/// once. Checks on a `None` value pass, as the field is optional.
+#[derive(Debug, Default)]
+pub struct Validator {
+  errors: Vec<FieldError>,
+}
+
+impl Validator {
+  pub fn error(&mut self, field: &'static str, reason: impl Into<String>) -> &mut Self {
+    self.errors.push(FieldError::new(field, reason));
+    self
+  }
+
+  /// Between `min` and `max` characters, not counting surrounding
+  /// whitespace towards `min`.
+  pub fn length(
+    &mut self,
+    field: &'static str,
+    value: Option<&str>,
+    min: usize,
+    max: usize,
+  ) -> &mut Self {
+    let Some(value) = value else {
+      return self;
+    };
+    if value.trim().chars().count() < min || value.chars().count() > max {
+      self.error(
+        field,
+        format!("must be between {} and {} characters", min, max),
+      );
+    }
+    self
+  }
+
+  pub fn at_least(&mut self, field: &'static str, value: Option<i32>, min: i32) -> &mut Self {
+    match value {
+      Some(value) if value < min => self.error(field, format!("must be at least {}", min)),
+      _ => self,
+    }
+  }
+
+  pub fn at_most(&mut self, field: &'static str, value: Option<i32>, max: i32) -> &mut Self {
+    match value {
+      Some(value) if value > max => self.error(field, format!("must be at most {}", max)),
+      _ => self,
+    }
+  }
+
+  pub fn one_of(
+    &mut self,
+    field: &'static str,
+    value: Option<&str>,
+    allowed: &[&str],
+  ) -> &mut Self {
+    match value {
+      Some(value) if !allowed.contains(&value) => {
+        self.error(field, format!("must be one of {}", allowed.join(", ")))
+      }
+      _ => self,
+    }
+  }
+
+  pub fn finish(self) -> Result<(), MyError> {
+    match self.errors.is_empty() {
+      true => Ok(()),
+      false => Err(MyError::ValidationError(self.errors)),
+    }
+  }
+}
+
+/// Checks on an input that don't need the database.
+pub trait Validate {
+  fn validate(&self, v: &mut Validator);
+
+  fn check(&self) -> Result<(), MyError> {
+    let mut v = Validator::default();
+    self.validate(&mut v);
+    v.finish()
+  }
+}
+
+/// The value of an optional field in an update DTO, if it is being set.
+pub fn set<T: std::ops::Deref>(field: &Option<Option<T>>) -> Option<&T::Target> {
+  field.as_ref().and_then(|value| value.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_validator() {
+    let mut v = Validator::default();
+    v.length("name", Some("  "), 1, 10)
+      .length("description", Some("太长了太长了"), 0, 5)
+      .length("format", None, 1, 10)
+      .at_least("price", Some(-1), 0)
+      .at_most("rating", Some(5), 5)
+      .one_of("level", Some("初级"), &["初级", "高级"]);
+
+    match v.finish() {
+      Err(MyError::ValidationError(errors)) => {
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, ["name", "description", "price"]);
+      }
+      other => panic!("unexpected {:?}", other),
+    }
+    assert!(Validator::default().finish().is_ok());
+  }
+}
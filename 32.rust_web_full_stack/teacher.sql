@@ -1,7 +0,0 @@
-drop table if exists teacher;
-
-create table teacher (
-  id serial primary key,
-  name varchar(30) not null,
-  profile varchar(140)
-);